@@ -0,0 +1,165 @@
+use std::sync::{Arc, Mutex};
+
+use futures::{SinkExt, StreamExt};
+use serde::Serialize;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::select;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::sync::mpsc::UnboundedSender;
+use tokio_tungstenite::tungstenite;
+
+use crate::scripting::{self, State};
+use crate::worker::{Command, Notification, WorkerData};
+
+///Address the API listens on when none is given, only reachable from this machine
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:9001";
+
+///Number of messages kept for a client that falls behind, older messages are skipped
+const CAPACITY: usize = 64;
+
+///Message
+///What the server sends to its clients as json, the type field tells them apart
+///State is sent after every tick and command, notifications just before the state they happened in
+///Error is only sent to the client whose message could not be handled
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Message {
+    State { state: State },
+    Notification { notification: Notification },
+    Error { message: String },
+}
+
+///Api
+///Handle shared by the servers, clients send their commands to the worker loop through it
+///Publish sends the data of the worker loop to every connected client and keeps it for requests, see rest
+///The servers are started from the interfaces
+#[derive(Debug, Clone)]
+pub struct Api {
+    commands: UnboundedSender<Command>,
+    messages: broadcast::Sender<String>,
+    latest: Arc<Mutex<Option<WorkerData>>>,
+}
+
+impl Api {
+    pub fn new(commands: UnboundedSender<Command>) -> Api {
+        let (messages, _) = broadcast::channel(CAPACITY);
+        Api {
+            commands,
+            messages,
+            latest: Arc::default(),
+        }
+    }
+
+    ///Send Command
+    ///Passes a command to the worker loop, returns false when the worker loop has stopped
+    pub fn send_command(&self, command: Command) -> bool {
+        self.commands.send(command).is_ok()
+    }
+
+    ///Latest
+    ///The data last published by the worker loop, None until the first tick
+    pub fn latest(&self) -> Option<WorkerData> {
+        self.latest.lock().ok().and_then(|latest| latest.clone())
+    }
+
+    ///Publish
+    ///Keeps the data and sends the notifications and the state to all clients
+    ///Nothing is sent when no client is connected
+    pub fn publish(&self, data: &WorkerData) {
+        if let Ok(mut latest) = self.latest.lock() {
+            *latest = Some(data.clone());
+        }
+
+        if self.messages.receiver_count() == 0 {
+            return;
+        }
+
+        for notification in &data.notifications {
+            self.send(&Message::Notification {
+                notification: notification.clone(),
+            });
+        }
+        self.send(&Message::State {
+            state: scripting::state(data),
+        });
+    }
+
+    fn send(&self, message: &Message) {
+        if let Ok(text) = serde_json::to_string(message) {
+            let _ = self.messages.send(text);
+        }
+    }
+}
+
+///Listen
+///Binds the address and serves WebSocket clients in the background
+pub fn listen(address: &str, api: &Api) -> std::io::Result<()> {
+    let listener = bind(address)?;
+    tokio::spawn(serve(listener, api.clone()));
+    Ok(())
+}
+
+///Bind
+///Binds a listener right away so a taken address is reported, must be called inside the tokio runtime
+pub fn bind(address: &str) -> std::io::Result<TcpListener> {
+    let listener = std::net::TcpListener::bind(address)?;
+    listener.set_nonblocking(true)?;
+    TcpListener::from_std(listener)
+}
+
+///Serve
+///Accepts WebSocket clients until the listener fails, every client gets its own task
+pub async fn serve(listener: TcpListener, api: Api) -> std::io::Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(handle_client(stream, api.clone()));
+    }
+}
+
+///Handle Client
+///Text messages are parsed as commands and passed to the worker loop, Quit is refused
+///so a client can't stop the simulation for everyone else
+async fn handle_client(stream: TcpStream, api: Api) {
+    let socket = match tokio_tungstenite::accept_async(stream).await {
+        Ok(socket) => socket,
+        Err(_) => return,
+    };
+    let (mut sink, mut source) = socket.split();
+    let mut messages = api.messages.subscribe();
+
+    loop {
+        let reply = select! {
+            message = source.next() => match message {
+                Some(Ok(tungstenite::Message::Text(text))) => {
+                    match serde_json::from_str::<Command>(&text) {
+                        Ok(Command::Quit) => error("Quit can only be sent from the app"),
+                        Ok(command) => match api.send_command(command) {
+                            true => continue,
+                            false => break,
+                        },
+                        Err(parse_error) => error(&format!("Invalid command: {}", parse_error)),
+                    }
+                }
+                Some(Ok(tungstenite::Message::Close(_))) | Some(Err(_)) | None => break,
+                Some(Ok(_)) => continue,
+            },
+
+            message = messages.recv() => match message {
+                Ok(text) => text,
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => break,
+            },
+        };
+
+        if sink.send(tungstenite::Message::Text(reply)).await.is_err() {
+            break;
+        }
+    }
+}
+
+fn error(message: &str) -> String {
+    serde_json::to_string(&Message::Error {
+        message: message.to_string(),
+    })
+    .unwrap_or_default()
+}
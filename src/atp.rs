@@ -0,0 +1,81 @@
+use serde::{Deserialize, Serialize};
+
+use crate::train::train_structs::*;
+
+///Meters per second above the permitted speed at which the driver is warned
+pub const WARNING_MARGIN: f64 = 1.0;
+
+///Meters per second above the permitted speed at which the emergency brake is applied
+pub const INTERVENTION_MARGIN: f64 = 2.5;
+
+///Emergency braking is this many times stronger than service braking
+pub const EMERGENCY_FACTOR: f64 = 1.5;
+
+///Incident Kind Enumerator
+///Overspeed is a train running faster than its intervention curve
+///Signal passed at danger is a train that passed the signal, showing stop, at the given index
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub enum IncidentKind {
+    Overspeed,
+    SignalPassedAtDanger(usize),
+}
+
+///Incident
+///An intervention of the train protection, with the speed of the train and its permitted speed in m/s
+///Structure is where the front of the train was
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Incident {
+    pub time: f64,
+    pub train: i32,
+    pub kind: IncidentKind,
+    pub structure: Option<usize>,
+    pub speed: f64,
+    pub permitted: f64,
+}
+
+///Permitted Speed
+///The speed a train may run at: its maximum speed or speed limit, and no faster than it can stop
+///at the end of its movement authority with service braking
+pub fn permitted_speed(performance: &Performance, authority: f64) -> f64 {
+    performance
+        .max_speed
+        .min((2.0 * performance.deceleration * authority).sqrt())
+}
+
+///Emergency Deceleration
+///The deceleration in m/s2 of a train braking with its emergency brake
+pub fn emergency_deceleration(performance: &Performance) -> f64 {
+    performance.deceleration * EMERGENCY_FACTOR
+}
+
+///Supervise
+///Compares the speed of a train with its warning and intervention curves, returns true when the emergency brake is applied
+///The tolerance allows for the speed a train loses in one tick of service braking, the curves move while it brakes
+///An emergency brake is only released once the train stands still, a tripped train waits for a reset
+pub fn supervise(train: &mut Train, permitted: f64, tolerance: f64) -> bool {
+    match train.atp {
+        AtpStatus::Emergency if train.speed == 0.0 => {
+            train.atp = AtpStatus::Normal;
+            return false;
+        }
+        AtpStatus::Emergency | AtpStatus::Tripped => return false,
+        _ => (),
+    }
+
+    let excess = train.speed - permitted - tolerance;
+    train.atp = match excess {
+        excess if excess > INTERVENTION_MARGIN => AtpStatus::Emergency,
+        excess if excess > WARNING_MARGIN => AtpStatus::Warning,
+        _ => AtpStatus::Normal,
+    };
+    train.atp == AtpStatus::Emergency
+}
+
+///Count
+///The number of incidents of a train
+pub fn count(incidents: &[Incident], train: i32) -> usize {
+    incidents
+        .iter()
+        .filter(|incident| incident.train == train)
+        .count()
+}
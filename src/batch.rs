@@ -0,0 +1,298 @@
+use std::fmt::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+
+use crate::dispatcher::Strategy;
+use crate::scenario::{self, ScenarioError};
+use crate::stats;
+use crate::train::train_structs::*;
+use crate::worker::{self, WorkerData};
+
+///Batch
+///A scenario with the parameters to sweep, every combination of the sweep is one run
+///The scenario path is relative to the batch file, duration and step are in seconds
+///Delay is the maximum random delay in seconds of each train at the start, drawn with the seed of the run
+///The seed only draws these delays, so sweeping it needs a delay
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Batch {
+    pub scenario: String,
+    pub duration: f64,
+    #[serde(default = "default_step")]
+    pub step: f64,
+    #[serde(default)]
+    pub delay: f64,
+    #[serde(default)]
+    pub sweep: Sweep,
+}
+
+fn default_step() -> f64 {
+    1.0
+}
+
+///Sweep
+///The values to try for each parameter, an empty list keeps the value of the scenario
+///Trains limits the scenario to its first trains, the timetable of the nth train is shifted by n times the headway
+///Performance replaces the performance of every train, strategy selects the dispatcher
+///Lookahead is the depth of the deadlock prediction of the lookahead dispatcher
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Sweep {
+    #[serde(default)]
+    pub trains: Vec<usize>,
+    #[serde(default)]
+    pub headway: Vec<f64>,
+    #[serde(default)]
+    pub performance: Vec<Performance>,
+    #[serde(default)]
+    pub strategy: Vec<Strategy>,
+    #[serde(default)]
+    pub lookahead: Vec<usize>,
+    #[serde(default)]
+    pub seed: Vec<u64>,
+}
+
+///Variant
+///One combination of the sweep, None keeps the value of the scenario
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct Variant {
+    pub trains: Option<usize>,
+    pub headway: Option<f64>,
+    pub performance: Option<Performance>,
+    pub strategy: Option<Strategy>,
+    pub lookahead: Option<usize>,
+    pub seed: Option<u64>,
+}
+
+///Batch Result
+///The parameters of a run and its KPIs, punctuality is over all stops of all trains
+///Completed trains reached the last stop of their timetable, deadlock is set when trains were deadlocked at the end
+///Energy is the net energy of all trains in kWh
+#[derive(Debug, Clone, Serialize)]
+pub struct BatchResult {
+    pub run: usize,
+    pub variant: Variant,
+    pub total_delay_minutes: f64,
+    pub punctuality: f64,
+    pub average_speed: f64,
+    pub signal_wait: f64,
+    pub switch_throws: usize,
+    pub completed_trains: usize,
+    pub deadlock: bool,
+    pub energy_kwh: f64,
+}
+
+///Load Batch
+///Reads a batch file and runs all variants of its scenario
+pub fn load_batch(path: &Path) -> Result<Vec<BatchResult>, ScenarioError> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|error| ScenarioError::Io(path.to_path_buf(), error))?;
+    let batch: Batch = serde_json::from_str(&content)?;
+    if !batch.sweep.seed.is_empty() && batch.delay <= 0.0 {
+        return Err(ScenarioError::Invalid(
+            "a seed sweep needs a delay, the seed only draws the start delays".to_string(),
+        ));
+    }
+    let base = path.parent().unwrap_or_else(|| Path::new("."));
+
+    let data = scenario::load_scenario(&base.join(&batch.scenario))?;
+    Ok(run_batch(&batch, &data))
+}
+
+///Variants
+///Every combination of the values in the sweep
+pub fn variants(sweep: &Sweep) -> Vec<Variant> {
+    fn values<T: Copy>(list: &[T]) -> Vec<Option<T>> {
+        match list.is_empty() {
+            true => vec![None],
+            false => list.iter().map(|value| Some(*value)).collect(),
+        }
+    }
+
+    let mut variants = vec![];
+    for trains in values(&sweep.trains) {
+        for headway in values(&sweep.headway) {
+            for performance in values(&sweep.performance) {
+                for strategy in values(&sweep.strategy) {
+                    for lookahead in values(&sweep.lookahead) {
+                        for seed in values(&sweep.seed) {
+                            variants.push(Variant {
+                                trains,
+                                headway,
+                                performance,
+                                strategy,
+                                lookahead,
+                                seed,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    variants
+}
+
+///Run Batch
+///Runs every variant headless, spread over the available CPU cores
+///The results are in the order of the variants
+pub fn run_batch(batch: &Batch, data: &WorkerData) -> Vec<BatchResult> {
+    let variants = variants(&batch.sweep);
+    let threads = std::thread::available_parallelism().map_or(1, |threads| threads.get());
+
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<BatchResult>>> = Mutex::new(vec![None; variants.len()]);
+
+    std::thread::scope(|scope| {
+        for _ in 0..threads.min(variants.len()) {
+            scope.spawn(|| loop {
+                let run = next.fetch_add(1, Ordering::Relaxed);
+                let variant = match variants.get(run) {
+                    Some(variant) => variant,
+                    None => break,
+                };
+
+                let result = run_variant(batch, data, run, variant);
+                results.lock().unwrap()[run] = Some(result);
+            });
+        }
+    });
+
+    results
+        .into_inner()
+        .unwrap()
+        .into_iter()
+        .flatten()
+        .collect()
+}
+
+///Run Variant
+///Applies the variant to a copy of the data and runs it for the duration of the batch
+pub fn run_variant(batch: &Batch, data: &WorkerData, run: usize, variant: &Variant) -> BatchResult {
+    let mut data = data.clone();
+    apply_variant(&mut data, variant, batch.delay);
+
+    worker::run_headless(&mut data, batch.duration, batch.step);
+    let report = stats::report(&data);
+
+    let stops: usize = report.trains.iter().map(|train| train.stops).sum();
+    let punctual: usize = report.trains.iter().map(|train| train.punctual_stops).sum();
+
+    BatchResult {
+        run,
+        variant: *variant,
+        total_delay_minutes: report.total_delay_minutes,
+        punctuality: match stops {
+            0 => 100.0,
+            stops => punctual as f64 / stops as f64 * 100.0,
+        },
+        average_speed: match report.trains.is_empty() {
+            true => 0.0,
+            false => {
+                report
+                    .trains
+                    .iter()
+                    .map(|train| train.average_speed)
+                    .sum::<f64>()
+                    / report.trains.len() as f64
+            }
+        },
+        signal_wait: report.trains.iter().map(|train| train.signal_wait).sum(),
+        switch_throws: report.switches.iter().map(|switch| switch.throws).sum(),
+        completed_trains: data
+            .trains
+            .iter()
+            .filter(|train| {
+                train
+                    .timetable
+                    .last()
+                    .is_some_and(|stop| stop.actual_arrival.is_some())
+            })
+            .count(),
+        deadlock: !data.deadlocks.is_empty(),
+        energy_kwh: report.total_energy_kwh - report.total_regenerated_kwh,
+    }
+}
+
+///Apply Variant
+///Changes the data of a scenario to the parameters of a variant
+pub fn apply_variant(data: &mut WorkerData, variant: &Variant, delay: f64) {
+    if let Some(trains) = variant.trains {
+        data.trains.truncate(trains);
+        worker::update_sections(data);
+    }
+
+    if let Some(strategy) = variant.strategy {
+        data.strategy = strategy;
+    }
+
+    if let Some(lookahead) = variant.lookahead {
+        data.lookahead = lookahead;
+    }
+
+    for (i, train) in data.trains.iter_mut().enumerate() {
+        if let Some(performance) = variant.performance {
+            train.performance = performance;
+        }
+
+        let shift = variant.headway.unwrap_or(0.0) * i as f64;
+        for stop in &mut train.timetable {
+            stop.arrival = stop.arrival.map(|time| time + shift);
+            stop.departure = stop.departure.map(|time| time + shift);
+        }
+    }
+
+    //A delayed train stands still until its start time
+    if delay > 0.0 {
+        let mut rng = StdRng::seed_from_u64(variant.seed.unwrap_or(0));
+        for train in &mut data.trains {
+            train.start_at = Some(data.time + rng.gen_range(0.0..delay));
+        }
+    }
+}
+
+///To Csv
+///The results as a table with a row per run, parameters that were not swept are left empty
+pub fn to_csv(results: &[BatchResult]) -> String {
+    let mut csv = String::from(
+        "run,trains,headway,max_speed,acceleration,deceleration,strategy,lookahead,seed,\
+         total_delay_minutes,punctuality,average_speed,signal_wait,switch_throws,completed_trains,deadlock,energy_kwh\n",
+    );
+
+    fn value<T: ToString>(value: Option<T>) -> String {
+        value.map_or(String::new(), |value| value.to_string())
+    }
+
+    for result in results {
+        let variant = &result.variant;
+        let performance = variant.performance;
+
+        let _ = writeln!(
+            csv,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            result.run,
+            value(variant.trains),
+            value(variant.headway),
+            value(performance.map(|performance| performance.max_speed)),
+            value(performance.map(|performance| performance.acceleration)),
+            value(performance.map(|performance| performance.deceleration)),
+            value(variant.strategy.map(|strategy| format!("{:?}", strategy))),
+            value(variant.lookahead),
+            value(variant.seed),
+            result.total_delay_minutes,
+            result.punctuality,
+            result.average_speed,
+            result.signal_wait,
+            result.switch_throws,
+            result.completed_trains,
+            result.deadlock,
+            result.energy_kwh
+        );
+    }
+
+    csv
+}
@@ -0,0 +1,159 @@
+use crate::train::routing;
+use crate::train::train_structs::*;
+use crate::worker::{self, WorkerData};
+
+///Largest gap in meters between two trains that can still be coupled
+pub const COUPLE_DISTANCE: f64 = 10.0;
+
+///Uncouple
+///Splits a stopped train in two in front of a vehicle, the front part keeps the number and the timetable
+///The vehicles from the given one to the back become a new train with the new number, it has no destination
+///Returns false when the train doesn't exist, moves, has no vehicle behind the split or the number is taken
+pub fn uncouple(data: &mut WorkerData, number: i32, vehicle: usize, new_number: i32) -> bool {
+    if data
+        .trains
+        .iter()
+        .any(|train| train.train_number == new_number)
+    {
+        return false;
+    }
+    let train = match data
+        .trains
+        .iter_mut()
+        .find(|train| train.train_number == number)
+    {
+        Some(train) => train,
+        None => return false,
+    };
+    if vehicle == 0 || vehicle >= train.consist.len() || train.speed > 0.0 {
+        return false;
+    }
+
+    //Walk back from the front over the length of the front part to find the front of the rear part
+    let mut remaining = train.consist[..vehicle]
+        .iter()
+        .map(|vehicle| vehicle.length as f64)
+        .sum::<f64>();
+    let mut end = train.head_offset;
+    let mut rear = None;
+
+    for (i, step) in train.path.iter().enumerate() {
+        if remaining <= end {
+            let mut part = Train::with_consist(new_number, train.consist[vehicle..].to_vec());
+            part.path = train.path.iter().skip(i).copied().collect();
+            part.head_offset = end - remaining;
+            part.priority = train.priority;
+            part.trim_path();
+            rear = Some(part);
+            break;
+        }
+        remaining -= end;
+        end = step.length;
+    }
+
+    let rear = match rear {
+        Some(rear) => rear,
+        None => return false,
+    };
+
+    let front = train.consist[..vehicle].to_vec();
+    train.set_consist(front);
+    train.trim_path();
+
+    data.trains.push(rear);
+    worker::update_sections(data);
+    true
+}
+
+///Couple
+///Joins two stopped trains whose ends are at most the couple distance apart, the gap between them is closed
+///The first train keeps its number and timetable and gets the vehicles of the second, which is removed
+///The joined train travels from the first train towards the second
+pub fn couple(data: &mut WorkerData, number: i32, other: i32) -> bool {
+    let find = |number: i32| {
+        data.trains
+            .iter()
+            .position(|train| train.train_number == number)
+    };
+    let (first, second) = match (find(number), find(other)) {
+        (Some(first), Some(second)) if first != second => (first, second),
+        _ => return false,
+    };
+
+    let stopped = |train: &Train| train.speed == 0.0 && !train.consist.is_empty();
+    if !stopped(&data.trains[first]) || !stopped(&data.trains[second]) {
+        return false;
+    }
+
+    //Try both trains facing both ways, the first train has to run into the back of the second
+    let behind = orientations(&data.trains[first]);
+    let ahead = orientations(&data.trains[second]);
+    let joined = behind.iter().find_map(|behind| {
+        ahead.iter().find_map(|ahead| {
+            let gap = gap(&data.train_system, behind, ahead)?;
+            (-0.01..=COUPLE_DISTANCE)
+                .contains(&gap)
+                .then(|| join(behind, ahead))
+        })
+    });
+
+    let joined = match joined {
+        Some(joined) => joined,
+        None => return false,
+    };
+
+    data.trains[first] = joined;
+    data.trains.remove(second);
+    data.dispatch_queue.retain(|number| *number != other);
+    worker::release_reservations(data);
+    worker::update_sections(data);
+    true
+}
+
+fn orientations(train: &Train) -> [Train; 2] {
+    let mut reversed = train.clone();
+    reversed.reverse();
+    [train.clone(), reversed]
+}
+
+///Gap
+///Distance from the front of one train to the back of a train ahead of it travelling the same way
+///None when the back of the other train is not within the couple distance ahead
+fn gap(system: &TrainSystem, behind: &Train, ahead: &Train) -> Option<f64> {
+    let (back, start, _) = *ahead.occupied_intervals().last()?;
+    let mut step = *behind.path.front()?;
+    let mut gap = -behind.head_offset;
+
+    loop {
+        if step.structure == back.structure && step.entry_pin == back.entry_pin {
+            return Some(gap + start);
+        }
+        gap += step.length;
+        if gap > COUPLE_DISTANCE {
+            return None;
+        }
+        step = routing::next_step(system, &step)?;
+    }
+}
+
+///Join
+///The train ahead with the vehicles of the train behind added to its back
+fn join(behind: &Train, ahead: &Train) -> Train {
+    let mut joined = behind.clone();
+
+    joined.path = ahead.path.clone();
+    for step in &behind.path {
+        let known = joined
+            .path
+            .iter()
+            .any(|known| known.structure == step.structure && known.entry_pin == step.entry_pin);
+        if !known {
+            joined.path.push_back(*step);
+        }
+    }
+    joined.head_offset = ahead.head_offset;
+    joined.set_consist([ahead.consist.clone(), behind.consist.clone()].concat());
+    joined.trim_path();
+    joined.route.clear();
+    joined
+}
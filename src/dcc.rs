@@ -0,0 +1,487 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{
+    AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader,
+};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::select;
+use tokio::sync::broadcast;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio_serial::SerialPortBuilderExt;
+
+use crate::scenario::ScenarioError;
+use crate::train::train_structs::*;
+use crate::worker::{Command, WorkerData};
+
+///Highest speed step of a 128 step throttle, 0 is stop
+pub const MAX_SPEED_STEP: u8 = 126;
+
+///Baud rate of a DCC++ command station on a serial port
+const DEFAULT_BAUD: u32 = 115200;
+
+///Longest frame read from a command station in bytes, anything longer without an end is dropped as noise
+pub const MAX_FRAME: usize = 256;
+
+///Dcc Config
+///How the simulation is mirrored onto a model layout with a DCC++ command station
+///Turnouts map switches to accessory addresses, locos map trains to loco addresses
+///Sensors map the occupancy sensors of the layout to sections
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DccConfig {
+    pub connection: Connection,
+    #[serde(default)]
+    pub turnouts: Vec<TurnoutMapping>,
+    #[serde(default)]
+    pub locos: Vec<LocoMapping>,
+    #[serde(default)]
+    pub sensors: Vec<SensorMapping>,
+}
+
+///Connection
+///A command station on a serial port, e.g. an Arduino on /dev/ttyACM0, or over TCP, e.g. a WiFi command station
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum Connection {
+    Serial {
+        path: String,
+        #[serde(default = "default_baud")]
+        baud: u32,
+    },
+    Tcp {
+        address: String,
+    },
+}
+
+fn default_baud() -> u32 {
+    DEFAULT_BAUD
+}
+
+///Turnout Mapping
+///A switch set to Right is thrown, set to Left it is closed, inverted swaps the two
+///A three way or slip switch needs two turnouts, e.g. a two-coil decoder: the address is thrown for Left,
+///the second address for Right and both are closed for Straight
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TurnoutMapping {
+    pub switch: usize,
+    pub address: u16,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub second_address: Option<u16>,
+    #[serde(default)]
+    pub inverted: bool,
+}
+
+///Loco Mapping
+///Max speed is the speed in m/s that is full throttle, without it the maximum speed of the train is used
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LocoMapping {
+    pub train: i32,
+    pub address: u16,
+    #[serde(default)]
+    pub max_speed: Option<f64>,
+}
+
+///Sensor Mapping
+///The section a sensor of the layout detects occupancy on
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SensorMapping {
+    pub sensor: u16,
+    pub structure: usize,
+    pub section: usize,
+}
+
+///Load Config
+///Reads the mapping of a model layout from a json file
+pub fn load_config(path: &Path) -> Result<DccConfig, ScenarioError> {
+    let content = std::fs::read_to_string(path)
+        .map_err(|error| ScenarioError::Io(path.to_path_buf(), error))?;
+    Ok(serde_json::from_str(&content)?)
+}
+
+///Outputs
+///What the layout should show: thrown turnouts by address and throttles by address as speed step and direction
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Outputs {
+    pub turnouts: BTreeMap<u16, bool>,
+    pub throttles: BTreeMap<u16, (u8, bool)>,
+}
+
+impl DccConfig {
+    ///Outputs
+    ///The turnouts and throttles for the state of the simulation, unknown switches and trains are left out
+    ///The direction of a loco is the direction the train travels on the structure of its front
+    pub fn outputs(&self, data: &WorkerData) -> Outputs {
+        let mut outputs = Outputs::default();
+
+        for turnout in &self.turnouts {
+            let structure = match data.train_system.structures.get(&turnout.switch) {
+                Some(structure) => structure,
+                None => continue,
+            };
+            let mut set = |address: u16, thrown: bool| {
+                outputs.turnouts.insert(address, thrown != turnout.inverted);
+            };
+
+            match (structure, turnout.second_address) {
+                (StructureTypes::Switch(switch), _) => {
+                    set(turnout.address, switch.position == SwitchPosition::Right)
+                }
+                (StructureTypes::ThreeWay(_) | StructureTypes::Slip(_), Some(second)) => {
+                    let position = structure.position();
+                    set(turnout.address, position == Some(SwitchPosition::Left));
+                    set(second, position == Some(SwitchPosition::Right));
+                }
+                _ => continue,
+            }
+        }
+
+        for loco in &self.locos {
+            if let Some(train) = data
+                .trains
+                .iter()
+                .find(|train| train.train_number == loco.train)
+            {
+                let max_speed = loco.max_speed.unwrap_or(train.performance.max_speed);
+                let forward = train.direction() != Some(Direction::Reverse);
+                outputs
+                    .throttles
+                    .insert(loco.address, (speed_step(train.speed, max_speed), forward));
+            }
+        }
+
+        outputs
+    }
+
+    ///Validate
+    ///Checks the turnouts against the layout, every turnout has to map a switch
+    ///and a three way or slip switch needs a second address for its third position
+    pub fn validate(&self, system: &TrainSystem) -> Result<(), ScenarioError> {
+        for turnout in &self.turnouts {
+            match system.structures.get(&turnout.switch) {
+                Some(StructureTypes::Switch(_)) => (),
+                Some(StructureTypes::ThreeWay(_) | StructureTypes::Slip(_))
+                    if turnout.second_address.is_some() => {}
+                Some(StructureTypes::ThreeWay(_) | StructureTypes::Slip(_)) => {
+                    return Err(ScenarioError::Invalid(format!(
+                        "turnout {} needs a second_address for the three positions of switch {}",
+                        turnout.address, turnout.switch
+                    )))
+                }
+                _ => {
+                    return Err(ScenarioError::Invalid(format!(
+                        "turnout {} maps structure {}, which is not a switch",
+                        turnout.address, turnout.switch
+                    )))
+                }
+            }
+        }
+        Ok(())
+    }
+
+    ///Sensor Command
+    ///The command for a sensor report, None when the sensor isn't mapped
+    pub fn sensor_command(&self, sensor: u16, occupied: bool) -> Option<Command> {
+        self.sensors
+            .iter()
+            .find(|mapping| mapping.sensor == sensor)
+            .map(|mapping| Command::SetSensor(mapping.structure, mapping.section, occupied))
+    }
+}
+
+///Speed Step
+///Speed as a step of a 128 step throttle, rounded and limited to full throttle
+pub fn speed_step(speed: f64, max_speed: f64) -> u8 {
+    if max_speed <= 0.0 || speed <= 0.0 {
+        return 0;
+    }
+    (speed / max_speed * MAX_SPEED_STEP as f64)
+        .round()
+        .clamp(0.0, MAX_SPEED_STEP as f64) as u8
+}
+
+///Turnout Command
+///<T address throw> throws a turnout with 1 or closes it with 0
+pub fn turnout_command(address: u16, thrown: bool) -> String {
+    format!("<T {} {}>", address, thrown as u8)
+}
+
+///Throttle Command
+///<t register address speed direction> sets the speed of a loco, direction is 1 for forward
+///Every loco gets its own register so the command station keeps refreshing them all
+pub fn throttle_command(register: usize, address: u16, step: u8, forward: bool) -> String {
+    format!("<t {} {} {} {}>", register, address, step, forward as u8)
+}
+
+///Parse Sensor
+///A sensor report is <Q sensor> when it became occupied and <q sensor> when it became free
+pub fn parse_sensor(frame: &str) -> Option<(u16, bool)> {
+    let mut parts = frame
+        .trim()
+        .trim_start_matches('<')
+        .trim_end_matches('>')
+        .split_whitespace();
+
+    let occupied = match parts.next()? {
+        "Q" => true,
+        "q" => false,
+        _ => return None,
+    };
+    let sensor = parts.next()?.parse().ok()?;
+
+    Some((sensor, occupied))
+}
+
+///Bridge
+///Handle to a running connection with a command station, publish mirrors the simulation onto the layout
+#[derive(Debug, Clone)]
+pub struct Bridge {
+    config: Arc<DccConfig>,
+    outputs: UnboundedSender<Outputs>,
+}
+
+impl Bridge {
+    ///Publish
+    ///Passes the turnouts and throttles for the data to the connection, only changes are sent to the layout
+    pub fn publish(&self, data: &WorkerData) {
+        let _ = self.outputs.send(self.config.outputs(data));
+    }
+}
+
+///Start
+///Connects to the command station in the background, sensor reports are sent to the worker loop as commands
+///A connection that fails is reported and the simulation keeps running without the layout
+pub fn start(config: DccConfig, commands: UnboundedSender<Command>) -> Bridge {
+    let config = Arc::new(config);
+    let (transmitter, receiver) = mpsc::unbounded_channel();
+
+    let bridge_config = config.clone();
+    tokio::spawn(async move {
+        let result = match &bridge_config.connection {
+            Connection::Tcp { address } => match TcpStream::connect(address).await {
+                Ok(stream) => run(stream, &bridge_config, commands, receiver).await,
+                Err(error) => Err(error),
+            },
+            Connection::Serial { path, baud } => {
+                match tokio_serial::new(path, *baud).open_native_async() {
+                    Ok(port) => run(port, &bridge_config, commands, receiver).await,
+                    Err(error) => Err(error.into()),
+                }
+            }
+        };
+
+        if let Err(error) = result {
+            eprintln!("Connection with the command station failed: {}", error);
+        }
+    });
+
+    Bridge {
+        config,
+        outputs: transmitter,
+    }
+}
+
+///Run
+///Turns on track power, then sends the changed outputs and reads sensor reports until either side closes
+///A loco whose train is gone, e.g. coupled to another train, is stopped
+pub async fn run<S: AsyncRead + AsyncWrite>(
+    stream: S,
+    config: &DccConfig,
+    commands: UnboundedSender<Command>,
+    mut outputs: UnboundedReceiver<Outputs>,
+) -> std::io::Result<()> {
+    let (reader, mut writer) = tokio::io::split(stream);
+    let mut reader = BufReader::new(reader);
+    let mut frame = vec![];
+    let mut sent = Outputs::default();
+
+    let registers: HashMap<u16, usize> = config
+        .locos
+        .iter()
+        .enumerate()
+        .map(|(i, loco)| (loco.address, i + 1))
+        .collect();
+
+    writer.write_all(b"<1>\n").await?;
+
+    loop {
+        select! {
+            read = read_frame(&mut reader, &mut frame) => {
+                if read? == 0 {
+                    return Ok(());
+                }
+
+                //Anything before the start of a frame is noise, e.g. the newline after the previous one
+                let text = String::from_utf8_lossy(&frame).to_string();
+                frame.clear();
+                let text = text.rsplit('<').next().unwrap_or_default();
+
+                if let Some(command) =
+                    parse_sensor(text).and_then(|(sensor, occupied)| config.sensor_command(sensor, occupied))
+                {
+                    if commands.send(command).is_err() {
+                        return Ok(());
+                    }
+                }
+            }
+
+            next = outputs.recv() => {
+                let next = match next {
+                    Some(next) => next,
+                    None => return Ok(()),
+                };
+
+                let mut lines = String::new();
+                for (address, thrown) in &next.turnouts {
+                    if sent.turnouts.get(address) != Some(thrown) {
+                        lines += &turnout_command(*address, *thrown);
+                        lines += "\n";
+                    }
+                }
+                for (address, (step, forward)) in &next.throttles {
+                    if sent.throttles.get(address) != Some(&(*step, *forward)) {
+                        let register = registers.get(address).copied().unwrap_or(0);
+                        lines += &throttle_command(register, *address, *step, *forward);
+                        lines += "\n";
+                    }
+                }
+                for (address, (step, forward)) in &sent.throttles {
+                    if *step > 0 && !next.throttles.contains_key(address) {
+                        let register = registers.get(address).copied().unwrap_or(0);
+                        lines += &throttle_command(register, *address, 0, *forward);
+                        lines += "\n";
+                    }
+                }
+
+                writer.write_all(lines.as_bytes()).await?;
+                writer.flush().await?;
+                sent = next;
+            }
+        }
+    }
+}
+
+///Read Frame
+///Reads up to the end of a frame into the buffer, a buffer that reaches the longest frame without an end is dropped
+///Returns 0 when the stream closed
+async fn read_frame<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+    frame: &mut Vec<u8>,
+) -> std::io::Result<usize> {
+    let limit = MAX_FRAME.saturating_sub(frame.len()).max(1) as u64;
+    let read = reader.take(limit).read_until(b'>', frame).await?;
+    if frame.last() != Some(&b'>') && frame.len() >= MAX_FRAME {
+        frame.clear();
+    }
+    Ok(read)
+}
+
+///Fake Station
+///A command station without hardware that speaks the protocol over TCP, to test a mapping or the bridge
+///It keeps the turnouts and throttles it was sent, answers like DCC++ does and can report sensors
+#[derive(Debug, Clone)]
+pub struct FakeStation {
+    pub address: SocketAddr,
+    pub received: Arc<Mutex<Vec<String>>>,
+    pub turnouts: Arc<Mutex<BTreeMap<u16, bool>>>,
+    pub throttles: Arc<Mutex<BTreeMap<u16, (u8, bool)>>>,
+    sensors: broadcast::Sender<String>,
+}
+
+impl FakeStation {
+    ///Start
+    ///Listens on the address, use port 0 to get a free port, and serves connections in the background
+    pub async fn start(address: &str) -> std::io::Result<FakeStation> {
+        let listener = TcpListener::bind(address).await?;
+        let (sensors, _) = broadcast::channel(64);
+
+        let station = FakeStation {
+            address: listener.local_addr()?,
+            received: Arc::default(),
+            turnouts: Arc::default(),
+            throttles: Arc::default(),
+            sensors,
+        };
+
+        let server = station.clone();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                tokio::spawn(server.clone().serve(stream));
+            }
+        });
+
+        Ok(station)
+    }
+
+    ///Report Sensor
+    ///Sends a sensor report to every connection
+    pub fn report_sensor(&self, sensor: u16, occupied: bool) {
+        let code = if occupied { "Q" } else { "q" };
+        let _ = self.sensors.send(format!("<{} {}>", code, sensor));
+    }
+
+    async fn serve(self, stream: TcpStream) {
+        let (reader, mut writer) = stream.into_split();
+        let mut reader = BufReader::new(reader);
+        let mut sensors = self.sensors.subscribe();
+        let mut frame = vec![];
+
+        loop {
+            let reply = select! {
+                read = read_frame(&mut reader, &mut frame) => {
+                    if !matches!(read, Ok(read) if read > 0) {
+                        return;
+                    }
+                    let text = String::from_utf8_lossy(&frame).to_string();
+                    frame.clear();
+                    match text.rsplit('<').next() {
+                        Some(text) if !text.is_empty() => self.handle(text.trim_end_matches('>')),
+                        _ => continue,
+                    }
+                }
+
+                report = sensors.recv() => match report {
+                    Ok(report) => Some(report),
+                    Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                    Err(broadcast::error::RecvError::Closed) => return,
+                },
+            };
+
+            if let Some(reply) = reply {
+                if writer.write_all(reply.as_bytes()).await.is_err() {
+                    return;
+                }
+            }
+        }
+    }
+
+    ///Handle
+    ///Keeps a command and returns the answer of a DCC++ command station
+    fn handle(&self, command: &str) -> Option<String> {
+        self.received.lock().unwrap().push(format!("<{}>", command));
+        let parts: Vec<&str> = command.split_whitespace().collect();
+
+        match parts.as_slice() {
+            ["1"] => Some("<p1>".to_string()),
+            ["0"] => Some("<p0>".to_string()),
+            ["T", address, throw] => {
+                let address = address.parse().ok()?;
+                let thrown = *throw == "1";
+                self.turnouts.lock().unwrap().insert(address, thrown);
+                Some(format!("<H {} {}>", address, thrown as u8))
+            }
+            ["t", register, address, step, direction] => {
+                let address = address.parse().ok()?;
+                let step = step.parse().ok()?;
+                self.throttles
+                    .lock()
+                    .unwrap()
+                    .insert(address, (step, *direction == "1"));
+                Some(format!("<T {} {} {}>", register, step, direction))
+            }
+            _ => None,
+        }
+    }
+}
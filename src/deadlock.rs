@@ -0,0 +1,182 @@
+use std::collections::{HashSet, VecDeque};
+
+use crate::dispatcher;
+use crate::train::routing::Route;
+use crate::train::train_structs::*;
+use crate::worker::WorkerData;
+
+///Number of moves the dispatcher looks ahead before setting a route
+pub const LOOKAHEAD: usize = 4;
+
+///Plan Train
+///What the deadlock search knows about a train
+///Holds are the structures it will keep, segments are the parts of its route it still needs
+///A train with a route set will always reach the end of it, so it only holds the last structure of that route
+///A running train will at least reach the end of the structure it is on, a stopped train holds all it occupies
+///A move gives a train its next segment, afterwards it only holds the last structure of that segment
+#[derive(Debug, Clone)]
+pub struct PlanTrain {
+    pub train_number: i32,
+    pub holds: Vec<usize>,
+    pub segments: VecDeque<Vec<usize>>,
+}
+
+impl PlanTrain {
+    ///Waits For
+    ///The next segment of this train needs a structure the other train holds
+    pub fn waits_for(&self, other: &PlanTrain) -> bool {
+        self.train_number != other.train_number
+            && self.segments.front().is_some_and(|segment| {
+                segment
+                    .iter()
+                    .any(|structure| other.holds.contains(structure))
+            })
+    }
+}
+
+///Plan
+///Builds the state for the deadlock search from the current reservations and the route of every train
+///Trains without a destination or that broke down only hold structures, they never move in the search
+pub fn plan(system: &TrainSystem, trains: &[Train]) -> Vec<PlanTrain> {
+    trains
+        .iter()
+        .map(|train| {
+            let mut holds: Vec<usize> = match (train.route.back(), train.path.front()) {
+                (Some(step), _) => vec![step.structure],
+                (None, Some(front)) if train.train_status == TrainStatus::Running => {
+                    vec![front.structure]
+                }
+                _ => train
+                    .occupied_intervals()
+                    .iter()
+                    .map(|(step, _, _)| step.structure)
+                    .collect(),
+            };
+            holds.sort_unstable();
+            holds.dedup();
+
+            //Skip the part of the route that is already reserved
+            let segments = match (
+                train.destination.filter(|_| !train.broken_down),
+                dispatcher::route_to_destination(system, train),
+            ) {
+                (Some(destination), Some(route)) if !route.steps.is_empty() => {
+                    let ahead = (1 + train.route.len()).min(route.steps.len());
+                    dispatcher::split_segments(system, &route.steps[ahead..], destination)
+                        .iter()
+                        .map(|segment| segment.steps.iter().map(|step| step.structure).collect())
+                        .collect()
+                }
+                _ => VecDeque::new(),
+            };
+
+            PlanTrain {
+                train_number: train.train_number,
+                holds,
+                segments,
+            }
+        })
+        .collect()
+}
+
+///Deadlocks
+///Looks for trains that wait for each other in a circle, none of them can ever move again
+///Every deadlock is returned once with its train numbers sorted
+pub fn deadlocks(plan: &[PlanTrain]) -> Vec<Vec<i32>> {
+    let mut found: Vec<Vec<i32>> = vec![];
+
+    for start in 0..plan.len() {
+        //Depth first search for a path of waiting trains that leads back to the start
+        let mut stack = vec![(start, vec![start])];
+        let mut visited = HashSet::new();
+
+        while let Some((current, path)) = stack.pop() {
+            for (next, other) in plan.iter().enumerate() {
+                if !plan[current].waits_for(other) {
+                    continue;
+                }
+
+                if next == start {
+                    let mut cycle: Vec<i32> = path.iter().map(|i| plan[*i].train_number).collect();
+                    cycle.sort_unstable();
+                    if !found.contains(&cycle) {
+                        found.push(cycle);
+                    }
+                } else if visited.insert(next) {
+                    let mut next_path = path.clone();
+                    next_path.push(next);
+                    stack.push((next, next_path));
+                }
+            }
+        }
+    }
+
+    found
+}
+
+///Detect
+///Deadlocks between the trains in the current state of the simulation
+pub fn detect(data: &WorkerData) -> Vec<Vec<i32>> {
+    deadlocks(&plan(&data.train_system, &data.trains))
+}
+
+///Can Move
+///A train can move when it still has segments and none of the structures of its next segment are held by others
+fn can_move(plan: &[PlanTrain], i: usize) -> bool {
+    !plan[i].segments.is_empty() && !plan.iter().any(|other| plan[i].waits_for(other))
+}
+
+///Advance
+///Gives a train its next segment in the search
+fn advance(plan: &[PlanTrain], i: usize) -> Vec<PlanTrain> {
+    let mut next = plan.to_vec();
+
+    if let Some(segment) = next[i].segments.pop_front() {
+        next[i].holds = segment.last().copied().into_iter().collect();
+    }
+
+    next
+}
+
+///Doomed
+///A state is doomed when it is deadlocked, or when every move that can be made within the depth leads to a doomed state
+///When no train can move, but there is no circle, trains are just blocked and it is not doomed
+fn doomed(plan: &[PlanTrain], depth: usize) -> bool {
+    if !deadlocks(plan).is_empty() {
+        return true;
+    }
+    if depth == 0 {
+        return false;
+    }
+
+    let moves: Vec<usize> = (0..plan.len()).filter(|i| can_move(plan, *i)).collect();
+
+    !moves.is_empty() && moves.iter().all(|i| doomed(&advance(plan, *i), depth - 1))
+}
+
+///Predicts Deadlock
+///Checks if setting a route for a train leads into a deadlock that can't be avoided within the depth
+///The route is reserved on a copy of the system first, so the other trains plan their routes around it
+///A depth of 0 only checks the state right after the route is set
+pub fn predicts_deadlock(
+    system: &TrainSystem,
+    trains: &[Train],
+    train_number: i32,
+    segment: &Route,
+    depth: usize,
+) -> bool {
+    let mut system = system.clone();
+    let mut trains = trains.to_vec();
+
+    for step in &segment.steps {
+        system.reservations.insert(step.structure, train_number);
+    }
+    if let Some(train) = trains
+        .iter_mut()
+        .find(|train| train.train_number == train_number)
+    {
+        train.route = segment.steps.iter().copied().collect();
+    }
+
+    doomed(&plan(&system, &trains), depth)
+}
@@ -0,0 +1,419 @@
+use std::fmt::Write;
+
+use eframe::emath::RectTransform;
+use eframe::epaint::{Color32, Pos2, Rect, Stroke, Vec2};
+
+use crate::train::geometry::{Geometry, TRACK_SPACING};
+use crate::train::train_structs::*;
+use crate::utils::format_time;
+use crate::worker::WorkerData;
+
+///Width of the svg drawing in pixels, the layout is scaled to fit
+const SVG_WIDTH: f32 = 1200.0;
+
+///Pixels between two parallel tracks in the svg drawing
+const SVG_TRACK_SPACING: f32 = 40.0;
+
+///Background of the svg drawing, the same as in the app
+const BACKGROUND: Color32 = Color32::from_rgb(0x3A, 0x3C, 0x49);
+
+///Colors of the layout drawing
+const RESERVED: Color32 = Color32::from_rgb(0x7B, 0xD3, 0x89);
+const DIMMED: Color32 = Color32::from_rgb(0x6B, 0x6D, 0x7A);
+const TRAIN: Color32 = Color32::from_rgb(0xFF, 0x8C, 0x42);
+
+///Line
+///A straight line between two points
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Line {
+    pub start: Pos2,
+    pub end: Pos2,
+    pub stroke: Stroke,
+}
+
+///Circle
+///A filled circle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Circle {
+    pub center: Pos2,
+    pub radius: f32,
+    pub color: Color32,
+}
+
+///Polygon
+///A filled convex polygon
+#[derive(Debug, Clone, PartialEq)]
+pub struct Polygon {
+    pub points: Vec<Pos2>,
+    pub color: Color32,
+}
+
+///Label
+///A text centered above its position
+#[derive(Debug, Clone, PartialEq)]
+pub struct Label {
+    pub position: Pos2,
+    pub text: String,
+    pub size: f32,
+    pub color: Color32,
+}
+
+///Drawing
+///The shapes of the layout, drawn in this order, both the app and the svg export draw it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Drawing {
+    pub lines: Vec<Line>,
+    pub polygons: Vec<Polygon>,
+    pub circles: Vec<Circle>,
+    pub labels: Vec<Label>,
+}
+
+///Dot
+///The pin and connection graph as a Graphviz graph, every structure is a node and every connection an edge
+///The ends of an edge are labeled with the pin ids, pins with a signal are marked with an S
+pub fn dot(system: &TrainSystem) -> String {
+    let mut dot = String::from("graph layout {\n    rankdir=LR;\n    node [shape=box];\n");
+
+    let mut idxs: Vec<&usize> = system.structures.keys().collect();
+    idxs.sort();
+    for idx in idxs {
+        let label = match &system.structures[idx] {
+            StructureTypes::Track(track) => format!("Track {}\\n{}m", idx, track.track_length),
+            StructureTypes::Switch(switch) => format!("Switch {}\\n{:?}", idx, switch.switch_type),
+            StructureTypes::ThreeWay(_) => format!("Three way switch {}", idx),
+            StructureTypes::Crossing(_) => format!("Crossing {}", idx),
+            StructureTypes::Slip(slip) => format!("Slip switch {}\\n{:?}", idx, slip.slip_type),
+            StructureTypes::Table(table) => format!(
+                "{:?} {}\\n{} tracks",
+                table.table_type,
+                idx,
+                table.output_pins.len()
+            ),
+        };
+        let _ = writeln!(dot, "    s{} [label=\"{}\"];", idx, label);
+    }
+
+    let mut connections: Vec<(&usize, &usize)> = system
+        .connections
+        .iter()
+        .filter(|(pin_a, pin_b)| pin_a < pin_b)
+        .collect();
+    connections.sort();
+    for (pin_a, pin_b) in connections {
+        if let (Some(a), Some(b)) = (system.pins.get(pin_a), system.pins.get(pin_b)) {
+            let _ = writeln!(
+                dot,
+                "    s{} -- s{} [taillabel=\"{}\", headlabel=\"{}\"];",
+                a,
+                b,
+                pin_label(system, *pin_a),
+                pin_label(system, *pin_b)
+            );
+        }
+    }
+
+    dot.push_str("}\n");
+    dot
+}
+
+fn pin_label(system: &TrainSystem, pin: usize) -> String {
+    match system.signal_at(pin) {
+        Some(signal) if signal.kind == SignalKind::Shunt => format!("{} SH", pin),
+        Some(_) => format!("{} S", pin),
+        None => pin.to_string(),
+    }
+}
+
+///Draw
+///The structures with their sections, the signals and the trains, transformed from the geometry to the target
+///Sections are yellow while active, red when disturbed, green when reserved and dimmed when closed
+///The paths a switch is not set to are drawn dimmed, section boundaries are short ticks
+///Signals are red, green or white for shunting and grey when failed
+///Each train is drawn over the part of the track it occupies with an arrow showing its direction and its number
+pub fn draw(data: &WorkerData, geometry: &Geometry, transform: &RectTransform) -> Drawing {
+    let system = &data.train_system;
+    let mut drawing = Drawing::default();
+    let line = |start: Pos2, end: Pos2, width: f32, color: Color32| Line {
+        start,
+        end,
+        stroke: Stroke::new(width, color),
+    };
+
+    let mut idxs: Vec<&usize> = system.structures.keys().collect();
+    idxs.sort();
+    for idx in idxs {
+        let structure = &system.structures[idx];
+
+        //The paths a switch is not set to and the tracks a table is not aligned to
+        //A switch that lost detection has no open path
+        let open = match structure.is_detection_lost() {
+            true => vec![],
+            false => structure.open_paths(),
+        };
+        for (input, output) in structure.idle_paths() {
+            if let (Some(input), Some(output)) =
+                (geometry.pins.get(&input), geometry.pins.get(&output))
+            {
+                drawing.lines.push(line(
+                    transform.transform_pos(*input),
+                    transform.transform_pos(*output),
+                    2.0,
+                    DIMMED,
+                ));
+            }
+        }
+
+        let reserved = system.reservations.contains_key(idx);
+        let closed = system.closures.contains(idx);
+
+        //The sections are drawn along every open path, the paths over a crossing share them
+        for ((input, output), section) in open.iter().flat_map(|path| {
+            structure
+                .sections()
+                .iter()
+                .map(move |section| (*path, section))
+        }) {
+            let start =
+                geometry.point_on_path(structure, input, output, section.distance_start as f64);
+            let end = geometry.point_on_path(structure, input, output, section.distance_end as f64);
+
+            if let (Some(start), Some(end)) = (start, end) {
+                let start = transform.transform_pos(start);
+                let end = transform.transform_pos(end);
+                let color = if section.disturbed {
+                    Color32::RED
+                } else if section.active {
+                    Color32::YELLOW
+                } else if reserved {
+                    RESERVED
+                } else if closed {
+                    DIMMED
+                } else {
+                    Color32::WHITE
+                };
+                drawing.lines.push(line(start, end, 2.0, color));
+
+                for point in [start, end] {
+                    drawing.lines.push(line(
+                        point - Vec2::new(0.0, 5.0),
+                        point + Vec2::new(0.0, 5.0),
+                        2.0,
+                        Color32::WHITE,
+                    ));
+                }
+            }
+        }
+    }
+
+    //Signals just inside the structure they stand on, facing the pin
+    let mut signals: Vec<&Signal> = system.signals.values().collect();
+    signals.sort_by_key(|signal| signal.idx);
+    for signal in signals {
+        let center = system
+            .pins
+            .get(&signal.pin)
+            .and_then(|idx| system.structures.get(idx))
+            .and_then(|structure| center(structure, geometry, transform));
+
+        if let (Some(center), Some(pin)) = (center, geometry.pins.get(&signal.pin)) {
+            let pin = transform.transform_pos(*pin);
+            let inward = (center - pin).normalized() * 8.0;
+
+            //Shunt signals are drawn smaller than main signals
+            drawing.circles.push(Circle {
+                center: pin + inward - Vec2::new(0.0, 10.0),
+                radius: match signal.kind {
+                    SignalKind::Main => 4.0,
+                    SignalKind::Shunt => 3.0,
+                },
+                color: match signal.aspect {
+                    _ if signal.failed => Color32::GRAY,
+                    Aspect::Stop => Color32::RED,
+                    Aspect::Proceed => Color32::GREEN,
+                    Aspect::Shunt => Color32::WHITE,
+                },
+            });
+        }
+    }
+
+    for train in &data.trains {
+        for (step, start, end) in train.occupied_intervals() {
+            if let (Some(start), Some(end)) = (
+                geometry.point_on_step(&step, start),
+                geometry.point_on_step(&step, end),
+            ) {
+                drawing.lines.push(line(
+                    transform.transform_pos(start),
+                    transform.transform_pos(end),
+                    6.0,
+                    TRAIN,
+                ));
+            }
+        }
+
+        //An arrow at the front of the train pointing in its direction of travel
+        if let Some(front) = train.path.front() {
+            let head = geometry.point_on_step(front, train.head_offset);
+            let exit = geometry.pins.get(&front.exit_pin);
+            let entry = geometry.pins.get(&front.entry_pin);
+
+            if let (Some(head), Some(exit), Some(entry)) = (head, exit, entry) {
+                let head = transform.transform_pos(head);
+                let heading =
+                    (transform.transform_pos(*exit) - transform.transform_pos(*entry)).normalized();
+                let side = heading.rot90() * 6.0;
+
+                drawing.polygons.push(Polygon {
+                    points: vec![head + heading * 10.0, head + side, head - side],
+                    color: TRAIN,
+                });
+                drawing.labels.push(Label {
+                    position: head - Vec2::new(0.0, 12.0),
+                    text: train.train_number.to_string(),
+                    size: 14.0,
+                    color: Color32::WHITE,
+                });
+            }
+        }
+    }
+
+    drawing
+}
+
+///Center
+///The middle of the pins of a structure, transformed to the target
+fn center(
+    structure: &StructureTypes,
+    geometry: &Geometry,
+    transform: &RectTransform,
+) -> Option<Pos2> {
+    let pins: Vec<Pos2> = structure
+        .pins()
+        .iter()
+        .filter_map(|pin| geometry.pins.get(pin))
+        .map(|pin| transform.transform_pos(*pin))
+        .collect();
+
+    match pins.is_empty() {
+        true => None,
+        false => Some(
+            Pos2::ZERO
+                + pins.iter().fold(Vec2::ZERO, |sum, pin| sum + pin.to_vec2()) / pins.len() as f32,
+        ),
+    }
+}
+
+///Svg
+///A schematic drawing of the layout at the moment of the data, drawn like the app draws it, see draw
+///The time of the data is written in the corner and every structure has its idx below its middle
+pub fn svg(data: &WorkerData) -> String {
+    let system = &data.train_system;
+    let geometry = Geometry::layout(system);
+
+    let bounds = match geometry.bounds() {
+        bounds if bounds.is_finite() => bounds.expand2(Vec2::new(TRACK_SPACING, TRACK_SPACING)),
+        _ => Rect::from_min_size(Pos2::ZERO, Vec2::splat(TRACK_SPACING)),
+    };
+    let height = (bounds.height() / TRACK_SPACING * SVG_TRACK_SPACING).ceil();
+    let to_svg = RectTransform::from_to(
+        bounds,
+        Rect::from_min_size(Pos2::ZERO, Vec2::new(SVG_WIDTH, height)),
+    );
+
+    let mut svg = String::new();
+    let _ = writeln!(
+        svg,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{}\" height=\"{}\" viewBox=\"0 0 {} {}\" font-family=\"sans-serif\">",
+        SVG_WIDTH, height, SVG_WIDTH, height
+    );
+    let _ = writeln!(
+        svg,
+        "  <rect width=\"100%\" height=\"100%\" fill=\"{}\"/>",
+        hex(BACKGROUND)
+    );
+    text(
+        &mut svg,
+        &Label {
+            position: Pos2::new(8.0, 16.0),
+            text: format_time(data.time),
+            size: 12.0,
+            color: Color32::WHITE,
+        },
+        "start",
+    );
+
+    let drawing = draw(data, &geometry, &to_svg);
+    for line in &drawing.lines {
+        let _ = writeln!(
+            svg,
+            "  <line x1=\"{:.1}\" y1=\"{:.1}\" x2=\"{:.1}\" y2=\"{:.1}\" stroke=\"{}\" stroke-width=\"{}\"/>",
+            line.start.x,
+            line.start.y,
+            line.end.x,
+            line.end.y,
+            hex(line.stroke.color),
+            line.stroke.width
+        );
+    }
+    for polygon in &drawing.polygons {
+        let points: Vec<String> = polygon
+            .points
+            .iter()
+            .map(|point| format!("{:.1},{:.1}", point.x, point.y))
+            .collect();
+        let _ = writeln!(
+            svg,
+            "  <polygon points=\"{}\" fill=\"{}\"/>",
+            points.join(" "),
+            hex(polygon.color)
+        );
+    }
+    for circle in &drawing.circles {
+        let _ = writeln!(
+            svg,
+            "  <circle cx=\"{:.1}\" cy=\"{:.1}\" r=\"{}\" fill=\"{}\"/>",
+            circle.center.x,
+            circle.center.y,
+            circle.radius,
+            hex(circle.color)
+        );
+    }
+    for label in &drawing.labels {
+        text(&mut svg, label, "middle");
+    }
+
+    //The structure idx below its middle
+    let mut idxs: Vec<&usize> = system.structures.keys().collect();
+    idxs.sort();
+    for idx in idxs {
+        if let Some(center) = center(&system.structures[idx], &geometry, &to_svg) {
+            let label = Label {
+                position: center + Vec2::new(0.0, 18.0),
+                text: idx.to_string(),
+                size: 10.0,
+                color: DIMMED,
+            };
+            text(&mut svg, &label, "middle");
+        }
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn text(svg: &mut String, label: &Label, anchor: &str) {
+    let _ = writeln!(
+        svg,
+        "  <text x=\"{:.1}\" y=\"{:.1}\" font-size=\"{}\" fill=\"{}\" text-anchor=\"{}\">{}</text>",
+        label.position.x,
+        label.position.y,
+        label.size,
+        hex(label.color),
+        anchor,
+        label.text
+    );
+}
+
+fn hex(color: Color32) -> String {
+    format!("#{:02X}{:02X}{:02X}", color.r(), color.g(), color.b())
+}
@@ -0,0 +1,342 @@
+use crate::deadlock;
+use crate::train::routing::{self, Route};
+use crate::train::train_structs::*;
+use crate::worker::{self, Command, Notification, WorkerData};
+use serde::{Deserialize, Serialize};
+
+///Waiting Signal
+///The signal at the end of the structure a train is on, when that signal shows Stop
+///The train doesn't have to stand still yet, the route can be set while it approaches
+///Only signals the train has to stop at count, a shunt signal doesn't hold a train that isn't shunting
+pub fn waiting_signal(system: &TrainSystem, train: &Train) -> Option<usize> {
+    let front = train.path.front()?;
+
+    match system.signal_at(front.exit_pin) {
+        Some(signal) if signal.stops(train.shunting) => Some(signal.idx),
+        _ => None,
+    }
+}
+
+///Held By Others
+///Structures that are reserved or occupied by any other train
+pub fn held_by_others(system: &TrainSystem, train_number: i32) -> Vec<usize> {
+    let mut held: Vec<usize> = system
+        .structures
+        .iter()
+        .filter(|(idx, _)| !system.is_free(**idx, train_number))
+        .map(|(idx, _)| *idx)
+        .collect();
+    held.sort_unstable();
+    held
+}
+
+///Route To Destination
+///The route from the front of a train to its destination
+///Structures held by other trains are avoided when possible, so trains can pass each other on a passing loop
+pub fn route_to_destination(system: &TrainSystem, train: &Train) -> Option<Route> {
+    let destination = train.destination?;
+    let front = train.path.front()?;
+    let avoid = held_by_others(system, train.train_number);
+
+    routing::find_route_avoiding(system, front, destination, &avoid)
+}
+
+///Split Segments
+///Splits a list of steps into the parts between signals, a segment ends at a main signal or at the destination
+pub fn split_segments(system: &TrainSystem, steps: &[TrainStep], destination: usize) -> Vec<Route> {
+    let mut segments = vec![];
+    let mut segment = Route::default();
+
+    for step in steps {
+        segment.steps.push(*step);
+
+        let at_destination = step.structure == destination;
+        let signal = system
+            .signal_at(step.exit_pin)
+            .is_some_and(|signal| signal.kind == SignalKind::Main);
+        if at_destination || signal {
+            segments.push(std::mem::take(&mut segment));
+        }
+        if at_destination {
+            break;
+        }
+    }
+
+    if !segment.steps.is_empty() {
+        segments.push(segment);
+    }
+
+    for segment in &mut segments {
+        for step in &segment.steps {
+            let item = &system.structures[&step.structure];
+            if let Some(position) = item.required_position(step.entry_pin, step.exit_pin) {
+                segment.switches.push((step.structure, position));
+            }
+        }
+    }
+
+    segments
+}
+
+///Next Segment
+///The part of the route towards the destination of a train, from the signal it waits at up to the next signal
+///A segment also ends at the destination of the train
+pub fn next_segment(system: &TrainSystem, train: &Train) -> Option<Route> {
+    let destination = train.destination?;
+    let route = route_to_destination(system, train)?;
+
+    split_segments(system, &route.steps[1..], destination)
+        .into_iter()
+        .next()
+}
+
+///Set Route
+///Throws the switches and reserves the structures of a segment for a train, then clears the signal
+///Nothing is changed when any of the structures is reserved or occupied by another train, or the signal failed
+pub fn set_route(
+    system: &mut TrainSystem,
+    train: &mut Train,
+    segment: &Route,
+    signal: usize,
+) -> bool {
+    if system
+        .signals
+        .get(&signal)
+        .is_some_and(|signal| signal.failed)
+    {
+        return false;
+    }
+
+    let free = segment
+        .steps
+        .iter()
+        .all(|step| system.is_free(step.structure, train.train_number));
+
+    if !free {
+        return false;
+    }
+
+    for (idx, position) in &segment.switches {
+        if !system.throw_switch(*idx, *position) {
+            return false;
+        }
+    }
+
+    for step in &segment.steps {
+        system
+            .reservations
+            .insert(step.structure, train.train_number);
+    }
+
+    if let Some(signal) = system.signals.get_mut(&signal) {
+        signal.aspect = Aspect::Proceed;
+    }
+
+    train.route = segment.steps.iter().copied().collect();
+    true
+}
+
+///Route Train
+///Sets the next part of the route for a train waiting at a signal, without checking for deadlocks
+///Returns false when the train isn't waiting, is held or the route can't be set
+pub fn route_train(data: &mut WorkerData, train_number: i32) -> bool {
+    let system = &mut data.train_system;
+
+    let train = match data
+        .trains
+        .iter_mut()
+        .find(|train| train.train_number == train_number && !train.held)
+    {
+        Some(train) => train,
+        None => return false,
+    };
+
+    let routed = match (waiting_signal(system, train), next_segment(system, train)) {
+        (Some(signal), Some(segment)) => {
+            set_route(system, train, &segment, signal).then_some(signal)
+        }
+        _ => None,
+    };
+
+    if let Some(signal) = routed {
+        data.notifications.push(Notification::RouteSet {
+            train: train_number,
+            signal,
+        });
+    }
+    routed.is_some()
+}
+
+///Dispatcher
+///A control strategy, it gets a read-only view of the simulation every step and returns the commands to run
+///Commands are run in order, e.g. set route, throw switch or hold train
+///The dispatch queue holds the trains waiting at a signal for their route, in the order they started waiting
+pub trait Dispatcher {
+    fn name(&self) -> &'static str;
+    fn dispatch(&self, data: &WorkerData) -> Vec<Command>;
+}
+
+///Strategy
+///The built-in dispatchers that can be selected from the UI and the scenario
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Strategy {
+    FirstComeFirstServed,
+    Priority,
+    #[default]
+    Lookahead,
+}
+
+impl Strategy {
+    pub const ALL: [Strategy; 3] = [
+        Strategy::FirstComeFirstServed,
+        Strategy::Priority,
+        Strategy::Lookahead,
+    ];
+
+    ///Dispatcher
+    ///The dispatcher implementing this strategy
+    pub fn dispatcher(&self) -> Box<dyn Dispatcher> {
+        match self {
+            Strategy::FirstComeFirstServed => Box::new(FirstComeFirstServed),
+            Strategy::Priority => Box::new(PriorityDispatcher),
+            Strategy::Lookahead => Box::new(LookaheadDispatcher),
+        }
+    }
+}
+
+///First Come First Served
+///Sets routes in the order the trains started waiting, a train whose route is not free waits for the next step
+pub struct FirstComeFirstServed;
+
+impl Dispatcher for FirstComeFirstServed {
+    fn name(&self) -> &'static str {
+        "First come first served"
+    }
+
+    fn dispatch(&self, data: &WorkerData) -> Vec<Command> {
+        data.dispatch_queue
+            .iter()
+            .map(|number| Command::SetRoute(*number))
+            .collect()
+    }
+}
+
+///Priority Dispatcher
+///Sets routes for the trains with the highest priority first, trains with the same priority keep the order of the queue
+pub struct PriorityDispatcher;
+
+impl Dispatcher for PriorityDispatcher {
+    fn name(&self) -> &'static str {
+        "Priority"
+    }
+
+    fn dispatch(&self, data: &WorkerData) -> Vec<Command> {
+        by_priority(data)
+            .into_iter()
+            .map(Command::SetRoute)
+            .collect()
+    }
+}
+
+///Lookahead Dispatcher
+///Handles the trains by priority, but a route that would lead into a deadlock within the lookahead of the data is not set
+///Routes it sets are reserved on a copy, so the next train is checked against them
+pub struct LookaheadDispatcher;
+
+impl Dispatcher for LookaheadDispatcher {
+    fn name(&self) -> &'static str {
+        "Lookahead"
+    }
+
+    fn dispatch(&self, data: &WorkerData) -> Vec<Command> {
+        let mut system = data.train_system.clone();
+        let mut trains = data.trains.clone();
+        let mut commands = vec![];
+
+        for number in by_priority(data) {
+            let i = match trains.iter().position(|train| train.train_number == number) {
+                Some(i) => i,
+                None => continue,
+            };
+
+            let (signal, segment) = match (
+                waiting_signal(&system, &trains[i]),
+                next_segment(&system, &trains[i]),
+            ) {
+                (Some(signal), Some(segment)) => (signal, segment),
+                _ => continue,
+            };
+
+            if deadlock::predicts_deadlock(&system, &trains, number, &segment, data.lookahead) {
+                continue;
+            }
+
+            if set_route(&mut system, &mut trains[i], &segment, signal) {
+                commands.push(Command::SetRoute(number));
+            }
+        }
+
+        commands
+    }
+}
+
+///By Priority
+///The dispatch queue sorted by priority, highest first, trains with the same priority keep their order
+fn by_priority(data: &WorkerData) -> Vec<i32> {
+    let priority = |number: &i32| {
+        data.trains
+            .iter()
+            .find(|train| train.train_number == *number)
+            .map_or(0, |train| train.priority)
+    };
+
+    let mut queue = data.dispatch_queue.clone();
+    queue.sort_by_key(|number| std::cmp::Reverse(priority(number)));
+    queue
+}
+
+///Update Queue
+///Adds the trains with a destination that started waiting at a signal to the end of the queue
+///Trains that are no longer waiting, or are held, leave the queue, shunting trains are moved by their shunt move
+pub fn update_queue(data: &mut WorkerData) {
+    let system = &data.train_system;
+
+    let waiting: Vec<i32> = data
+        .trains
+        .iter()
+        .filter(|train| {
+            train.destination.is_some()
+                && !train.held
+                && !train.shunting
+                && waiting_signal(system, train).is_some()
+        })
+        .map(|train| train.train_number)
+        .collect();
+
+    data.dispatch_queue
+        .retain(|number| waiting.contains(number));
+    for number in waiting {
+        if !data.dispatch_queue.contains(&number) {
+            data.dispatch_queue.push(number);
+        }
+    }
+}
+
+///Dispatch
+///Runs the dispatcher of the selected strategy, see dispatch with
+pub fn dispatch(data: &mut WorkerData) {
+    let dispatcher = data.strategy.dispatcher();
+    dispatch_with(data, dispatcher.as_ref());
+}
+
+///Dispatch With
+///Updates the queue, runs the commands of a dispatcher and removes the trains that got their route from the queue
+pub fn dispatch_with(data: &mut WorkerData, dispatcher: &dyn Dispatcher) {
+    update_queue(data);
+
+    for command in dispatcher.dispatch(data) {
+        worker::apply_command(data, command);
+    }
+
+    update_queue(data);
+}
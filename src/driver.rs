@@ -0,0 +1,200 @@
+use crate::train::physics::{Forces, MIN_DECELERATION};
+use crate::train::routing;
+use crate::train::train_structs::*;
+use crate::worker;
+
+///Meters a driver looks ahead for signals showing stop and the end of the movement authority
+pub const LOOKAHEAD: f64 = 3000.0;
+
+///Share of its maximum speed an eco driver cruises at
+pub const ECO_CRUISE: f64 = 0.85;
+
+///Share of full traction an eco driver accelerates with
+pub const ECO_THROTTLE: f64 = 0.6;
+
+///Share of full braking an eco driver brakes with
+pub const ECO_BRAKE: f64 = 0.5;
+
+///An eco driver starts coasting this many times its braking distance before it has to stop
+pub const COAST_FACTOR: f64 = 3.0;
+
+///Speed in m/s a driver doesn't coast below, so the train doesn't come to a halt before it gets anywhere
+pub const MIN_COAST_SPEED: f64 = 5.0;
+
+///A timetable driver keeps this much above the average speed it needs to arrive on time, it has to brake at the end
+pub const TIMETABLE_RESERVE: f64 = 1.15;
+
+///Control
+///The share of traction and the share of braking a driver chooses for a tick, both from 0 to 1
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Control {
+    pub throttle: f64,
+    pub brake: f64,
+}
+
+impl Control {
+    ///Full throttle, the train accelerates as hard as it can
+    pub const FULL: Control = Control {
+        throttle: 1.0,
+        brake: 0.0,
+    };
+
+    ///No traction and no braking, the train rolls on against its resistance
+    pub const COAST: Control = Control {
+        throttle: 0.0,
+        brake: 0.0,
+    };
+}
+
+///Control
+///What the driver of a train does this tick, given the forces on the train, its effective performance and its movement authority
+///Whatever its style, a driver brakes for the end of its authority and keeps to its speed limit as it judges them,
+///the train protection supervises whether that was enough
+///A train that broke down has lost its traction, its driver brakes it to a stand
+pub fn control(
+    system: &TrainSystem,
+    train: &Train,
+    time: f64,
+    elapsed: f64,
+    authority: f64,
+    forces: &Forces,
+    performance: &Performance,
+) -> Control {
+    if train.broken_down {
+        return Control {
+            throttle: 0.0,
+            brake: 1.0,
+        };
+    }
+
+    let chosen = match train.driver {
+        Driver::Aggressive => Control::FULL,
+        Driver::Timetable => timetable(system, train, time),
+        Driver::Eco => eco(system, train, forces, performance),
+    };
+
+    let target = performance
+        .max_speed
+        .min((2.0 * judged_deceleration(train, forces) * authority).sqrt());
+    let limit = follow(forces, train.speed, target, elapsed);
+
+    match forces.acceleration(limit.throttle, limit.brake)
+        < forces.acceleration(chosen.throttle, chosen.brake)
+    {
+        true => limit,
+        false => chosen,
+    }
+}
+
+///Judged Deceleration
+///The deceleration a driver counts on when it brakes for the end of its authority
+///Aggressive and timetable drivers brake as late as the brakes allow and leave the gradient out,
+///so downhill they brake too late. An eco driver takes the resistance into account
+fn judged_deceleration(train: &Train, forces: &Forces) -> f64 {
+    match train.driver {
+        Driver::Aggressive | Driver::Timetable => forces.braking,
+        Driver::Eco => forces.braking + forces.resistance,
+    }
+    .max(MIN_DECELERATION)
+}
+
+///Follow
+///The traction or braking that brings the train to the target speed in the elapsed time, as far as it can
+fn follow(forces: &Forces, speed: f64, target: f64, elapsed: f64) -> Control {
+    let required = match elapsed > 0.0 {
+        true => (target - speed) / elapsed,
+        false => 0.0,
+    };
+
+    match required + forces.resistance {
+        wheel if wheel >= 0.0 => Control {
+            throttle: match forces.traction > 0.0 {
+                true => (wheel / forces.traction).min(1.0),
+                false => 0.0,
+            },
+            brake: 0.0,
+        },
+        wheel => Control {
+            throttle: 0.0,
+            brake: (-wheel / forces.braking.max(MIN_DECELERATION)).min(1.0),
+        },
+    }
+}
+
+///Timetable
+///Runs at full throttle, but coasts while the train is faster than it needs to be to reach its next stop on time
+///A train that is late, or has no timetable, is driven at full throttle
+fn timetable(system: &TrainSystem, train: &Train, time: f64) -> Control {
+    let stop = match train.timetable.get(train.next_stop) {
+        Some(stop) => stop,
+        None => return Control::FULL,
+    };
+    let due = match stop.arrival.or(stop.departure) {
+        Some(due) if due > time => due - time,
+        _ => return Control::FULL,
+    };
+    let distance = match train
+        .path
+        .front()
+        .and_then(|front| routing::find_route(system, front, stop.structure))
+    {
+        Some(route) => route.length() - train.head_offset,
+        None => return Control::FULL,
+    };
+
+    let required = distance / due * TIMETABLE_RESERVE;
+    match train.speed > required && train.speed > MIN_COAST_SPEED {
+        true => Control::COAST,
+        false => Control::FULL,
+    }
+}
+
+///Eco
+///Accelerates with part of its traction up to its cruising speed and holds it there
+///Ahead of a stop it coasts first and then brakes softly, just enough to stop at the end of its movement authority
+fn eco(system: &TrainSystem, train: &Train, forces: &Forces, performance: &Performance) -> Control {
+    let speed = train.speed;
+    let stop = worker::movement_authority(system, train, LOOKAHEAD);
+    let soft = (forces.braking * ECO_BRAKE + forces.resistance).max(MIN_DECELERATION);
+    let braking_distance = speed * speed / (2.0 * soft);
+
+    if stop <= braking_distance {
+        let required = match stop > 0.0 {
+            true => speed * speed / (2.0 * stop),
+            false => forces.braking + forces.resistance,
+        };
+        return Control {
+            throttle: 0.0,
+            brake: ((required - forces.resistance) / forces.braking.max(MIN_DECELERATION))
+                .clamp(0.0, 1.0),
+        };
+    }
+
+    if stop <= braking_distance * COAST_FACTOR && speed > MIN_COAST_SPEED {
+        return Control::COAST;
+    }
+
+    match speed < performance.max_speed * ECO_CRUISE {
+        true => Control {
+            throttle: ECO_THROTTLE,
+            brake: 0.0,
+        },
+        false => hold(forces),
+    }
+}
+
+///Hold
+///Just enough traction to balance the resistance, or braking when the train runs downhill
+fn hold(forces: &Forces) -> Control {
+    match forces.resistance > 0.0 {
+        true if forces.traction > 0.0 => Control {
+            throttle: (forces.resistance / forces.traction).min(1.0),
+            brake: 0.0,
+        },
+        true => Control::COAST,
+        false => Control {
+            throttle: 0.0,
+            brake: (-forces.resistance / forces.braking.max(MIN_DECELERATION)).min(1.0),
+        },
+    }
+}
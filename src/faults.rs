@@ -0,0 +1,160 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::train::train_structs::*;
+use crate::worker::{self, WorkerData};
+
+///Fault
+///A disruption that can be injected into the simulation and cleared later
+///A stuck switch can't be thrown, a switch that lost detection can't be thrown or passed
+///A failed signal shows Stop, a false occupancy keeps a section active without a train
+///A closed track can't be entered and a train that broke down can't move
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Fault {
+    SwitchStuck(usize),
+    SwitchDetectionLost(usize),
+    SignalFailure(usize),
+    FalseOccupancy(usize, usize),
+    TrackClosed(usize),
+    TrainBreakdown(i32),
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fault::SwitchStuck(idx) => write!(f, "Switch {} is stuck", idx),
+            Fault::SwitchDetectionLost(idx) => write!(f, "Switch {} lost detection", idx),
+            Fault::SignalFailure(idx) => write!(f, "Signal {} failed", idx),
+            Fault::FalseOccupancy(idx, section) => {
+                write!(f, "False occupancy on section {} of {}", section, idx)
+            }
+            Fault::TrackClosed(idx) => write!(f, "Structure {} is closed", idx),
+            Fault::TrainBreakdown(number) => write!(f, "Train {} broke down", number),
+        }
+    }
+}
+
+///Inject
+///Puts a fault into the simulation, returns false when what it applies to doesn't exist
+pub fn inject(data: &mut WorkerData, fault: Fault) -> bool {
+    set_fault(data, fault, true)
+}
+
+///Clear
+///Removes a fault from the simulation, returns false when what it applies to doesn't exist
+pub fn clear(data: &mut WorkerData, fault: Fault) -> bool {
+    set_fault(data, fault, false)
+}
+
+///Set Fault
+///Sets or clears the flag for a fault, sections are updated afterwards so occupancy is shown right away
+fn set_fault(data: &mut WorkerData, fault: Fault, active: bool) -> bool {
+    let system = &mut data.train_system;
+
+    let found = match fault {
+        Fault::SwitchStuck(idx) => system
+            .structures
+            .get_mut(&idx)
+            .is_some_and(|structure| structure.set_stuck(active)),
+        Fault::SwitchDetectionLost(idx) => system
+            .structures
+            .get_mut(&idx)
+            .is_some_and(|structure| structure.set_detection_lost(active)),
+        Fault::SignalFailure(idx) => match system.signals.get_mut(&idx) {
+            Some(signal) => {
+                signal.failed = active;
+                if active {
+                    signal.aspect = Aspect::Stop;
+                }
+                true
+            }
+            None => false,
+        },
+        Fault::FalseOccupancy(idx, section) => match system
+            .structures
+            .get_mut(&idx)
+            .and_then(|structure| structure.sections_mut().get_mut(section))
+        {
+            Some(section) => {
+                section.false_occupancy = active;
+                true
+            }
+            None => false,
+        },
+        Fault::TrackClosed(idx) => {
+            if !active {
+                system.closures.remove(&idx);
+            } else if system.structures.contains_key(&idx) {
+                system.closures.insert(idx);
+            }
+            system.structures.contains_key(&idx)
+        }
+        Fault::TrainBreakdown(number) => match data
+            .trains
+            .iter_mut()
+            .find(|train| train.train_number == number)
+        {
+            Some(train) => {
+                train.broken_down = active;
+                true
+            }
+            None => false,
+        },
+    };
+
+    worker::update_sections(data);
+    found
+}
+
+///Active Faults
+///All faults currently in the simulation, ordered by structure, signal and train
+pub fn active_faults(data: &WorkerData) -> Vec<Fault> {
+    let system = &data.train_system;
+    let mut faults = vec![];
+
+    let mut structures: Vec<&usize> = system.structures.keys().collect();
+    structures.sort_unstable();
+
+    for idx in structures {
+        let structure = &system.structures[idx];
+
+        if structure.is_stuck() {
+            faults.push(Fault::SwitchStuck(*idx));
+        }
+        if structure.is_detection_lost() {
+            faults.push(Fault::SwitchDetectionLost(*idx));
+        }
+
+        for (i, section) in structure.sections().iter().enumerate() {
+            if section.false_occupancy {
+                faults.push(Fault::FalseOccupancy(*idx, i));
+            }
+        }
+
+        if system.closures.contains(idx) {
+            faults.push(Fault::TrackClosed(*idx));
+        }
+    }
+
+    let mut signals: Vec<&Signal> = system
+        .signals
+        .values()
+        .filter(|signal| signal.failed)
+        .collect();
+    signals.sort_by_key(|signal| signal.idx);
+    faults.extend(
+        signals
+            .iter()
+            .map(|signal| Fault::SignalFailure(signal.idx)),
+    );
+
+    faults.extend(
+        data.trains
+            .iter()
+            .filter(|train| train.broken_down)
+            .map(|train| Fault::TrainBreakdown(train.train_number)),
+    );
+
+    faults
+}
@@ -0,0 +1,268 @@
+use std::collections::{BTreeMap, HashMap};
+
+use crate::train::routing;
+use crate::train::train_structs::*;
+use crate::worker::WorkerData;
+
+///Seconds between two samples of the position of a train
+pub const SAMPLE_INTERVAL: f64 = 5.0;
+
+///Seconds of history kept for the train graph, older samples and occupations are dropped
+pub const HISTORY_WINDOW: f64 = 2.0 * 3600.0;
+
+///Sample
+///Where the front of a train was at a time, as a distance from the input pin of its structure
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Sample {
+    pub time: f64,
+    pub structure: usize,
+    pub distance: f64,
+}
+
+///Occupation
+///A section that was active from the start until the end time
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Occupation {
+    pub structure: usize,
+    pub section: usize,
+    pub start: f64,
+    pub end: f64,
+}
+
+///History
+///What the train graph draws: the positions of the trains and the occupation of the sections over time
+///Active holds the start time of the sections that are active right now
+#[derive(Debug, Clone, Default)]
+pub struct History {
+    pub trains: BTreeMap<i32, Vec<Sample>>,
+    pub occupations: Vec<Occupation>,
+    active: HashMap<(usize, usize), f64>,
+}
+
+impl History {
+    ///Occupations
+    ///All occupations including those of the sections that are still active, they end now
+    pub fn occupations(&self, now: f64) -> Vec<Occupation> {
+        let mut occupations = self.occupations.clone();
+        for ((structure, section), start) in &self.active {
+            occupations.push(Occupation {
+                structure: *structure,
+                section: *section,
+                start: *start,
+                end: now,
+            });
+        }
+        occupations
+    }
+}
+
+///Record
+///Adds the state after a tick to the history, a train gets a new sample every sample interval
+///or when its front moved onto another structure
+pub fn record(data: &mut WorkerData) {
+    let time = data.time;
+    let history = &mut data.history;
+
+    for train in &data.trains {
+        let front = match train.path.front() {
+            Some(front) => front,
+            None => continue,
+        };
+        let sample = Sample {
+            time,
+            structure: front.structure,
+            distance: front.structure_distance(train.head_offset),
+        };
+
+        let samples = history.trains.entry(train.train_number).or_default();
+        let due = samples.last().is_none_or(|last| {
+            time - last.time >= SAMPLE_INTERVAL || last.structure != sample.structure
+        });
+        if due {
+            samples.push(sample);
+        }
+    }
+
+    for (idx, structure) in &data.train_system.structures {
+        for (i, section) in structure.sections().iter().enumerate() {
+            match (section.active, history.active.get(&(*idx, i))) {
+                (true, None) => {
+                    history.active.insert((*idx, i), time);
+                }
+                (false, Some(start)) => {
+                    history.occupations.push(Occupation {
+                        structure: *idx,
+                        section: i,
+                        start: *start,
+                        end: time,
+                    });
+                    history.active.remove(&(*idx, i));
+                }
+                _ => {}
+            }
+        }
+    }
+
+    let oldest = time - HISTORY_WINDOW;
+    for samples in history.trains.values_mut() {
+        let old = samples
+            .iter()
+            .take_while(|sample| sample.time < oldest)
+            .count();
+        samples.drain(..old);
+    }
+    history
+        .occupations
+        .retain(|occupation| occupation.end >= oldest);
+}
+
+///Corridor
+///The distance axis of the train graph: a route through the train system from one structure to another
+///Every step is kept with the distance of its entry pin from the start of the corridor
+#[derive(Debug, Clone)]
+pub struct Corridor {
+    pub steps: Vec<(TrainStep, f64)>,
+}
+
+impl Corridor {
+    ///New
+    ///The shortest route between two structures, leaving the first structure in either direction
+    pub fn new(system: &TrainSystem, from: usize, to: usize) -> Option<Corridor> {
+        let structure = system.structures.get(&from)?;
+
+        let route = [Direction::Forward, Direction::Reverse]
+            .iter()
+            .filter_map(|direction| structure.entry_pin(*direction))
+            .filter_map(|entry_pin| routing::step(system, from, entry_pin))
+            .filter_map(|step| routing::find_route(system, &step, to))
+            .min_by(|a, b| a.length().total_cmp(&b.length()))?;
+
+        let mut distance = 0.0;
+        let mut steps = vec![];
+        for step in route.steps {
+            steps.push((step, distance));
+            distance += step.length;
+        }
+
+        Some(Corridor { steps })
+    }
+
+    ///Length
+    ///Total length of the corridor in meters
+    pub fn length(&self) -> f64 {
+        self.steps
+            .last()
+            .map_or(0.0, |(step, distance)| distance + step.length)
+    }
+
+    ///Position
+    ///Distance along the corridor of a point on a structure, given as a distance from its input pin
+    ///None when the structure is not part of the corridor
+    pub fn position(&self, structure: usize, distance: f64) -> Option<f64> {
+        let (step, start) = self
+            .steps
+            .iter()
+            .find(|(step, _)| step.structure == structure)?;
+        Some(start + step.structure_distance(distance.clamp(0.0, step.length)))
+    }
+
+    ///Span
+    ///The part of the corridor a stretch of a structure covers, the lowest distance first
+    pub fn span(&self, structure: usize, start: f64, end: f64) -> Option<(f64, f64)> {
+        let a = self.position(structure, start)?;
+        let b = self.position(structure, end)?;
+        Some((a.min(b), a.max(b)))
+    }
+
+    ///Actual
+    ///The lines a train drew through the graph as points of time and distance
+    ///A line ends where the train left the corridor, the next one starts where it came back
+    pub fn actual(&self, samples: &[Sample]) -> Vec<Vec<(f64, f64)>> {
+        let mut lines = vec![];
+        let mut line = vec![];
+
+        for sample in samples {
+            match self.position(sample.structure, sample.distance) {
+                Some(position) => line.push((sample.time, position)),
+                None if !line.is_empty() => lines.push(std::mem::take(&mut line)),
+                None => {}
+            }
+        }
+        if !line.is_empty() {
+            lines.push(line);
+        }
+
+        lines
+    }
+
+    ///Planned
+    ///The planned line of a train through the stops of its timetable on the corridor
+    ///A train arrives at the far end of a stop and departs from the end towards its next stop
+    pub fn planned(&self, train: &Train) -> Vec<(f64, f64)> {
+        let stops: Vec<(&TimetableStop, (f64, f64))> = train
+            .timetable
+            .iter()
+            .filter_map(|stop| Some((stop, self.span(stop.structure, 0.0, f64::INFINITY)?)))
+            .collect();
+        let center = |i: usize| (stops[i].1 .0 + stops[i].1 .1) / 2.0;
+
+        let mut points = vec![];
+        for (i, (stop, (low, high))) in stops.iter().enumerate() {
+            let previous = i.checked_sub(1).map(center);
+            let next = Some(i + 1).filter(|next| *next < stops.len()).map(center);
+
+            if let Some(arrival) = stop.arrival {
+                let distance = match previous {
+                    Some(previous) if previous < center(i) => *high,
+                    Some(_) => *low,
+                    None => center(i),
+                };
+                points.push((arrival, distance));
+            }
+            if let Some(departure) = stop.departure {
+                let distance = match next {
+                    Some(next) if next > center(i) => *high,
+                    Some(_) => *low,
+                    None => center(i),
+                };
+                points.push((departure, distance));
+            }
+        }
+
+        points
+    }
+
+    ///Occupied
+    ///The occupations of sections on the corridor as time and distance ranges
+    pub fn occupied(&self, occupations: &[Occupation], system: &TrainSystem) -> Vec<Block> {
+        occupations
+            .iter()
+            .filter_map(|occupation| {
+                let structure = system.structures.get(&occupation.structure)?;
+                let section = structure.sections().get(occupation.section)?;
+                let (low, high) = self.span(
+                    occupation.structure,
+                    section.distance_start as f64,
+                    section.distance_end as f64,
+                )?;
+                Some(Block {
+                    start: occupation.start,
+                    end: occupation.end,
+                    low,
+                    high,
+                })
+            })
+            .collect()
+    }
+}
+
+///Block
+///A section on the corridor that was occupied from the start until the end time
+///Low and high are its distances along the corridor
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Block {
+    pub start: f64,
+    pub end: f64,
+    pub low: f64,
+    pub high: f64,
+}
@@ -0,0 +1,84 @@
+use tokio::sync::mpsc::UnboundedSender;
+
+use crate::api::{self, Api};
+use crate::dcc::{self, Bridge, DccConfig};
+use crate::mqtt::{self, Mqtt, MqttConfig};
+use crate::rest;
+use crate::worker::{Command, WorkerData};
+
+///Interfaces
+///Everything besides the app that can see and control the simulation, only what is configured is started
+///The WebSocket API and the REST endpoints listen on their address, see api and rest
+///A model layout is connected with a DCC++ command station, see dcc, and MQTT clients through a broker, see mqtt
+#[derive(Debug, Clone, Default)]
+pub struct Interfaces {
+    pub websocket: Option<String>,
+    pub http: Option<String>,
+    pub dcc: Option<DccConfig>,
+    pub mqtt: Option<MqttConfig>,
+}
+
+///Publishers
+///The running interfaces, each gets every update of the worker loop
+#[derive(Debug, Clone, Default)]
+pub struct Publishers {
+    pub api: Option<Api>,
+    pub bridge: Option<Bridge>,
+    pub mqtt: Option<Mqtt>,
+}
+
+impl Interfaces {
+    pub fn is_empty(&self) -> bool {
+        self.websocket.is_none() && self.http.is_none() && self.dcc.is_none() && self.mqtt.is_none()
+    }
+
+    ///Start
+    ///Starts the configured interfaces, their commands are sent to the worker loop like those of the UI
+    ///The servers bind their address right away, the error names the address that could not be bound
+    ///The command station and the broker are connected in the background
+    pub fn start(&self, commands: UnboundedSender<Command>) -> std::io::Result<Publishers> {
+        let mut publishers = Publishers::default();
+
+        if self.websocket.is_some() || self.http.is_some() {
+            let api = Api::new(commands.clone());
+            if let Some(address) = &self.websocket {
+                api::listen(address, &api).map_err(|error| named(address, error))?;
+            }
+            if let Some(address) = &self.http {
+                rest::listen(address, &api).map_err(|error| named(address, error))?;
+            }
+            publishers.api = Some(api);
+        }
+
+        publishers.bridge = self
+            .dcc
+            .clone()
+            .map(|config| dcc::start(config, commands.clone()));
+        publishers.mqtt = self
+            .mqtt
+            .clone()
+            .map(|config| mqtt::start(config, commands));
+
+        Ok(publishers)
+    }
+}
+
+impl Publishers {
+    ///Publish
+    ///Passes an update of the worker loop to every running interface
+    pub fn publish(&self, data: &WorkerData) {
+        if let Some(api) = &self.api {
+            api.publish(data);
+        }
+        if let Some(bridge) = &self.bridge {
+            bridge.publish(data);
+        }
+        if let Some(mqtt) = &self.mqtt {
+            mqtt.publish(data);
+        }
+    }
+}
+
+fn named(address: &str, error: std::io::Error) -> std::io::Error {
+    std::io::Error::new(error.kind(), format!("{}: {}", address, error))
+}
@@ -0,0 +1,26 @@
+pub mod api;
+pub mod atp;
+pub mod batch;
+pub mod consist;
+pub mod dcc;
+pub mod deadlock;
+pub mod diagram;
+pub mod dispatcher;
+pub mod driver;
+pub mod faults;
+pub mod graph;
+pub mod interfaces;
+pub mod mqtt;
+pub mod railml;
+pub mod rest;
+pub mod scenario;
+pub mod scripting;
+pub mod shunting;
+pub mod stats;
+pub mod tables;
+pub mod train;
+pub mod trainsim;
+pub mod utils;
+pub mod worker;
+
+mod tests;
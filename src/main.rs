@@ -1,24 +1,396 @@
-mod tests;
-mod train;
-mod trainsim;
-mod utils;
-mod worker;
+use std::path::Path;
 
 use eframe::{run_native, NativeOptions};
-use trainsim::TrainSim;
+use trainsim::api;
+use trainsim::batch;
+use trainsim::dcc;
+use trainsim::diagram;
+use trainsim::interfaces::Interfaces;
+use trainsim::mqtt::MqttConfig;
+use trainsim::railml;
+use trainsim::rest;
+use trainsim::scenario::{load_layout, load_scenario};
+use trainsim::stats;
+use trainsim::trainsim::TrainSim;
+use trainsim::utils::format_time;
+use trainsim::worker::{self, WorkerData};
 
-//use train::*;
+///Default duration of a headless run in seconds
+const HEADLESS_DURATION: f64 = 3600.0;
+
+///Command line options that take a value
+const OPTIONS: [&str; 12] = [
+    "--duration",
+    "--stats",
+    "--dot",
+    "--svg",
+    "--batch",
+    "--output",
+    "--api",
+    "--http",
+    "--dcc",
+    "--mqtt",
+    "--railml",
+    "--export-railml",
+];
+
+///Option
+///The value following a command line option
+fn option(args: &[String], name: &str) -> Option<String> {
+    args.iter()
+        .position(|arg| arg == name)
+        .and_then(|i| args.get(i + 1))
+        .cloned()
+}
 
 //Tokio::main macro translates the main function back to a non-async function
 // .await calls are transformed to block_on to make for easy coding
+//Usage: trainsim [scenario.json] or trainsim --headless scenario.json [--duration seconds] [--stats file]
+//The statistics are written as csv when the file ends in .csv, otherwise as json
+//--dot layout.dot and --svg layout.svg write diagrams of the layout, the svg shows the end of the run
+//A batch of runs is started with trainsim --batch batch.json [--output results.csv]
+//--api address starts the WebSocket API next to the app, --http address the REST endpoints
+//--no-ui runs them without a window, both on their default address when neither is given
+//--dcc dcc.json mirrors the simulation onto a model layout with a DCC++ command station
+//--mqtt host[:port] publishes the state to an MQTT broker and takes commands from it
+//--railml infrastructure.xml [--output layout.json] converts a railML infrastructure to a layout
+//--export-railml layout.json [--output infrastructure.xml] converts a layout to railML
 #[tokio::main]
 async fn main() {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    if let Some(path) = option(&args, "--batch") {
+        run_batch(&path, option(&args, "--output"));
+        return;
+    }
+
+    if let Some(path) = option(&args, "--railml") {
+        import_railml(&path, option(&args, "--output"));
+        return;
+    }
+
+    if let Some(path) = option(&args, "--export-railml") {
+        export_railml(&path, option(&args, "--output"));
+        return;
+    }
+
+    let headless = args.iter().any(|arg| arg == "--headless");
+    let duration = option(&args, "--duration")
+        .and_then(|duration| duration.parse::<f64>().ok())
+        .unwrap_or(HEADLESS_DURATION);
+    let stats = option(&args, "--stats");
+    let no_ui = args.iter().any(|arg| arg == "--no-ui");
+    let mut interfaces = Interfaces {
+        websocket: option(&args, "--api"),
+        http: option(&args, "--http"),
+        dcc: match option(&args, "--dcc") {
+            Some(path) => match dcc::load_config(Path::new(&path)) {
+                Ok(config) => Some(config),
+                Err(error) => {
+                    eprintln!("{}", error);
+                    std::process::exit(1);
+                }
+            },
+            None => None,
+        },
+        mqtt: match option(&args, "--mqtt") {
+            Some(address) => match MqttConfig::parse(&address) {
+                Some(config) => Some(config),
+                None => {
+                    eprintln!("Invalid MQTT broker {}, use host or host:port", address);
+                    std::process::exit(1);
+                }
+            },
+            None => None,
+        },
+    };
+    let scenario = args
+        .iter()
+        .enumerate()
+        .find(|(i, arg)| {
+            !arg.starts_with("--") && (*i == 0 || !OPTIONS.contains(&args[i - 1].as_str()))
+        })
+        .map(|(_, arg)| arg.clone());
+
+    let data = match &scenario {
+        Some(path) => match load_scenario(Path::new(path)) {
+            Ok(data) => {
+                for warning in &data.warnings {
+                    eprintln!("{}", warning);
+                }
+                Some(data)
+            }
+            Err(error) => {
+                eprintln!("{}", error);
+                std::process::exit(1);
+            }
+        },
+        None => None,
+    };
+
+    if let (Some(config), Some(data)) = (&interfaces.dcc, &data) {
+        if let Err(error) = config.validate(&data.train_system) {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    }
+
+    if headless {
+        let mut data = match data {
+            Some(data) => data,
+            None => {
+                eprintln!("A scenario is needed to run headless");
+                std::process::exit(1);
+            }
+        };
+
+        worker::run_headless(&mut data, duration, 1.0);
+        print_summary(&data);
+
+        if let Some(path) = stats {
+            if let Err(error) = export_stats(&data, &path) {
+                eprintln!("Failed to write statistics to {}: {}", path, error);
+                std::process::exit(1);
+            }
+        }
+
+        if let Some(path) = option(&args, "--dot") {
+            write_diagram(&path, &diagram::dot(&data.train_system));
+        }
+        if let Some(path) = option(&args, "--svg") {
+            write_diagram(&path, &diagram::svg(&data));
+        }
+        return;
+    }
+
+    if no_ui {
+        if interfaces.is_empty() {
+            interfaces.websocket = Some(api::DEFAULT_ADDRESS.to_string());
+            interfaces.http = Some(rest::DEFAULT_ADDRESS.to_string());
+        }
+        run_without_ui(data.unwrap_or_default(), &interfaces).await;
+        return;
+    }
+
     //default options for Egui
     let native_options = NativeOptions::default();
 
     //start our Egui window
-    let app = TrainSim::new();
+    let app = match data {
+        Some(data) => TrainSim::with_data(data),
+        None => TrainSim::new(),
+    };
+    let app = app.with_interfaces(interfaces);
 
     //run window
     run_native(Box::new(app), native_options);
 }
+
+///Run Without Ui
+///Runs the worker loop in real time without a window, only the interfaces can see and control it
+async fn run_without_ui(data: WorkerData, interfaces: &Interfaces) {
+    let (transmitter, receiver) = tokio::sync::mpsc::unbounded_channel();
+    let (worker_transmitter, mut updates) = tokio::sync::mpsc::unbounded_channel();
+
+    let publishers = match interfaces.start(transmitter) {
+        Ok(publishers) => publishers,
+        Err(error) => {
+            eprintln!("Failed to start the API on {}", error);
+            std::process::exit(1);
+        }
+    };
+    if let Some(address) = &interfaces.websocket {
+        println!("WebSocket API listening on ws://{}", address);
+    }
+    if let Some(address) = &interfaces.http {
+        println!("REST endpoints listening on http://{}", address);
+    }
+
+    tokio::spawn(async move {
+        while let Some(data) = updates.recv().await {
+            publishers.publish(&data);
+        }
+    });
+
+    if let Err(error) = worker::worker_loop(receiver, worker_transmitter, data).await {
+        eprintln!("{}", error);
+    }
+}
+
+///Run Batch
+///Runs all variants of a batch and prints or writes the results table
+fn run_batch(path: &str, output: Option<String>) {
+    let results = match batch::load_batch(Path::new(path)) {
+        Ok(results) => results,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+
+    let content = match output
+        .as_deref()
+        .and_then(|output| Path::new(output).extension())
+        .and_then(|ext| ext.to_str())
+    {
+        Some("json") => serde_json::to_string_pretty(&results).unwrap(),
+        _ => batch::to_csv(&results),
+    };
+
+    write_output(&content, output, &format!("{} runs", results.len()));
+}
+
+///Import RailML
+///Converts a railML infrastructure to a layout, the elements that were left out are listed
+fn import_railml(path: &str, output: Option<String>) {
+    let import = match railml::load(Path::new(path)) {
+        Ok(import) => import,
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+    for element in &import.unsupported {
+        eprintln!("Unsupported {}", element);
+    }
+
+    write_output(
+        &serde_json::to_string_pretty(&import.layout).unwrap(),
+        output,
+        &format!("{} structures", import.layout.structures.len()),
+    );
+}
+
+///Export RailML
+///Converts a layout to a railML infrastructure, the parts that could not be written are listed
+fn export_railml(path: &str, output: Option<String>) {
+    let system = match load_layout(Path::new(path)) {
+        Ok((system, unsupported)) => {
+            for element in &unsupported {
+                eprintln!("{}: unsupported {}", path, element);
+            }
+            system
+        }
+        Err(error) => {
+            eprintln!("{}", error);
+            std::process::exit(1);
+        }
+    };
+
+    let export = railml::export(&system);
+    for part in &export.unsupported {
+        eprintln!("Unsupported {}", part);
+    }
+
+    write_output(
+        &export.document,
+        output,
+        &format!("{} structures", system.structures.len()),
+    );
+}
+
+///Write Output
+///Writes the content to the output file, or prints it when there is none
+fn write_output(content: &str, output: Option<String>, written: &str) {
+    match output {
+        Some(output) => {
+            if let Err(error) = std::fs::write(&output, content) {
+                eprintln!("Failed to write {}: {}", output, error);
+                std::process::exit(1);
+            }
+            println!("{} written to {}", written, output);
+        }
+        None => print!("{}", content),
+    }
+}
+
+///Print Summary
+///Prints where every train ended up and its timetable with the actual times
+fn print_summary(data: &WorkerData) {
+    println!("Simulation ended at {}", format_time(data.time));
+    println!("Dispatcher: {}", data.strategy.dispatcher().name());
+
+    for train in &data.trains {
+        match train.path.front() {
+            Some(front) => println!(
+                "Train {} on structure {} at {:.0}m, {:.1}m/s",
+                train.train_number, front.structure, train.head_offset, train.speed
+            ),
+            None => println!("Train {} is not placed", train.train_number),
+        }
+
+        for stop in &train.timetable {
+            let time = |time: Option<f64>| time.map_or("--:--:--".to_string(), format_time);
+            println!(
+                "  Structure {}: arrival {} (planned {}), departure {} (planned {})",
+                stop.structure,
+                time(stop.actual_arrival),
+                time(stop.arrival),
+                time(stop.actual_departure),
+                time(stop.departure)
+            );
+        }
+    }
+
+    for trains in &data.deadlocks {
+        println!("Deadlock between trains {:?}", trains);
+    }
+
+    if let Some(error) = data
+        .script
+        .as_ref()
+        .and_then(|script| script.error.as_ref())
+    {
+        println!("Script error: {}", error);
+    }
+
+    let report = stats::report(data);
+    println!("Total delay {:.1} minutes", report.total_delay_minutes);
+    println!(
+        "Energy {:.1} kWh, {:.1} kWh regenerated",
+        report.total_energy_kwh, report.total_regenerated_kwh
+    );
+    for incident in &report.incidents {
+        println!(
+            "ATP intervention at {}: train {} {:?} at {:.1}m/s, {:.1}m/s permitted",
+            format_time(incident.time),
+            incident.train,
+            incident.kind,
+            incident.speed,
+            incident.permitted
+        );
+    }
+    for train in &report.trains {
+        println!(
+            "Train {}: {:.0}m at {:.1}m/s average, {:.0}s at red signals, {:.0}% punctual, {:.2} kWh/km",
+            train.train_number,
+            train.distance,
+            train.average_speed,
+            train.signal_wait,
+            train.punctuality,
+            train.energy_per_km
+        );
+    }
+}
+
+///Write Diagram
+///Writes a diagram of the layout, see diagram
+fn write_diagram(path: &str, content: &str) {
+    if let Err(error) = std::fs::write(path, content) {
+        eprintln!("Failed to write diagram to {}: {}", path, error);
+        std::process::exit(1);
+    }
+}
+
+///Export Stats
+///Writes the report of the run as csv or json, depending on the extension of the file
+fn export_stats(data: &WorkerData, path: &str) -> std::io::Result<()> {
+    let report = stats::report(data);
+
+    let content = match Path::new(path).extension().and_then(|ext| ext.to_str()) {
+        Some("csv") => report.to_csv(),
+        _ => report.to_json()?,
+    };
+
+    std::fs::write(path, content)
+}
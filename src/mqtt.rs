@@ -0,0 +1,258 @@
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+use tokio::sync::mpsc::{self, UnboundedSender};
+
+use crate::scripting;
+use crate::train::train_structs::*;
+use crate::utils::format_time;
+use crate::worker::{Command, WorkerData};
+
+///Port of an MQTT broker when none is given
+pub const DEFAULT_PORT: u16 = 1883;
+
+///Number of requests the client queues while the connection is busy
+const CAPACITY: usize = 1000;
+
+///Time to wait before connecting again after the connection with the broker failed
+const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+///Mqtt Config
+///The broker to connect to and the prefix of all topics
+#[derive(Debug, Clone)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub prefix: String,
+    pub client_id: String,
+}
+
+impl MqttConfig {
+    ///Parse
+    ///A broker written as host or host:port, topics start with trainsim
+    pub fn parse(address: &str) -> Option<MqttConfig> {
+        let (host, port) = match address.rsplit_once(':') {
+            Some((host, port)) => (host, port.parse().ok()?),
+            None => (address, DEFAULT_PORT),
+        };
+        if host.is_empty() {
+            return None;
+        }
+
+        Some(MqttConfig {
+            host: host.to_string(),
+            port,
+            prefix: "trainsim".to_string(),
+            client_id: format!("trainsim-{}", std::process::id()),
+        })
+    }
+
+    ///Topics
+    ///The retained state of the simulation by topic, payloads are plain text except for trains
+    ///prefix/clock holds the time of day, prefix/sections/structure/section is occupied or free
+    ///prefix/signals/idx holds the aspect, prefix/switches/idx the position
+    ///prefix/trains/number holds the telemetry of a train as json, like the state of the WebSocket API
+    pub fn topics(&self, data: &WorkerData) -> BTreeMap<String, String> {
+        let prefix = &self.prefix;
+        let mut topics = BTreeMap::new();
+
+        topics.insert(format!("{}/clock", prefix), format_time(data.time));
+
+        for (idx, structure) in &data.train_system.structures {
+            for (i, section) in structure.sections().iter().enumerate() {
+                let occupancy = if section.active { "occupied" } else { "free" };
+                topics.insert(
+                    format!("{}/sections/{}/{}", prefix, idx, i),
+                    occupancy.to_string(),
+                );
+            }
+
+            if let Some(position) = structure.position() {
+                topics.insert(
+                    format!("{}/switches/{}", prefix, idx),
+                    format!("{:?}", position),
+                );
+            }
+        }
+
+        for signal in data.train_system.signals.values() {
+            topics.insert(
+                format!("{}/signals/{}", prefix, signal.idx),
+                format!("{:?}", signal.aspect),
+            );
+        }
+
+        for train in scripting::state(data).trains {
+            if let Ok(payload) = serde_json::to_string(&train) {
+                topics.insert(format!("{}/trains/{}", prefix, train.number), payload);
+            }
+        }
+
+        topics
+    }
+
+    ///Command
+    ///The command for a message on one of the command topics, None when it isn't one or can't be read
+    ///prefix/switches/idx/set with Left, Right or Straight, prefix/trains/number/speed/set with a speed limit in m/s or none
+    ///prefix/trains/number/hold/set with true or false, prefix/trains/number/destination/set with a structure or none
+    ///prefix/command with any command as json like the WebSocket API, except Quit
+    pub fn command(&self, topic: &str, payload: &[u8]) -> Option<Command> {
+        let payload = std::str::from_utf8(payload).ok()?.trim();
+        let topic = topic.strip_prefix(&self.prefix)?.strip_prefix('/')?;
+        let parts: Vec<&str> = topic.split('/').collect();
+
+        match parts.as_slice() {
+            ["command"] => match serde_json::from_str(payload).ok()? {
+                Command::Quit => None,
+                command => Some(command),
+            },
+            ["switches", idx, "set"] => {
+                let position = match payload {
+                    "Left" | "left" => SwitchPosition::Left,
+                    "Right" | "right" => SwitchPosition::Right,
+                    "Straight" | "straight" => SwitchPosition::Straight,
+                    _ => return None,
+                };
+                Some(Command::ThrowSwitch(idx.parse().ok()?, position))
+            }
+            ["trains", number, "speed", "set"] => {
+                Some(Command::SetSpeed(number.parse().ok()?, optional(payload)?))
+            }
+            ["trains", number, "hold", "set"] => Some(Command::HoldTrain(
+                number.parse().ok()?,
+                payload.parse().ok()?,
+            )),
+            ["trains", number, "destination", "set"] => Some(Command::SetDestination(
+                number.parse().ok()?,
+                optional(payload)?,
+            )),
+            _ => None,
+        }
+    }
+}
+
+///Optional
+///A value that can be removed with an empty payload, none or null
+fn optional<T: std::str::FromStr>(payload: &str) -> Option<Option<T>> {
+    match payload {
+        "" | "none" | "null" => Some(None),
+        value => value.parse().ok().map(Some),
+    }
+}
+
+///Mqtt
+///Handle to a running connection with a broker, publish sends the topics that changed
+#[derive(Debug, Clone)]
+pub struct Mqtt {
+    config: Arc<MqttConfig>,
+    topics: UnboundedSender<BTreeMap<String, String>>,
+}
+
+impl Mqtt {
+    ///Publish
+    ///Passes the topics for the data to the connection, only changed topics are published
+    pub fn publish(&self, data: &WorkerData) {
+        let _ = self.topics.send(self.config.topics(data));
+    }
+}
+
+///Start
+///Connects to the broker in the background, subscribes to the command topics and sends their commands
+///to the worker loop. A lost connection is made again, every topic is published again after that
+pub fn start(config: MqttConfig, commands: UnboundedSender<Command>) -> Mqtt {
+    let config = Arc::new(config);
+    let (transmitter, mut receiver) = mpsc::unbounded_channel::<BTreeMap<String, String>>();
+
+    let mut options = MqttOptions::new(&config.client_id, &config.host, config.port);
+    options.set_keep_alive(Duration::from_secs(30));
+    let (client, mut eventloop) = AsyncClient::new(options, CAPACITY);
+    let reconnected = Arc::new(AtomicBool::new(false));
+
+    //The event loop has to be polled to keep the connection going, incoming messages are commands
+    let subscriber = client.clone();
+    let connected = reconnected.clone();
+    let command_config = config.clone();
+    tokio::spawn(async move {
+        let mut failed = false;
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                    failed = false;
+                    let filters = [
+                        format!("{}/command", command_config.prefix),
+                        format!("{}/+/+/set", command_config.prefix),
+                        format!("{}/+/+/+/set", command_config.prefix),
+                    ];
+                    //The event loop isn't polled while we wait, so the requests must not block
+                    for filter in filters {
+                        let _ = subscriber.try_subscribe(filter, QoS::AtLeastOnce);
+                    }
+                    connected.store(true, Ordering::Relaxed);
+                }
+                Ok(Event::Incoming(Packet::Publish(message))) => {
+                    if let Some(command) = command_config.command(&message.topic, &message.payload)
+                    {
+                        if commands.send(command).is_err() {
+                            return;
+                        }
+                    }
+                }
+                Ok(_) => {}
+                Err(error) => {
+                    if !failed {
+                        eprintln!("Connection with the MQTT broker failed: {}", error);
+                        failed = true;
+                    }
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            }
+        }
+    });
+
+    //Topics are retained so a display that connects later gets the current state right away
+    //A topic that doesn't fit in the queue, e.g. while the broker is away, is tried again with the next update
+    //The topic of a train or section that is gone is cleared with an empty retained payload
+    //After a reconnect the payloads the broker keeps are unknown, so every topic is published again
+    tokio::spawn(async move {
+        let mut sent: BTreeMap<String, Option<String>> = BTreeMap::new();
+        while let Some(topics) = receiver.recv().await {
+            if reconnected.swap(false, Ordering::Relaxed) {
+                sent.values_mut().for_each(|payload| *payload = None);
+            }
+
+            let gone: Vec<String> = sent
+                .keys()
+                .filter(|topic| !topics.contains_key(*topic))
+                .cloned()
+                .collect();
+            for topic in gone {
+                if client
+                    .try_publish(topic.clone(), QoS::AtMostOnce, true, "")
+                    .is_ok()
+                {
+                    sent.remove(&topic);
+                }
+            }
+
+            for (topic, payload) in topics {
+                if sent.get(&topic) == Some(&Some(payload.clone())) {
+                    continue;
+                }
+                if client
+                    .try_publish(topic.clone(), QoS::AtMostOnce, true, payload.clone())
+                    .is_ok()
+                {
+                    sent.insert(topic, Some(payload));
+                }
+            }
+        }
+    });
+
+    Mqtt {
+        config,
+        topics: transmitter,
+    }
+}
@@ -0,0 +1,731 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt::Write;
+use std::path::Path;
+
+use roxmltree::{Document, Node};
+
+use crate::scenario::{Layout, ScenarioError, StructureDefinition};
+use crate::train::train_structs::*;
+
+///Namespace of the railML 2 documents we write
+const NAMESPACE: &str = "https://www.railml.org/schemas/2013";
+
+///Import
+///A railML infrastructure converted to a layout and the train system built from it
+///Unsupported are the elements that were left out, e.g. "crossing cr1 on track t2"
+#[derive(Debug, Clone)]
+pub struct Import {
+    pub layout: Layout,
+    pub system: TrainSystem,
+    pub unsupported: Vec<String>,
+}
+
+///Export
+///A train system written as a railML document, unsupported are the parts that could not be written
+#[derive(Debug, Clone)]
+pub struct Export {
+    pub document: String,
+    pub unsupported: Vec<String>,
+}
+
+///Load
+///Reads a railML file and imports its infrastructure
+pub fn load(path: &Path) -> Result<Import, ScenarioError> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|error| ScenarioError::Io(path.to_path_buf(), error))?;
+    import(&source)
+}
+
+///Import
+///Reads the railML 2 infrastructure subset of tracks with their begin, end and switches, connections and signals
+///Every part of a track between its begin, its switches and its end becomes a track of that length in meters
+///A switch becomes a switch structure, its connection is the branch and the course tells if that is left or right
+///An outgoing switch has its input towards the begin of the track, an incoming switch towards the end
+///A signal is placed at the nearest pin ahead of it in its direction, up is towards the end of the track
+///Gradient and radius changes become the profile of the tracks, a radius of 0 is straight
+pub fn import(source: &str) -> Result<Import, ScenarioError> {
+    let document = Document::parse(source).map_err(|error| railml(error.to_string()))?;
+    let infrastructure = document
+        .descendants()
+        .find(|node| is(node, "infrastructure"))
+        .ok_or_else(|| railml("no infrastructure element".to_string()))?;
+
+    let mut importer = Importer::default();
+    for child in elements(infrastructure) {
+        if is(&child, "tracks") {
+            for track in elements(child) {
+                match is(&track, "track") {
+                    true => importer.track(track)?,
+                    false => importer.unsupported(&track, None),
+                }
+            }
+        } else {
+            importer.unsupported(&child, None);
+        }
+    }
+    importer.connect()?;
+
+    let system = importer.layout.build()?;
+    Ok(Import {
+        layout: importer.layout,
+        system,
+        unsupported: importer.unsupported,
+    })
+}
+
+///Point
+///A place on a railML track where one of our structures ends, before and after are the keys of the pins
+///on the side towards the begin and towards the end of the track
+struct Point {
+    pos: f64,
+    before: String,
+    after: String,
+}
+
+///Importer
+///Links are the keys that are joined together, a key is a pin like "3:input", a railML connection like "ref:c1"
+///or an open end
+#[derive(Default)]
+struct Importer {
+    layout: Layout,
+    links: Vec<(String, String)>,
+    connections: HashSet<String>,
+    references: Vec<(String, String)>,
+    unsupported: Vec<String>,
+    open_ends: usize,
+}
+
+impl Importer {
+    ///Track
+    ///Splits the track at its switches and adds the parts, the switches and the signals to the layout
+    ///Exits are the pins a train leaves a structure through with their place on the track and whether it travels up
+    fn track(&mut self, track: Node) -> Result<(), ScenarioError> {
+        let id = track.attribute("id").unwrap_or("?").to_string();
+        let topology = match child(track, "trackTopology") {
+            Some(topology) => topology,
+            None => {
+                self.unsupported
+                    .push(format!("track {} without topology", id));
+                return Ok(());
+            }
+        };
+
+        let begin = child(topology, "trackBegin")
+            .ok_or_else(|| railml(format!("track {} has no trackBegin", id)))?;
+        let end = child(topology, "trackEnd")
+            .ok_or_else(|| railml(format!("track {} has no trackEnd", id)))?;
+        let begin_pos = pos(begin)?;
+        let end_pos = pos(end)?;
+
+        let key = self.end(begin, &id);
+        let mut points = vec![Point {
+            pos: begin_pos,
+            before: key.clone(),
+            after: key,
+        }];
+        let mut exits: Vec<(f64, String, bool)> = vec![];
+        let mut switch_exits = vec![];
+
+        for element in elements(topology) {
+            match element.tag_name().name() {
+                "trackBegin" | "trackEnd" | "mileageChanges" => {}
+                "connections" => {
+                    for connection in elements(element) {
+                        match is(&connection, "switch") {
+                            true => {
+                                let point = self.switch(connection, &id, &mut switch_exits)?;
+                                if point.pos < begin_pos || point.pos > end_pos {
+                                    return Err(railml(format!(
+                                        "switch {} lies outside of track {}",
+                                        connection.attribute("id").unwrap_or("?"),
+                                        id
+                                    )));
+                                }
+                                points.push(point);
+                            }
+                            false => self.unsupported(&connection, Some(&id)),
+                        }
+                    }
+                }
+                _ => self.unsupported(&element, Some(&id)),
+            }
+        }
+
+        let key = self.end(end, &id);
+        points.push(Point {
+            pos: end_pos,
+            before: key.clone(),
+            after: key,
+        });
+        points[1..].sort_by(|a, b| a.pos.total_cmp(&b.pos));
+
+        let (gradients, radii) = match child(track, "trackElements") {
+            Some(elements) => (
+                changes(elements, "gradientChanges", "slope")?,
+                changes(elements, "radiusChanges", "radius")?,
+            ),
+            None => (vec![], vec![]),
+        };
+
+        //Parts without length join the pins on both sides directly
+        for pair in points.windows(2) {
+            let length = (pair[1].pos - pair[0].pos).round() as usize;
+            if length == 0 {
+                self.link(&pair[0].after, &pair[1].before);
+                continue;
+            }
+
+            let idx = self.layout.structures.len();
+            self.layout.structures.push(StructureDefinition::Track {
+                length,
+                sections: None,
+                profile: profile(&gradients, &radii, pair[0].pos, pair[1].pos),
+            });
+            let input = format!("{}:input", idx);
+            let output = format!("{}:output", idx);
+            self.link(&pair[0].after, &input);
+            self.link(&pair[1].before, &output);
+            exits.push((pair[0].pos, input, false));
+            exits.push((pair[1].pos, output, true));
+        }
+        //At the place of a switch a train reaches the end of the track part before the switch itself
+        exits.extend(switch_exits);
+
+        for element in elements(track) {
+            match element.tag_name().name() {
+                "trackTopology" => {}
+                "ocsElements" => {
+                    for group in elements(element) {
+                        match is(&group, "signals") {
+                            true => {
+                                for signal in elements(group) {
+                                    self.signal(signal, &id, &exits)?;
+                                }
+                            }
+                            false => self.unsupported(&group, Some(&id)),
+                        }
+                    }
+                }
+                "trackElements" => {
+                    for group in elements(element) {
+                        if !is(&group, "gradientChanges") && !is(&group, "radiusChanges") {
+                            self.unsupported(&group, Some(&id));
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    ///End
+    ///The key of the begin or end of a track, its connection or an open end for a buffer stop or open end
+    fn end(&mut self, end: Node, track: &str) -> String {
+        for element in elements(end) {
+            match element.tag_name().name() {
+                "connection" => return self.connection(element),
+                "bufferStop" | "openEnd" => {}
+                _ => self.unsupported(&element, Some(track)),
+            }
+        }
+        self.open_end()
+    }
+
+    ///Switch
+    ///Adds a switch structure, the point joins the input and the continuing output into the track
+    fn switch(
+        &mut self,
+        switch: Node,
+        track: &str,
+        exits: &mut Vec<(f64, String, bool)>,
+    ) -> Result<Point, ScenarioError> {
+        let id = switch.attribute("id").unwrap_or("?");
+        let switch_pos = pos(switch)?;
+        let mut connections = elements(switch).filter(|element| is(element, "connection"));
+        let connection = connections
+            .next()
+            .ok_or_else(|| railml(format!("switch {} has no connection", id)))?;
+        if connections.next().is_some() {
+            self.unsupported.push(format!(
+                "connections of switch {} on track {} besides the first",
+                id, track
+            ));
+        }
+
+        let branch_left = match connection.attribute("course") {
+            Some("left") => true,
+            Some("right") => false,
+            _ => {
+                self.unsupported.push(format!(
+                    "course of switch {} on track {}, its branch is taken as left",
+                    id, track
+                ));
+                true
+            }
+        };
+        let switch_type = match branch_left {
+            true => SwitchType::LeftSplitUp,
+            false => SwitchType::RightSplitDown,
+        };
+
+        let idx = self.layout.structures.len();
+        self.layout.structures.push(StructureDefinition::Switch {
+            switch_type,
+            sections: None,
+        });
+        let input = format!("{}:input", idx);
+        let (branch, continuing) = match branch_left {
+            true => (format!("{}:left", idx), format!("{}:right", idx)),
+            false => (format!("{}:right", idx), format!("{}:left", idx)),
+        };
+
+        let key = self.connection(connection);
+        self.link(&key, &branch);
+
+        let outgoing = connection.attribute("orientation") != Some("incoming");
+        exits.push((switch_pos, input.clone(), !outgoing));
+        exits.push((switch_pos, continuing.clone(), outgoing));
+
+        Ok(match outgoing {
+            true => Point {
+                pos: switch_pos,
+                before: input,
+                after: continuing,
+            },
+            false => Point {
+                pos: switch_pos,
+                before: continuing,
+                after: input,
+            },
+        })
+    }
+
+    ///Signal
+    ///Places a signal at the first pin a train travelling in its direction reaches from its position
+    ///A signal of type shunting becomes a shunt signal
+    fn signal(
+        &mut self,
+        signal: Node,
+        track: &str,
+        exits: &[(f64, String, bool)],
+    ) -> Result<(), ScenarioError> {
+        let id = signal.attribute("id").unwrap_or("?");
+        let signal_pos = pos(signal)?;
+
+        let pin = match signal.attribute("dir") {
+            Some("up") => exits
+                .iter()
+                .filter(|(pos, _, up)| *up && *pos >= signal_pos)
+                .min_by(|a, b| a.0.total_cmp(&b.0)),
+            Some("down") => exits
+                .iter()
+                .filter(|(pos, _, up)| !*up && *pos <= signal_pos)
+                .min_by(|a, b| b.0.total_cmp(&a.0)),
+            _ => {
+                self.unsupported.push(format!(
+                    "signal {} on track {} without direction up or down",
+                    id, track
+                ));
+                return Ok(());
+            }
+        };
+
+        match pin {
+            Some((_, pin, _)) if signal.attribute("type") == Some("shunting") => {
+                self.layout.shunt_signals.push(pin.clone())
+            }
+            Some((_, pin, _)) => self.layout.signals.push(pin.clone()),
+            None => self.unsupported.push(format!(
+                "signal {} on track {} without a pin ahead of it",
+                id, track
+            )),
+        }
+        Ok(())
+    }
+
+    ///Connection
+    ///The key of a connection element, it is linked to the connection it refers to
+    fn connection(&mut self, connection: Node) -> String {
+        let key = format!("ref:{}", connection.attribute("id").unwrap_or_default());
+        self.connections.insert(key.clone());
+
+        if let Some(reference) = connection.attribute("ref") {
+            let reference = format!("ref:{}", reference);
+            self.references.push((key.clone(), reference.clone()));
+            self.link(&key, &reference);
+        }
+        key
+    }
+
+    fn open_end(&mut self) -> String {
+        self.open_ends += 1;
+        format!("end:{}", self.open_ends)
+    }
+
+    fn link(&mut self, a: &str, b: &str) {
+        self.links.push((a.to_string(), b.to_string()));
+    }
+
+    fn unsupported(&mut self, element: &Node, track: Option<&str>) {
+        let name = element.tag_name().name();
+        self.unsupported
+            .push(match (element.attribute("id"), track) {
+                (Some(id), Some(track)) => format!("{} {} on track {}", name, id, track),
+                (None, Some(track)) => format!("{} on track {}", name, track),
+                (Some(id), None) => format!("{} {}", name, id),
+                (None, None) => name.to_string(),
+            });
+    }
+
+    ///Connect
+    ///Follows the links from every pin to the pin on the other side and adds the connections to the layout
+    ///Pins without a pin on the other side are open ends
+    fn connect(&mut self) -> Result<(), ScenarioError> {
+        for (key, reference) in &self.references {
+            if !self.connections.contains(reference) {
+                self.unsupported.push(format!(
+                    "connection {} refers to unknown connection {}",
+                    &key[4..],
+                    &reference[4..]
+                ));
+            }
+        }
+
+        let mut neighbours: HashMap<&str, Vec<&str>> = HashMap::new();
+        let mut keys = vec![];
+        for (a, b) in &self.links {
+            for (key, other) in [(a, b), (b, a)] {
+                let entry = neighbours.entry(key).or_default();
+                if entry.is_empty() {
+                    keys.push(key.as_str());
+                }
+                entry.push(other);
+            }
+        }
+
+        let mut visited = HashSet::new();
+        for key in keys {
+            if !visited.insert(key) {
+                continue;
+            }
+
+            let mut pins = vec![];
+            let mut queue = VecDeque::from([key]);
+            while let Some(key) = queue.pop_front() {
+                if !key.starts_with("ref:") && !key.starts_with("end:") {
+                    pins.push(key.to_string());
+                }
+                for other in &neighbours[key] {
+                    if visited.insert(*other) {
+                        queue.push_back(*other);
+                    }
+                }
+            }
+
+            match pins.as_slice() {
+                [a, b] => self.layout.connections.push((a.clone(), b.clone())),
+                [_] => {}
+                _ => {
+                    return Err(railml(format!(
+                        "the pins {} are all connected to each other",
+                        pins.join(", ")
+                    )))
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+///Export
+///Writes the tracks, switches, connections and signals of a train system as a railML 2 infrastructure
+///A track becomes a railML track from 0 to its length with gradient and radius changes for its profile, a switch a track without length with an outgoing switch
+///at its begin, the continuing output is the end of the track. Sections are not written
+///Three way switches, crossings, slip switches and tables are reported as unsupported, their neighbours end in buffer stops
+pub fn export(system: &TrainSystem) -> Export {
+    let mut unsupported = vec![];
+    let mut idxs: Vec<&usize> = system.structures.keys().collect();
+    idxs.sort();
+
+    let mut tracks = String::new();
+    for idx in idxs {
+        //Where the pins of the structure lie on its railML track and the direction trains leave through them
+        let (length, begin, end, switch) = match &system.structures[idx] {
+            StructureTypes::Track(track) => (
+                track.track_length,
+                track.input_pin,
+                track.output_pin,
+                String::new(),
+            ),
+            StructureTypes::Switch(switch) => {
+                let (course, branch, continuing) = match switch.switch_type {
+                    SwitchType::LeftSplitUp | SwitchType::LeftSplitDown => {
+                        ("left", switch.left_output_pin, switch.right_output_pin)
+                    }
+                    SwitchType::RightSplitUp | SwitchType::RightSplitDown => {
+                        ("right", switch.right_output_pin, switch.left_output_pin)
+                    }
+                };
+                //The branch always gets a connection, without ref when nothing is connected to it
+                let reference = system
+                    .connections
+                    .get(&branch)
+                    .filter(|other| exported(system, **other))
+                    .map(|other| format!(" ref=\"c{}\"", other))
+                    .unwrap_or_default();
+                let connection = format!(
+                    "<connection id=\"c{}\"{} orientation=\"outgoing\" course=\"{}\"/>",
+                    branch, reference, course
+                );
+                let element = format!(
+                    "          <connections>\n            <switch id=\"sw{}\" pos=\"0\">\n              {}\n            </switch>\n          </connections>\n",
+                    idx, connection
+                );
+
+                if let Some(signal) = system.signal_at(branch) {
+                    unsupported.push(format!(
+                        "signal {} at the branch of switch {}",
+                        signal.idx, idx
+                    ));
+                }
+                (0, switch.input_pin, continuing, element)
+            }
+            StructureTypes::ThreeWay(_)
+            | StructureTypes::Crossing(_)
+            | StructureTypes::Slip(_)
+            | StructureTypes::Table(_) => {
+                unsupported.push(format!("{} {}", kind(&system.structures[idx]), idx));
+                continue;
+            }
+        };
+
+        let mut signals: Vec<&Signal> = system
+            .signals
+            .values()
+            .filter(|signal| signal.pin == begin || signal.pin == end)
+            .collect();
+        signals.sort_by_key(|signal| signal.idx);
+
+        let _ = writeln!(tracks, "      <track id=\"t{}\">", idx);
+        let _ = writeln!(tracks, "        <trackTopology>");
+        let _ = writeln!(
+            tracks,
+            "          <trackBegin id=\"t{}_begin\" pos=\"0\">\n            {}\n          </trackBegin>",
+            idx,
+            end_element(system, begin)
+        );
+        let _ = writeln!(
+            tracks,
+            "          <trackEnd id=\"t{}_end\" pos=\"{}\">\n            {}\n          </trackEnd>",
+            idx,
+            length,
+            end_element(system, end)
+        );
+        tracks.push_str(&switch);
+        let _ = writeln!(tracks, "        </trackTopology>");
+        let profile = system.structures[idx].profile();
+        if !profile.is_empty() {
+            let _ = writeln!(
+                tracks,
+                "        <trackElements>\n          <gradientChanges>"
+            );
+            for (i, segment) in profile.iter().enumerate() {
+                let _ = writeln!(
+                    tracks,
+                    "            <gradientChange id=\"t{}_g{}\" pos=\"{}\" slope=\"{}\"/>",
+                    idx, i, segment.start, segment.gradient
+                );
+            }
+            let _ = writeln!(
+                tracks,
+                "          </gradientChanges>\n          <radiusChanges>"
+            );
+            for (i, segment) in profile.iter().enumerate() {
+                let _ = writeln!(
+                    tracks,
+                    "            <radiusChange id=\"t{}_r{}\" pos=\"{}\" radius=\"{}\"/>",
+                    idx,
+                    i,
+                    segment.start,
+                    segment.radius.unwrap_or(0.0)
+                );
+            }
+            let _ = writeln!(
+                tracks,
+                "          </radiusChanges>\n        </trackElements>"
+            );
+        }
+        if !signals.is_empty() {
+            let _ = writeln!(tracks, "        <ocsElements>\n          <signals>");
+            for signal in signals {
+                let (pos, dir) = match signal.pin == end {
+                    true => (length, "up"),
+                    false => (0, "down"),
+                };
+                let kind = match signal.kind {
+                    SignalKind::Main => "",
+                    SignalKind::Shunt => " type=\"shunting\"",
+                };
+                let _ = writeln!(
+                    tracks,
+                    "            <signal id=\"sig{}\" pos=\"{}\" dir=\"{}\"{}/>",
+                    signal.idx, pos, dir, kind
+                );
+            }
+            let _ = writeln!(tracks, "          </signals>\n        </ocsElements>");
+        }
+        let _ = writeln!(tracks, "      </track>");
+    }
+
+    let document = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<railml xmlns=\"{}\" version=\"2.2\">\n  <infrastructure id=\"trainsim\">\n    <tracks>\n{}    </tracks>\n  </infrastructure>\n</railml>\n",
+        NAMESPACE, tracks
+    );
+
+    Export {
+        document,
+        unsupported,
+    }
+}
+
+///Changes
+///The place and value of every change in a group of track elements, e.g. the slope of the gradient changes
+fn changes(elements: Node, group: &str, value: &str) -> Result<Vec<(f64, f64)>, ScenarioError> {
+    let mut changes = vec![];
+
+    for change in child(elements, group).into_iter().flat_map(self::elements) {
+        let number = change
+            .attribute(value)
+            .and_then(|number| number.parse::<f64>().ok())
+            .ok_or_else(|| {
+                railml(format!(
+                    "{} {} has no valid {}",
+                    change.tag_name().name(),
+                    change.attribute("id").unwrap_or("?"),
+                    value
+                ))
+            })?;
+        changes.push((pos(change)?, number));
+    }
+
+    changes.sort_by(|a, b| a.0.total_cmp(&b.0));
+    Ok(changes)
+}
+
+///Profile
+///The profile of the part of a track from one place to another, from the gradient and radius changes of the track
+///A part that is level and straight throughout has no profile
+fn profile(
+    gradients: &[(f64, f64)],
+    radii: &[(f64, f64)],
+    from: f64,
+    to: f64,
+) -> Vec<ProfileSegment> {
+    let at = |changes: &[(f64, f64)], place: f64| {
+        changes
+            .iter()
+            .rev()
+            .find(|(pos, _)| *pos <= place)
+            .map_or(0.0, |(_, value)| *value)
+    };
+
+    let mut places: Vec<f64> = gradients
+        .iter()
+        .chain(radii)
+        .map(|(pos, _)| *pos)
+        .filter(|pos| *pos > from && *pos < to)
+        .collect();
+    places.insert(0, from);
+    places.sort_by(|a, b| a.total_cmp(b));
+    places.dedup();
+
+    let profile: Vec<ProfileSegment> = places
+        .into_iter()
+        .map(|place| ProfileSegment {
+            start: place - from,
+            gradient: at(gradients, place),
+            radius: Some(at(radii, place).abs()).filter(|radius| *radius > 0.0),
+        })
+        .collect();
+
+    match profile
+        .iter()
+        .all(|segment| segment.gradient == 0.0 && segment.radius.is_none())
+    {
+        true => vec![],
+        false => profile,
+    }
+}
+
+///End Element
+///A connection to the pin on the other side, or a buffer stop when that pin is not connected or not exported
+fn end_element(system: &TrainSystem, pin: usize) -> String {
+    match system
+        .connections
+        .get(&pin)
+        .filter(|other| exported(system, **other))
+    {
+        Some(other) => format!("<connection id=\"c{}\" ref=\"c{}\"/>", pin, other),
+        None => format!("<bufferStop id=\"b{}\"/>", pin),
+    }
+}
+
+///Exported
+///Only tracks and switches are written, the pins of other structures can't be referred to
+fn exported(system: &TrainSystem, pin: usize) -> bool {
+    system
+        .pins
+        .get(&pin)
+        .and_then(|idx| system.structures.get(idx))
+        .is_some_and(|structure| {
+            matches!(
+                structure,
+                StructureTypes::Track(_) | StructureTypes::Switch(_)
+            )
+        })
+}
+
+fn kind(structure: &StructureTypes) -> &'static str {
+    match structure {
+        StructureTypes::Track(_) => "track",
+        StructureTypes::Switch(_) => "switch",
+        StructureTypes::ThreeWay(_) => "three way switch",
+        StructureTypes::Crossing(_) => "crossing",
+        StructureTypes::Slip(_) => "slip switch",
+        StructureTypes::Table(table) => match table.table_type {
+            TableType::Turntable => "turntable",
+            TableType::TransferTable => "transfer table",
+        },
+    }
+}
+
+fn elements<'a, 'input>(node: Node<'a, 'input>) -> impl Iterator<Item = Node<'a, 'input>> {
+    node.children().filter(|child| child.is_element())
+}
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    elements(node).find(|child| is(child, name))
+}
+
+fn is(node: &Node, name: &str) -> bool {
+    node.is_element() && node.tag_name().name() == name
+}
+
+fn pos(node: Node) -> Result<f64, ScenarioError> {
+    node.attribute("pos")
+        .and_then(|pos| pos.parse::<f64>().ok())
+        .ok_or_else(|| {
+            railml(format!(
+                "{} {} has no valid pos",
+                node.tag_name().name(),
+                node.attribute("id").unwrap_or("?")
+            ))
+        })
+}
+
+fn railml(message: String) -> ScenarioError {
+    ScenarioError::Railml(message)
+}
@@ -0,0 +1,435 @@
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::timeout;
+
+use crate::api::{self, Api};
+use crate::scripting;
+use crate::train::train_structs::*;
+use crate::utils::format_time;
+use crate::worker::{Command, WorkerData};
+
+///Address the REST endpoints listen on when none is given, only reachable from this machine
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:9002";
+
+///Largest request body we accept in bytes, control requests are tiny
+const MAX_BODY: usize = 64 * 1024;
+
+///Largest number of header lines we read before giving up on a request
+pub const MAX_HEADERS: usize = 100;
+
+///Longest request line or header line we read in bytes, a longer line is not buffered
+pub const MAX_LINE: usize = 8 * 1024;
+
+///Longest we wait for the next part of a request, a client that stalls is answered with 408
+pub const READ_TIMEOUT: Duration = Duration::from_secs(10);
+
+///Request
+///The parts of an HTTP request the endpoints look at
+#[derive(Debug, Clone, Default)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: Vec<(String, String)>,
+    pub body: String,
+}
+
+///Response
+///Status code and json body
+#[derive(Debug, Clone)]
+pub struct Response {
+    pub status: u16,
+    pub body: String,
+}
+
+///Clock
+///Simulation time in seconds since midnight and as a time of day
+#[derive(Debug, Clone, Serialize)]
+pub struct Clock {
+    pub time: f64,
+    pub time_of_day: String,
+    pub count: usize,
+}
+
+///Switch Info
+///A switch with its position and faults, reserved by is the train it is part of the route of
+///Kind is switch, three_way or slip, the switch type is only set for a switch
+#[derive(Debug, Clone, Serialize)]
+pub struct SwitchInfo {
+    pub idx: usize,
+    pub kind: String,
+    pub switch_type: Option<SwitchType>,
+    pub position: SwitchPosition,
+    pub reserved_by: Option<i32>,
+    pub stuck: bool,
+    pub detection_lost: bool,
+}
+
+///Section Info
+///A section with its place on the structure in meters from the input pin
+///The train number is 0 when no train is on the section
+#[derive(Debug, Clone, Serialize)]
+pub struct SectionInfo {
+    pub structure: usize,
+    pub section: usize,
+    pub start: usize,
+    pub end: usize,
+    pub detection: Detection,
+    pub active: bool,
+    pub train: i32,
+}
+
+///Throw Request
+///Body of a request to throw a switch
+#[derive(Debug, Clone, Deserialize)]
+pub struct ThrowRequest {
+    pub position: SwitchPosition,
+}
+
+///Speed Request
+///Body of a request to set the speed limit of a train in m/s, null removes it
+#[derive(Debug, Clone, Deserialize)]
+pub struct SpeedRequest {
+    pub speed: Option<f64>,
+}
+
+///Listen
+///Binds the address and serves HTTP requests in the background
+pub fn listen(address: &str, api: &Api) -> std::io::Result<()> {
+    let listener = api::bind(address)?;
+    tokio::spawn(serve(listener, api.clone()));
+    Ok(())
+}
+
+///Serve
+///Accepts connections until the listener fails, every connection gets its own task
+pub async fn serve(listener: TcpListener, api: Api) -> std::io::Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        tokio::spawn(handle_connection(stream, api.clone()));
+    }
+}
+
+///Handle Connection
+///Answers a single request and closes the connection
+async fn handle_connection(stream: TcpStream, api: Api) {
+    let mut stream = BufReader::new(stream);
+
+    let response = match read_request(&mut stream).await {
+        Ok(request) => handle(&api, &request),
+        Err(response) => response,
+    };
+
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        reason(response.status),
+        response.body.len()
+    );
+    let stream = stream.get_mut();
+    let _ = stream.write_all(head.as_bytes()).await;
+    let _ = stream.write_all(response.body.as_bytes()).await;
+    let _ = stream.shutdown().await;
+}
+
+///Read Request
+///Reads the request line, the headers and a body with a content length
+///A request that isn't valid HTTP is answered with 400, a client that stalls with 408, a body that is too large
+///with 413, a request line that is too long with 414 and too many or too long headers with 431
+async fn read_request(stream: &mut BufReader<TcpStream>) -> Result<Request, Response> {
+    let bad_request = || error(400, "Bad request");
+    let too_large = || error(431, "Request header fields too large");
+
+    let mut line = String::new();
+    read_line(stream, &mut line, error(414, "URI too long")).await?;
+
+    let mut parts = line.split_whitespace();
+    let method = parts.next().ok_or_else(bad_request)?.to_string();
+    let target = parts.next().ok_or_else(bad_request)?.to_string();
+
+    let mut length = 0;
+    for _ in 0..MAX_HEADERS {
+        read_line(stream, &mut line, too_large()).await?;
+
+        let header = line.trim();
+        if header.is_empty() {
+            let mut body = vec![0; length];
+            match timeout(READ_TIMEOUT, stream.read_exact(&mut body)).await {
+                Ok(Ok(_)) => (),
+                Ok(Err(_)) => return Err(bad_request()),
+                Err(_) => return Err(timed_out()),
+            }
+
+            let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+            return Ok(Request {
+                method,
+                path: path.to_string(),
+                query: query
+                    .split('&')
+                    .filter_map(|pair| pair.split_once('='))
+                    .map(|(key, value)| (decode(key), decode(value)))
+                    .collect(),
+                body: String::from_utf8(body).map_err(|_| bad_request())?,
+            });
+        }
+
+        if let Some((name, value)) = header.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("content-length") {
+                length = value.trim().parse().map_err(|_| bad_request())?;
+                if length > MAX_BODY {
+                    return Err(error(413, "Payload too large"));
+                }
+            }
+        }
+    }
+
+    Err(too_large())
+}
+
+///Read Line
+///Reads a line of at most the maximum line length into the buffer, a longer line is answered with the given response
+async fn read_line(
+    stream: &mut BufReader<TcpStream>,
+    line: &mut String,
+    too_long: Response,
+) -> Result<(), Response> {
+    line.clear();
+    let mut limited = (&mut *stream).take(MAX_LINE as u64 + 1);
+
+    match timeout(READ_TIMEOUT, limited.read_line(line)).await {
+        Ok(Ok(_)) if line.len() > MAX_LINE => Err(too_long),
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err(_)) => Err(error(400, "Bad request")),
+        Err(_) => Err(timed_out()),
+    }
+}
+
+///Decode
+///Decodes a percent-encoded query key or value, a plus is a space, an invalid escape is kept as it is
+pub fn decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        let escaped = bytes
+            .get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            }
+            (b'+', _) => {
+                decoded.push(b' ');
+                i += 1;
+            }
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).to_string()
+}
+
+fn timed_out() -> Response {
+    error(408, "Request timeout")
+}
+
+///Handle
+///Routes a request to its endpoint, reading endpoints answer from the data last published by the worker loop
+///GET /clock, /trains, /trains/{number}, /structures, /structures/{idx}, /switches, /sections?occupied=true
+///POST /switches/{idx} with {"position": "Left"} and /trains/{number}/speed with {"speed": 10.0}
+pub fn handle(api: &Api, request: &Request) -> Response {
+    let data = match api.latest() {
+        Some(data) => data,
+        None => return error(503, "The simulation has not started yet"),
+    };
+
+    let segments: Vec<&str> = request
+        .path
+        .split('/')
+        .filter(|segment| !segment.is_empty())
+        .collect();
+
+    match (request.method.as_str(), segments.as_slice()) {
+        ("GET", ["clock"]) => ok(&Clock {
+            time: data.time,
+            time_of_day: format_time(data.time),
+            count: data.count,
+        }),
+        ("GET", ["trains"]) => ok(&scripting::state(&data).trains),
+        ("GET", ["trains", number]) => {
+            let number = number.parse::<i32>().ok();
+            match scripting::state(&data)
+                .trains
+                .into_iter()
+                .find(|train| Some(train.number) == number)
+            {
+                Some(train) => ok(&train),
+                None => error(404, "Unknown train"),
+            }
+        }
+        ("GET", ["structures"]) => ok(&scripting::state(&data).structures),
+        ("GET", ["structures", idx]) => {
+            let idx = idx.parse::<usize>().ok();
+            match scripting::state(&data)
+                .structures
+                .into_iter()
+                .find(|structure| Some(structure.idx) == idx)
+            {
+                Some(structure) => ok(&structure),
+                None => error(404, "Unknown structure"),
+            }
+        }
+        ("GET", ["switches"]) => ok(&switches(&data)),
+        ("GET", ["sections"]) => {
+            let occupied = request
+                .query
+                .iter()
+                .find(|(key, _)| key == "occupied")
+                .map(|(_, value)| value == "true");
+            match occupied {
+                Some(occupied) => ok(&sections(&data)
+                    .into_iter()
+                    .filter(|section| section.active == occupied)
+                    .collect::<Vec<SectionInfo>>()),
+                None => ok(&sections(&data)),
+            }
+        }
+        ("POST", ["switches", idx]) => {
+            let idx = match idx.parse::<usize>() {
+                Ok(idx) if switches(&data).iter().any(|switch| switch.idx == idx) => idx,
+                _ => return error(404, "Unknown switch"),
+            };
+            match serde_json::from_str::<ThrowRequest>(&request.body) {
+                Ok(throw) => command(api, Command::ThrowSwitch(idx, throw.position)),
+                Err(parse_error) => error(400, &format!("Invalid body: {}", parse_error)),
+            }
+        }
+        ("POST", ["trains", number, "speed"]) => {
+            let number = match number.parse::<i32>() {
+                Ok(number) if data.trains.iter().any(|train| train.train_number == number) => {
+                    number
+                }
+                _ => return error(404, "Unknown train"),
+            };
+            match serde_json::from_str::<SpeedRequest>(&request.body) {
+                Ok(speed) => command(api, Command::SetSpeed(number, speed.speed)),
+                Err(parse_error) => error(400, &format!("Invalid body: {}", parse_error)),
+            }
+        }
+        (_, ["clock"] | ["trains", ..] | ["structures", ..] | ["switches", ..] | ["sections"]) => {
+            error(405, "Method not allowed")
+        }
+        _ => error(404, "Not found"),
+    }
+}
+
+///Switches
+///All switches ordered by their index
+fn switches(data: &WorkerData) -> Vec<SwitchInfo> {
+    let system = &data.train_system;
+
+    let mut switches: Vec<SwitchInfo> = system
+        .structures
+        .iter()
+        .filter_map(|(idx, structure)| {
+            let (kind, switch_type) = match structure {
+                StructureTypes::Switch(switch) => ("switch", Some(switch.switch_type)),
+                StructureTypes::ThreeWay(_) => ("three_way", None),
+                StructureTypes::Slip(_) => ("slip", None),
+                _ => return None,
+            };
+            Some(SwitchInfo {
+                idx: *idx,
+                kind: kind.to_string(),
+                switch_type,
+                position: structure.position()?,
+                reserved_by: system.reservations.get(idx).copied(),
+                stuck: structure.is_stuck(),
+                detection_lost: structure.is_detection_lost(),
+            })
+        })
+        .collect();
+    switches.sort_by_key(|switch| switch.idx);
+
+    switches
+}
+
+///Sections
+///All sections ordered by structure and their place on it
+fn sections(data: &WorkerData) -> Vec<SectionInfo> {
+    let mut sections: Vec<SectionInfo> = data
+        .train_system
+        .structures
+        .iter()
+        .flat_map(|(idx, structure)| {
+            structure
+                .sections()
+                .iter()
+                .enumerate()
+                .map(|(i, section)| SectionInfo {
+                    structure: *idx,
+                    section: i,
+                    start: section.distance_start,
+                    end: section.distance_end,
+                    detection: section.detection,
+                    active: section.active,
+                    train: section.train_number,
+                })
+                .collect::<Vec<SectionInfo>>()
+        })
+        .collect();
+    sections.sort_by_key(|section| (section.structure, section.section));
+
+    sections
+}
+
+///Command
+///Passes a control request to the worker loop, it is applied before the next tick
+fn command(api: &Api, command: Command) -> Response {
+    match api.send_command(command.clone()) {
+        true => Response {
+            status: 202,
+            body: serde_json::to_string(&command).unwrap_or_default(),
+        },
+        false => error(503, "The simulation has stopped"),
+    }
+}
+
+fn ok<T: Serialize>(value: &T) -> Response {
+    match serde_json::to_string(value) {
+        Ok(body) => Response { status: 200, body },
+        Err(serialize_error) => error(500, &serialize_error.to_string()),
+    }
+}
+
+fn error(status: u16, message: &str) -> Response {
+    Response {
+        status,
+        body: serde_json::json!({ "error": message }).to_string(),
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        202 => "Accepted",
+        400 => "Bad Request",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        408 => "Request Timeout",
+        413 => "Payload Too Large",
+        414 => "URI Too Long",
+        431 => "Request Header Fields Too Large",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
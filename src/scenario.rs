@@ -0,0 +1,562 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::deadlock;
+use crate::dispatcher::Strategy;
+use crate::railml;
+use crate::scripting;
+use crate::train::train_structs::*;
+use crate::utils::parse_time;
+use crate::worker::{self, Command, ScriptedEvent, WorkerData};
+
+///Scenario
+///Everything needed to start a simulation: the layout, the trains with their timetables and the initial state
+///The layout is either a path to a layout file, relative to the scenario file, or the layout itself
+///Times are written as a time of day like "08:15" or "08:15:30"
+///A script file, relative to the scenario file, can control the simulation, see scripting
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Scenario {
+    pub layout: LayoutSource,
+    #[serde(default = "default_start_time")]
+    pub start_time: String,
+    #[serde(default = "default_auto_dispatch")]
+    pub auto_dispatch: bool,
+    #[serde(default)]
+    pub strategy: Strategy,
+    #[serde(default = "default_lookahead")]
+    pub lookahead: usize,
+    #[serde(default)]
+    pub trains: Vec<TrainDefinition>,
+    #[serde(default)]
+    pub switches: Vec<SwitchState>,
+    #[serde(default)]
+    pub events: Vec<EventDefinition>,
+    #[serde(default)]
+    pub script: Option<String>,
+}
+
+fn default_start_time() -> String {
+    "00:00".to_string()
+}
+
+fn default_auto_dispatch() -> bool {
+    true
+}
+
+fn default_lookahead() -> usize {
+    deadlock::LOOKAHEAD
+}
+
+///Layout Source
+///A layout can be shared by several scenarios through a file, or be written inline
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum LayoutSource {
+    File(String),
+    Inline(Layout),
+}
+
+///Layout
+///Structures get their index from their position in the list, pins are written as "structure:pin"
+///Pin names are input and output for a track, input, left and right for a switch, input, left, straight and right
+///for a three way switch and input, output, cross_input and cross_output for a crossing or slip switch
+///A turntable has an input and output_0, output_1 .. a transfer table input_0, input_1 .. and output_0, output_1 ..
+///E.g. "0:output" is the output pin of the first structure, a signal at a pin faces trains travelling towards it
+///Shunt signals are placed the same way, they only apply to shunting trains
+///Non electrified lists the structures without overhead line
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Layout {
+    pub structures: Vec<StructureDefinition>,
+    #[serde(default)]
+    pub connections: Vec<(String, String)>,
+    #[serde(default)]
+    pub signals: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub shunt_signals: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub non_electrified: Vec<usize>,
+}
+
+///Structure Definition
+///A track with its length in meters, a switch with its type, a three way switch, a crossing,
+///a slip switch with its slip type or a turntable or transfer table with its bridge length and number of tracks
+///Sections are optional, a track can have a profile of gradients and curves
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum StructureDefinition {
+    Track {
+        length: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sections: Option<SectionDefinition>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        profile: Vec<ProfileSegment>,
+    },
+    Switch {
+        switch_type: SwitchType,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sections: Option<SectionDefinition>,
+    },
+    #[serde(rename = "three_way")]
+    ThreeWay {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sections: Option<SectionDefinition>,
+    },
+    Crossing {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sections: Option<SectionDefinition>,
+    },
+    Slip {
+        slip_type: SlipType,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sections: Option<SectionDefinition>,
+    },
+    Turntable {
+        length: usize,
+        tracks: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sections: Option<SectionDefinition>,
+    },
+    #[serde(rename = "transfer_table")]
+    TransferTable {
+        length: usize,
+        tracks: usize,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        sections: Option<SectionDefinition>,
+    },
+}
+
+///Section Definition
+///Either the boundaries of the sections in meters from the input pin, or an amount of seeded random sections
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum SectionDefinition {
+    Boundaries {
+        boundaries: Vec<usize>,
+        #[serde(default = "default_detection")]
+        detection: Detection,
+    },
+    Random {
+        amount: usize,
+        seed: u64,
+        #[serde(default = "default_detection")]
+        detection: Detection,
+    },
+}
+
+fn default_detection() -> Detection {
+    Detection::TrackCircuit
+}
+
+///Train Definition
+///A train with its performance and where it starts, the destination is ignored when it has a timetable
+///A train made of vehicles gives its consist instead, its length and performance follow from it
+///Traction overrides the traction of the train, by default that of its locomotive or electric
+///Driver is how the train is driven, see driver
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TrainDefinition {
+    pub number: i32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub length: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub performance: Option<Performance>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub consist: Vec<Vehicle>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub traction: Option<Traction>,
+    #[serde(default)]
+    pub driver: Driver,
+    pub start: StartPosition,
+    #[serde(default)]
+    pub destination: Option<usize>,
+    #[serde(default)]
+    pub priority: i32,
+    #[serde(default)]
+    pub timetable: Vec<StopDefinition>,
+}
+
+///Start Position
+///The structure the front of the train is on, its direction of travel and the distance from the pin it entered at
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StartPosition {
+    pub structure: usize,
+    #[serde(default = "default_direction")]
+    pub direction: Direction,
+    #[serde(default)]
+    pub offset: f64,
+}
+
+fn default_direction() -> Direction {
+    Direction::Forward
+}
+
+///Stop Definition
+///A timetable stop, without a departure time the stop is the end of the journey
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StopDefinition {
+    pub structure: usize,
+    #[serde(default)]
+    pub arrival: Option<String>,
+    #[serde(default)]
+    pub departure: Option<String>,
+}
+
+///Switch State
+///The position of a switch at the start of the scenario
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwitchState {
+    pub structure: usize,
+    pub position: SwitchPosition,
+}
+
+///Event Definition
+///A command that runs at a time of day
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EventDefinition {
+    pub time: String,
+    pub command: Command,
+}
+
+///Scenario Error
+///Reading a file failed, the json or railML could not be parsed or the content doesn't fit the layout
+#[derive(Debug)]
+pub enum ScenarioError {
+    Io(PathBuf, std::io::Error),
+    Parse(serde_json::Error),
+    Invalid(String),
+    Railml(String),
+}
+
+impl fmt::Display for ScenarioError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ScenarioError::Io(path, error) => {
+                write!(f, "Failed to read {}: {}", path.display(), error)
+            }
+            ScenarioError::Parse(error) => write!(f, "Failed to parse scenario: {}", error),
+            ScenarioError::Invalid(message) => write!(f, "Invalid scenario: {}", message),
+            ScenarioError::Railml(message) => write!(f, "Failed to import railML: {}", message),
+        }
+    }
+}
+
+impl std::error::Error for ScenarioError {}
+
+impl From<serde_json::Error> for ScenarioError {
+    fn from(error: serde_json::Error) -> Self {
+        ScenarioError::Parse(error)
+    }
+}
+
+///Load Scenario
+///Reads a scenario file and builds the data the simulation starts from
+pub fn load_scenario(path: &Path) -> Result<WorkerData, ScenarioError> {
+    let scenario: Scenario = serde_json::from_str(&read_file(path)?)?;
+    let base = path.parent().unwrap_or_else(|| Path::new("."));
+
+    scenario.build(base)
+}
+
+///Load Layout
+///Reads a layout file and builds the train system, with the elements that were left out
+///Files ending in .xml or .railml are imported as railML, see railml, a json layout leaves nothing out
+pub fn load_layout(path: &Path) -> Result<(TrainSystem, Vec<String>), ScenarioError> {
+    if let Some("xml" | "railml") = path.extension().and_then(|ext| ext.to_str()) {
+        let import = railml::load(path)?;
+        return Ok((import.system, import.unsupported));
+    }
+
+    let layout: Layout = serde_json::from_str(&read_file(path)?)?;
+    Ok((layout.build()?, vec![]))
+}
+
+fn read_file(path: &Path) -> Result<String, ScenarioError> {
+    std::fs::read_to_string(path).map_err(|error| ScenarioError::Io(path.to_path_buf(), error))
+}
+
+impl Scenario {
+    ///Build
+    ///Builds the layout, places the trains and sets the initial state
+    ///Layout files are looked up relative to the base directory, what an import left out ends up in the warnings
+    pub fn build(&self, base: &Path) -> Result<WorkerData, ScenarioError> {
+        let mut warnings = vec![];
+        let mut system = match &self.layout {
+            LayoutSource::File(file) => {
+                let (system, unsupported) = load_layout(&base.join(file))?;
+                for element in unsupported {
+                    warnings.push(format!("{}: unsupported {}", file, element));
+                }
+                system
+            }
+            LayoutSource::Inline(layout) => layout.build()?,
+        };
+
+        for switch in &self.switches {
+            let positions = match system.structures.get(&switch.structure) {
+                Some(structure) if structure.position().is_some() => structure.positions(),
+                _ => {
+                    return Err(invalid(format!(
+                        "structure {} is not a switch",
+                        switch.structure
+                    )))
+                }
+            };
+            if !positions.contains(&switch.position) {
+                return Err(invalid(format!(
+                    "switch {} has no position {:?}",
+                    switch.structure, switch.position
+                )));
+            }
+            if !system.throw_switch(switch.structure, switch.position) {
+                return Err(invalid(format!(
+                    "switch {} can't be thrown to {:?}",
+                    switch.structure, switch.position
+                )));
+            }
+        }
+
+        let mut trains: Vec<Train> = vec![];
+        for definition in &self.trains {
+            let train = definition.build(&system)?;
+            if trains
+                .iter()
+                .any(|other| other.train_number == train.train_number)
+            {
+                return Err(invalid(format!(
+                    "train number {} is used more than once",
+                    train.train_number
+                )));
+            }
+            if let Some(other) = trains.iter().find(|other| other.overlaps(&train)) {
+                return Err(invalid(format!(
+                    "trains {} and {} start on the same track",
+                    other.train_number, train.train_number
+                )));
+            }
+            trains.push(train);
+        }
+
+        let mut events = vec![];
+        for event in &self.events {
+            events.push(ScriptedEvent {
+                time: time(&event.time)?,
+                command: event.command.clone(),
+            });
+        }
+        events.sort_by(|a, b| a.time.total_cmp(&b.time));
+
+        let script = match &self.script {
+            Some(file) => {
+                let source = read_file(&base.join(file))?;
+                Some(scripting::compile(file, &source).map_err(invalid)?)
+            }
+            None => None,
+        };
+
+        let mut data = WorkerData {
+            time: time(&self.start_time)?,
+            train_system: system,
+            trains,
+            auto_dispatch: self.auto_dispatch,
+            strategy: self.strategy,
+            lookahead: self.lookahead,
+            events,
+            script,
+            warnings,
+            ..Default::default()
+        };
+        worker::update_sections(&mut data);
+
+        Ok(data)
+    }
+}
+
+impl Layout {
+    ///Build
+    ///Creates the structures in order, then the connections and signals between their pins
+    pub fn build(&self) -> Result<TrainSystem, ScenarioError> {
+        let mut system = TrainSystem::new();
+
+        for (idx, structure) in self.structures.iter().enumerate() {
+            let sections = match structure {
+                StructureDefinition::Track {
+                    length,
+                    sections,
+                    profile,
+                } => {
+                    system.track(*length);
+                    system.set_profile(idx, profile);
+                    sections
+                }
+                StructureDefinition::Switch {
+                    switch_type,
+                    sections,
+                } => {
+                    system.switch(*switch_type);
+                    sections
+                }
+                StructureDefinition::ThreeWay { sections } => {
+                    system.three_way_switch();
+                    sections
+                }
+                StructureDefinition::Crossing { sections } => {
+                    system.crossing();
+                    sections
+                }
+                StructureDefinition::Slip {
+                    slip_type,
+                    sections,
+                } => {
+                    system.slip_switch(*slip_type);
+                    sections
+                }
+                StructureDefinition::Turntable {
+                    length,
+                    tracks,
+                    sections,
+                } => {
+                    system.turntable(*length, *tracks);
+                    sections
+                }
+                StructureDefinition::TransferTable {
+                    length,
+                    tracks,
+                    sections,
+                } => {
+                    system.transfer_table(*length, *tracks);
+                    sections
+                }
+            };
+
+            match sections {
+                Some(SectionDefinition::Boundaries {
+                    boundaries,
+                    detection,
+                }) => {
+                    system.set_sections(idx, boundaries, *detection);
+                }
+                Some(SectionDefinition::Random {
+                    amount,
+                    seed,
+                    detection,
+                }) => {
+                    system.set_random_sections(idx, *amount, *seed, *detection);
+                }
+                None => {}
+            }
+        }
+
+        for (a, b) in &self.connections {
+            let pin_a = pin(&system, a)?;
+            let pin_b = pin(&system, b)?;
+            system.connect(pin_a, pin_b);
+        }
+
+        for signal in &self.signals {
+            let pin = pin(&system, signal)?;
+            system.signal(pin);
+        }
+
+        for signal in &self.shunt_signals {
+            let pin = pin(&system, signal)?;
+            system.shunt_signal(pin);
+        }
+
+        for idx in &self.non_electrified {
+            if !system.structures.contains_key(idx) {
+                return Err(invalid(format!(
+                    "non electrified structure {} is unknown",
+                    idx
+                )));
+            }
+            system.non_electrified.insert(*idx);
+        }
+
+        Ok(system)
+    }
+}
+
+impl TrainDefinition {
+    ///Build
+    ///Creates the train and places it on the layout
+    pub fn build(&self, system: &TrainSystem) -> Result<Train, ScenarioError> {
+        let mut train = match (self.length, self.performance) {
+            _ if !self.consist.is_empty() => Train::with_consist(self.number, self.consist.clone()),
+            (Some(length), Some(performance)) => {
+                let mut train = Train::new(self.number, length, performance.max_speed);
+                train.performance = performance;
+                train
+            }
+            _ => {
+                return Err(invalid(format!(
+                    "train {} needs a consist or a length and performance",
+                    self.number
+                )))
+            }
+        };
+        train.priority = self.priority;
+        train.destination = self.destination;
+        if let Some(traction) = self.traction {
+            train.traction = traction;
+        }
+        train.driver = self.driver;
+
+        train
+            .place(
+                system,
+                self.start.structure,
+                self.start.direction,
+                self.start.offset,
+            )
+            .ok_or_else(|| {
+                invalid(format!(
+                    "train {} can't be placed on structure {}",
+                    self.number, self.start.structure
+                ))
+            })?;
+
+        for stop in &self.timetable {
+            if !system.structures.contains_key(&stop.structure) {
+                return Err(invalid(format!(
+                    "train {} stops at unknown structure {}",
+                    self.number, stop.structure
+                )));
+            }
+
+            train.timetable.push(TimetableStop {
+                structure: stop.structure,
+                arrival: stop.arrival.as_deref().map(time).transpose()?,
+                departure: stop.departure.as_deref().map(time).transpose()?,
+                actual_arrival: None,
+                actual_departure: None,
+            });
+        }
+
+        Ok(train)
+    }
+}
+
+///Pin
+///Finds the pin for a reference like "3:output"
+fn pin(system: &TrainSystem, reference: &str) -> Result<usize, ScenarioError> {
+    let (structure, name) = reference
+        .split_once(':')
+        .ok_or_else(|| invalid(format!("pin {} should look like structure:pin", reference)))?;
+
+    structure
+        .trim()
+        .parse::<usize>()
+        .ok()
+        .and_then(|idx| system.structures.get(&idx))
+        .and_then(|structure| structure.named_pin(name.trim()))
+        .ok_or_else(|| invalid(format!("unknown pin {}", reference)))
+}
+
+fn time(time: &str) -> Result<f64, ScenarioError> {
+    parse_time(time).ok_or_else(|| invalid(format!("invalid time {}", time)))
+}
+
+fn invalid(message: String) -> ScenarioError {
+    ScenarioError::Invalid(message)
+}
@@ -0,0 +1,415 @@
+use std::sync::{Arc, Mutex};
+
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, Scope, AST};
+use serde::Serialize;
+
+use crate::dispatcher;
+use crate::train::train_structs::*;
+use crate::worker::{self, Command, WorkerData};
+
+///Most operations a script may run in one call, a script that loops forever stops with an error
+pub const MAX_OPERATIONS: u64 = 1_000_000;
+
+///Deepest a script may nest its function calls, deeper recursion stops with an error
+pub const MAX_CALL_LEVELS: usize = 32;
+
+///Script
+///A Rhai script that controls the simulation, it can define these functions:
+///on_tick(state) runs every tick, on_waiting(state, train, signal) runs when a train starts waiting at a red signal
+///The state is a read-only view of the simulation, changes are made by calling the command functions
+///The error of the last run is kept so it can be shown, the script keeps running on the next tick
+///The engine is built once, the commands a run issues are collected in its list
+///A clone gets its own engine and list, so clones that run side by side, e.g. in a batch, keep their commands apart
+#[derive(Debug)]
+pub struct Script {
+    pub name: String,
+    pub ast: AST,
+    pub error: Option<String>,
+    waiting: Vec<i32>,
+    engine: Arc<Engine>,
+    commands: Arc<Mutex<Vec<Command>>>,
+}
+
+impl Clone for Script {
+    fn clone(&self) -> Self {
+        let commands: Arc<Mutex<Vec<Command>>> = Arc::default();
+        Script {
+            name: self.name.clone(),
+            ast: self.ast.clone(),
+            error: self.error.clone(),
+            waiting: self.waiting.clone(),
+            engine: Arc::new(engine(commands.clone())),
+            commands,
+        }
+    }
+}
+
+///Compile
+///Compiles the source of a script, the name is used in error messages
+pub fn compile(name: &str, source: &str) -> Result<Script, String> {
+    let commands: Arc<Mutex<Vec<Command>>> = Arc::default();
+    let engine = engine(commands.clone());
+    let ast = engine
+        .compile(source)
+        .map_err(|error| format!("{}: {}", name, error))?;
+
+    Ok(Script {
+        name: name.to_string(),
+        ast,
+        error: None,
+        waiting: vec![],
+        engine: Arc::new(engine),
+        commands,
+    })
+}
+
+///Engine
+///Creates the engine with the command functions, the commands a script issues are collected in the list
+///set_route(train) sets the next part of the route of a train waiting at a signal
+///throw_switch(switch, "Left", "Right" or "Straight"), align_table(table, track, turn)
+///uncouple(train, vehicle, new_number), couple(train, other), shunt(train, structure)
+///set_driver(train, "Aggressive", "Timetable" or "Eco"), reset_atp(train)
+///set_destination(train, structure), clear_destination(train), hold_train(train, held) and reverse_train(train)
+///Unknown switch positions and drivers are errors, so a typo doesn't throw a switch the wrong way
+fn engine(commands: Arc<Mutex<Vec<Command>>>) -> Engine {
+    let mut engine = Engine::new();
+    engine
+        .set_max_operations(MAX_OPERATIONS)
+        .set_max_call_levels(MAX_CALL_LEVELS);
+
+    let list = commands.clone();
+    engine.register_fn("set_route", move |train: i64| {
+        list.lock().unwrap().push(Command::SetRoute(train as i32));
+    });
+
+    let list = commands.clone();
+    engine.register_fn(
+        "throw_switch",
+        move |switch: i64, position: &str| -> Result<(), Box<EvalAltResult>> {
+            let position = match position {
+                "Left" | "left" => SwitchPosition::Left,
+                "Straight" | "straight" => SwitchPosition::Straight,
+                "Right" | "right" => SwitchPosition::Right,
+                _ => return Err(format!("Unknown switch position {}", position).into()),
+            };
+            list.lock()
+                .unwrap()
+                .push(Command::ThrowSwitch(switch as usize, position));
+            Ok(())
+        },
+    );
+
+    let list = commands.clone();
+    engine.register_fn("align_table", move |table: i64, track: i64, turn: bool| {
+        list.lock()
+            .unwrap()
+            .push(Command::AlignTable(table as usize, track as usize, turn));
+    });
+
+    let list = commands.clone();
+    engine.register_fn(
+        "uncouple",
+        move |train: i64, vehicle: i64, new_number: i64| {
+            list.lock().unwrap().push(Command::Uncouple(
+                train as i32,
+                vehicle as usize,
+                new_number as i32,
+            ));
+        },
+    );
+
+    let list = commands.clone();
+    engine.register_fn("couple", move |train: i64, other: i64| {
+        list.lock()
+            .unwrap()
+            .push(Command::Couple(train as i32, other as i32));
+    });
+
+    let list = commands.clone();
+    engine.register_fn("shunt", move |train: i64, structure: i64| {
+        list.lock()
+            .unwrap()
+            .push(Command::Shunt(train as i32, structure as usize));
+    });
+
+    let list = commands.clone();
+    engine.register_fn(
+        "set_driver",
+        move |train: i64, driver: &str| -> Result<(), Box<EvalAltResult>> {
+            let driver = match driver {
+                "Aggressive" | "aggressive" => Driver::Aggressive,
+                "Timetable" | "timetable" => Driver::Timetable,
+                "Eco" | "eco" => Driver::Eco,
+                _ => return Err(format!("Unknown driver {}", driver).into()),
+            };
+            list.lock()
+                .unwrap()
+                .push(Command::SetDriver(train as i32, driver));
+            Ok(())
+        },
+    );
+
+    let list = commands.clone();
+    engine.register_fn("reset_atp", move |train: i64| {
+        list.lock().unwrap().push(Command::ResetAtp(train as i32));
+    });
+
+    let list = commands.clone();
+    engine.register_fn("set_destination", move |train: i64, structure: i64| {
+        list.lock().unwrap().push(Command::SetDestination(
+            train as i32,
+            Some(structure as usize),
+        ));
+    });
+
+    let list = commands.clone();
+    engine.register_fn("clear_destination", move |train: i64| {
+        list.lock()
+            .unwrap()
+            .push(Command::SetDestination(train as i32, None));
+    });
+
+    let list = commands.clone();
+    engine.register_fn("hold_train", move |train: i64, held: bool| {
+        list.lock()
+            .unwrap()
+            .push(Command::HoldTrain(train as i32, held));
+    });
+
+    let list = commands;
+    engine.register_fn("reverse_train", move |train: i64| {
+        list.lock()
+            .unwrap()
+            .push(Command::ReverseTrain(train as i32));
+    });
+
+    engine
+}
+
+///State
+///What a script can see of the simulation, times in seconds, distances in meters and speeds in m/s
+#[derive(Debug, Clone, Serialize)]
+pub struct State {
+    pub time: f64,
+    pub trains: Vec<TrainState>,
+    pub structures: Vec<StructureState>,
+    pub signals: Vec<SignalState>,
+}
+
+///Train State
+///Structure and offset are the position of the front, waiting signal is the red signal the train stands in front of
+#[derive(Debug, Clone, Serialize)]
+pub struct TrainState {
+    pub number: i32,
+    pub structure: Option<usize>,
+    pub offset: f64,
+    pub speed: f64,
+    pub speed_limit: Option<f64>,
+    pub direction: Option<Direction>,
+    pub destination: Option<usize>,
+    pub priority: i32,
+    pub running: bool,
+    pub broken_down: bool,
+    pub shunting: bool,
+    pub driver: Driver,
+    pub atp: AtpStatus,
+    pub waiting_signal: Option<usize>,
+}
+
+///Structure State
+///Kind is track, switch, three_way, crossing, slip, turntable or transfer_table
+///Position is only set for the switches
+#[derive(Debug, Clone, Serialize)]
+pub struct StructureState {
+    pub idx: usize,
+    pub kind: String,
+    pub length: usize,
+    pub position: Option<SwitchPosition>,
+    pub reserved_by: Option<i32>,
+    pub closed: bool,
+    pub occupied: bool,
+    pub sections: Vec<SectionState>,
+}
+
+///Section State
+///The train number is 0 when no train is on the section
+#[derive(Debug, Clone, Serialize)]
+pub struct SectionState {
+    pub active: bool,
+    pub train: i32,
+}
+
+///Signal State
+///A signal with the structure of the pin it stands at
+#[derive(Debug, Clone, Serialize)]
+pub struct SignalState {
+    pub idx: usize,
+    pub structure: Option<usize>,
+    pub kind: SignalKind,
+    pub aspect: Aspect,
+    pub failed: bool,
+}
+
+///State
+///Builds the view of the simulation, structures and signals are ordered by their index
+pub fn state(data: &WorkerData) -> State {
+    let system = &data.train_system;
+
+    let trains = data
+        .trains
+        .iter()
+        .map(|train| TrainState {
+            number: train.train_number,
+            structure: train.path.front().map(|step| step.structure),
+            offset: train.head_offset,
+            speed: train.speed,
+            speed_limit: train.speed_limit,
+            direction: train.direction(),
+            destination: train.destination,
+            priority: train.priority,
+            running: train.train_status == TrainStatus::Running,
+            broken_down: train.broken_down,
+            shunting: train.shunting,
+            driver: train.driver,
+            atp: train.atp,
+            waiting_signal: dispatcher::waiting_signal(system, train),
+        })
+        .collect();
+
+    let mut structures: Vec<StructureState> = system
+        .structures
+        .iter()
+        .map(|(idx, structure)| StructureState {
+            idx: *idx,
+            kind: match structure {
+                StructureTypes::Track(_) => "track".to_string(),
+                StructureTypes::Switch(_) => "switch".to_string(),
+                StructureTypes::ThreeWay(_) => "three_way".to_string(),
+                StructureTypes::Crossing(_) => "crossing".to_string(),
+                StructureTypes::Slip(_) => "slip".to_string(),
+                StructureTypes::Table(table) => match table.table_type {
+                    TableType::Turntable => "turntable".to_string(),
+                    TableType::TransferTable => "transfer_table".to_string(),
+                },
+            },
+            length: structure.length(),
+            position: structure.position(),
+            reserved_by: system.reservations.get(idx).copied(),
+            closed: system.closures.contains(idx),
+            occupied: structure.sections().iter().any(|section| section.active),
+            sections: structure
+                .sections()
+                .iter()
+                .map(|section| SectionState {
+                    active: section.active,
+                    train: section.train_number,
+                })
+                .collect(),
+        })
+        .collect();
+    structures.sort_by_key(|structure| structure.idx);
+
+    let mut signals: Vec<SignalState> = system
+        .signals
+        .values()
+        .map(|signal| SignalState {
+            idx: signal.idx,
+            structure: system.pins.get(&signal.pin).copied(),
+            kind: signal.kind,
+            aspect: signal.aspect,
+            failed: signal.failed,
+        })
+        .collect();
+    signals.sort_by_key(|signal| signal.idx);
+
+    State {
+        time: data.time,
+        trains,
+        structures,
+        signals,
+    }
+}
+
+///Run
+///Calls the functions of the script that are defined and applies the commands they issued
+///on_waiting is called once for every train that started waiting at a red signal since the last tick
+pub fn run(data: &mut WorkerData) {
+    let script = match &data.script {
+        Some(script) => script,
+        None => return,
+    };
+
+    let defines = |name: &str| script.ast.iter_functions().any(|f| f.name == name);
+
+    let engine = script.engine.clone();
+    let commands = script.commands.clone();
+    commands.lock().unwrap().clear();
+    let options = || CallFnOptions::new().eval_ast(false);
+    let state = match rhai::serde::to_dynamic(state(data)) {
+        Ok(state) => state,
+        Err(error) => {
+            set_error(data, error.to_string());
+            return;
+        }
+    };
+
+    let mut result = Ok(());
+
+    if defines("on_tick") {
+        result = engine
+            .call_fn_with_options::<Dynamic>(
+                options(),
+                &mut Scope::new(),
+                &script.ast,
+                "on_tick",
+                (state.clone(),),
+            )
+            .map(|_| ());
+    }
+
+    let waiting: Vec<(i32, usize)> = data
+        .trains
+        .iter()
+        .filter_map(|train| {
+            dispatcher::waiting_signal(&data.train_system, train)
+                .map(|signal| (train.train_number, signal))
+        })
+        .collect();
+
+    if defines("on_waiting") {
+        for (train, signal) in &waiting {
+            if result.is_err() || script.waiting.contains(train) {
+                continue;
+            }
+            result = engine
+                .call_fn_with_options::<Dynamic>(
+                    options(),
+                    &mut Scope::new(),
+                    &script.ast,
+                    "on_waiting",
+                    (state.clone(), *train as i64, *signal as i64),
+                )
+                .map(|_| ());
+        }
+    }
+
+    let name = script.name.clone();
+    if let Some(script) = &mut data.script {
+        script.waiting = waiting.iter().map(|(train, _)| *train).collect();
+        script.error = None;
+    }
+    if let Err(error) = result {
+        set_error(data, format!("{}: {}", name, error));
+    }
+
+    let commands = std::mem::take(&mut *commands.lock().unwrap());
+    for command in commands {
+        worker::apply_command(data, command);
+    }
+}
+
+fn set_error(data: &mut WorkerData, error: String) {
+    if let Some(script) = &mut data.script {
+        script.error = Some(error);
+    }
+}
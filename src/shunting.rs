@@ -0,0 +1,402 @@
+use std::collections::{BinaryHeap, HashMap, VecDeque};
+
+use crate::train::routing::{self, Route, SearchState};
+use crate::train::train_structs::*;
+use crate::worker::{self, WorkerData};
+
+///Meters a shunting train keeps to a train standing ahead of it, close enough to couple
+pub const SHUNT_MARGIN: f64 = 2.0;
+
+///Penalty in meters for every reversal of a shunt move, a move with fewer reversals is preferred
+pub const REVERSAL_PENALTY: f64 = 1000.0;
+
+///A train number with the intervals the train occupies, see occupied intervals
+pub type Occupation = (i32, Vec<(TrainStep, f64, f64)>);
+
+///Plan
+///The legs of a move from where a train is to a target structure, ignoring the current switch positions
+///A train reverses where it stands or on a track it fits on completely, e.g. the headshunt of a yard ladder
+///Every reversal ends a leg, the last leg ends at the target
+pub fn plan(system: &TrainSystem, train: &Train, target: usize) -> Option<VecDeque<Route>> {
+    let mut distances: HashMap<(usize, usize), f64> = HashMap::new();
+    let mut previous: HashMap<(usize, usize), (usize, usize, usize, bool)> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+
+    //The train starts either way, the first step keeps its own exit
+    let mut reversed = train.clone();
+    reversed.reverse();
+    let starts: Vec<TrainStep> = [train, &reversed]
+        .iter()
+        .filter_map(|train| train.path.front().copied())
+        .collect();
+
+    for (step, distance) in starts.iter().zip([0.0, REVERSAL_PENALTY]) {
+        distances.insert((step.structure, step.entry_pin), distance);
+        queue.push(SearchState {
+            distance,
+            structure: step.structure,
+            entry_pin: step.entry_pin,
+        });
+    }
+
+    while let Some(SearchState {
+        distance,
+        structure,
+        entry_pin,
+    }) = queue.pop()
+    {
+        if structure == target {
+            return Some(build_legs(
+                system,
+                &starts,
+                &previous,
+                (structure, entry_pin),
+            ));
+        }
+
+        if distance
+            > *distances
+                .get(&(structure, entry_pin))
+                .unwrap_or(&f64::INFINITY)
+        {
+            continue;
+        }
+
+        let item = match system.structures.get(&structure) {
+            Some(item) => item,
+            None => continue,
+        };
+        let length = item.length() as f64;
+        let fits = matches!(item, StructureTypes::Track(_)) && item.length() >= train.train_length;
+
+        let exits = match starts
+            .iter()
+            .find(|step| (step.structure, step.entry_pin) == (structure, entry_pin))
+        {
+            Some(step) => vec![step.exit_pin],
+            None => item.exits(entry_pin),
+        };
+
+        for exit in exits {
+            let mut moves = vec![];
+
+            if let Some(next_pin) = system.connections.get(&exit) {
+                if let Some(next_structure) = system.pins.get(next_pin) {
+                    moves.push(((*next_structure, *next_pin), distance + length, false));
+                }
+            }
+
+            //Run to the end of the track and come back the other way
+            if fits {
+                moves.push((
+                    (structure, exit),
+                    distance + length + REVERSAL_PENALTY,
+                    true,
+                ));
+            }
+
+            for (key, next_distance, reversal) in moves {
+                if next_distance < *distances.get(&key).unwrap_or(&f64::INFINITY) {
+                    distances.insert(key, next_distance);
+                    previous.insert(key, (structure, entry_pin, exit, reversal));
+                    queue.push(SearchState {
+                        distance: next_distance,
+                        structure: key.0,
+                        entry_pin: key.1,
+                    });
+                }
+            }
+        }
+    }
+
+    None
+}
+
+///Build Legs
+///Walks back through the search results and turns them into steps, split into legs at the reversals
+fn build_legs(
+    system: &TrainSystem,
+    starts: &[TrainStep],
+    previous: &HashMap<(usize, usize), (usize, usize, usize, bool)>,
+    target: (usize, usize),
+) -> VecDeque<Route> {
+    let mut steps = vec![];
+    let mut current = target;
+    let mut exit_pin = None;
+    let mut reversal = false;
+
+    loop {
+        let (structure, entry_pin) = current;
+        let item = &system.structures[&structure];
+
+        let start = starts
+            .iter()
+            .find(|step| (step.structure, step.entry_pin) == current);
+        let exit = match (exit_pin, start) {
+            (Some(exit), _) => exit,
+            (None, Some(start)) => start.exit_pin,
+            (None, None) => item
+                .exit_pin(entry_pin)
+                .unwrap_or_else(|| item.exits(entry_pin).first().copied().unwrap_or(entry_pin)),
+        };
+
+        let step = TrainStep {
+            structure,
+            entry_pin,
+            exit_pin: exit,
+            direction: item.direction_from(entry_pin),
+            length: item.length() as f64,
+        };
+        steps.push((step, reversal));
+
+        match previous.get(&current) {
+            Some((structure, entry_pin, exit, reversed)) => {
+                current = (*structure, *entry_pin);
+                exit_pin = Some(*exit);
+                reversal = *reversed;
+            }
+            None => break,
+        }
+    }
+
+    let mut legs = VecDeque::new();
+    let mut leg = Route::default();
+
+    for (step, reversal) in steps.into_iter().rev() {
+        let item = &system.structures[&step.structure];
+        if let Some(position) = item.required_position(step.entry_pin, step.exit_pin) {
+            leg.switches.push((step.structure, position));
+        }
+        leg.steps.push(step);
+
+        if reversal {
+            legs.push_back(std::mem::take(&mut leg));
+        }
+    }
+    legs.push_back(leg);
+    legs
+}
+
+///Start
+///Puts a train in shunting mode and plans a move to a target structure, e.g. a siding of the yard
+///Returns false when the train doesn't exist or there is no way to the target
+pub fn start(data: &mut WorkerData, number: i32, target: usize) -> bool {
+    let system = &data.train_system;
+    let train = match data
+        .trains
+        .iter_mut()
+        .find(|train| train.train_number == number)
+    {
+        Some(train) => train,
+        None => return false,
+    };
+
+    let legs = match plan(system, train, target) {
+        Some(legs) => legs,
+        None => return false,
+    };
+
+    train.shunting = true;
+    train.shunt_move = Some(ShuntMove {
+        target,
+        legs,
+        started: false,
+    });
+    train.route.clear();
+    worker::release_reservations(data);
+    true
+}
+
+///Update
+///Runs the shunt moves, a leg is done when the train stands still with its front on the last structure of the leg
+///The next leg starts once its structures are free, the train reverses first when the leg leads the other way
+///The target may be occupied, the train then stops behind the train standing there so the two can be coupled
+///A move whose train is no longer where the leg starts is planned again
+pub fn update(data: &mut WorkerData) {
+    for i in 0..data.trains.len() {
+        let train = &mut data.trains[i];
+        if train.broken_down || train.held {
+            continue;
+        }
+        let shunt = match train.shunt_move.as_mut() {
+            Some(shunt) => shunt,
+            None => continue,
+        };
+
+        if shunt.started {
+            let end = shunt
+                .legs
+                .front()
+                .and_then(|leg| leg.steps.last())
+                .map(|step| step.structure);
+            let arrived = train.speed == 0.0
+                && train.train_status == TrainStatus::Stopped
+                && train.path.front().map(|front| front.structure) == end;
+            if !arrived {
+                continue;
+            }
+
+            shunt.legs.pop_front();
+            shunt.started = false;
+            train.route.clear();
+        }
+
+        //The train stays at the target, it keeps it as destination so it doesn't run on
+        if shunt.legs.is_empty() {
+            train.destination = Some(shunt.target);
+            train.shunt_move = None;
+            continue;
+        }
+
+        start_leg(data, i);
+    }
+
+    worker::release_reservations(data);
+}
+
+///Start Leg
+///Sets the next leg of the shunt move of a train, returns false when it has to wait
+fn start_leg(data: &mut WorkerData, i: usize) -> bool {
+    //The leg before is released first, so its switches can be thrown again
+    worker::release_reservations(data);
+
+    let system = &mut data.train_system;
+    let train = &mut data.trains[i];
+    let number = train.train_number;
+
+    let (target, last, leg) = match &train.shunt_move {
+        Some(shunt) => match shunt.legs.front() {
+            Some(leg) => (shunt.target, shunt.legs.len() == 1, leg.clone()),
+            None => return false,
+        },
+        None => return false,
+    };
+    let first = match leg.steps.first() {
+        Some(first) => (first.structure, first.entry_pin),
+        None => return false,
+    };
+
+    let front = train
+        .path
+        .front()
+        .map(|step| (step.structure, step.entry_pin));
+    if front != Some(first) {
+        let mut reversed = train.clone();
+        reversed.reverse();
+
+        let turns = reversed
+            .path
+            .front()
+            .map(|step| (step.structure, step.entry_pin))
+            == Some(first);
+        if !turns {
+            let legs = plan(system, train, target);
+            if let (Some(shunt), Some(legs)) = (train.shunt_move.as_mut(), legs) {
+                shunt.legs = legs;
+            }
+            return false;
+        }
+        if train.speed > 0.0 {
+            return false;
+        }
+        *train = reversed;
+    }
+
+    //Structures the train is on and a target it couples to may hold other trains
+    let open = |idx: usize| {
+        !system.closures.contains(&idx)
+            && system
+                .reservations
+                .get(&idx)
+                .is_none_or(|reserved| *reserved == number)
+            && system
+                .structures
+                .get(&idx)
+                .is_some_and(|structure| !structure.is_moving())
+    };
+    let free = leg.steps.iter().all(|step| {
+        let shared = (last && step.structure == target)
+            || train.path.iter().any(|on| on.structure == step.structure);
+        match shared {
+            true => open(step.structure),
+            false => system.is_free(step.structure, number),
+        }
+    });
+    let signals: Vec<usize> = leg.steps[..leg.steps.len() - 1]
+        .iter()
+        .filter_map(|step| system.signal_at(step.exit_pin))
+        .map(|signal| signal.idx)
+        .collect();
+    let failed = signals.iter().any(|idx| system.signals[idx].failed);
+    if !free || failed {
+        return false;
+    }
+
+    for (idx, position) in &leg.switches {
+        if !system.throw_switch(*idx, *position) {
+            return false;
+        }
+    }
+
+    for step in &leg.steps {
+        system.reservations.insert(step.structure, number);
+    }
+    for idx in signals {
+        if let Some(signal) = system.signals.get_mut(&idx) {
+            signal.aspect = Aspect::Shunt;
+        }
+    }
+
+    train.route = leg.steps.iter().copied().collect();
+    train.destination = leg.steps.last().map(|step| step.structure);
+    if let Some(shunt) = train.shunt_move.as_mut() {
+        shunt.started = true;
+    }
+    worker::update_sections(data);
+    true
+}
+
+///Clearance
+///Distance from the front of a shunting train to the back of the nearest other train ahead, less the shunt margin
+///Others are the occupied intervals of the other trains, we look no further than the limit
+pub fn clearance(system: &TrainSystem, train: &Train, others: &[Occupation], limit: f64) -> f64 {
+    let mut step = match train.path.front() {
+        Some(front) => *front,
+        None => return limit,
+    };
+    let mut offset = train.head_offset;
+    let mut distance = 0.0;
+
+    while distance < limit {
+        let head = step.structure_distance(offset);
+
+        let ahead = others
+            .iter()
+            .filter(|(number, _)| *number != train.train_number)
+            .flat_map(|(_, intervals)| intervals)
+            .filter(|(other, _, _)| other.structure == step.structure)
+            .filter_map(|(other, start, end)| {
+                let a = other.structure_distance(*start);
+                let b = other.structure_distance(*end);
+                let gap = match step.direction {
+                    Direction::Forward => a.min(b) - head,
+                    Direction::Reverse => head - a.max(b),
+                };
+                (gap >= -0.01).then_some(gap)
+            })
+            .reduce(f64::min);
+
+        if let Some(gap) = ahead {
+            return (distance + gap - SHUNT_MARGIN).max(0.0);
+        }
+
+        distance += step.length - offset;
+        offset = 0.0;
+        step = match routing::next_step(system, &step) {
+            Some(next) => next,
+            None => break,
+        };
+    }
+
+    limit
+}
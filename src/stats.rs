@@ -0,0 +1,406 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use serde::Serialize;
+
+use crate::atp::{self, Incident};
+use crate::dispatcher;
+use crate::train::physics;
+use crate::train::train_structs::*;
+use crate::worker::{WorkerData, ARRIVAL_MARGIN};
+
+///A stop counts as punctual when the train arrives less than three minutes late
+pub const PUNCTUALITY_MARGIN: f64 = 180.0;
+
+///Statistics
+///Metrics collected while the simulation runs, each tick adds the elapsed time to what it measured
+///Occupied time is kept per section, entries count the trains that entered a structure
+///Switch positions are remembered so a change between ticks counts as a throw
+///Incidents are the interventions of the train protection
+#[derive(Debug, Clone, Default)]
+pub struct Statistics {
+    pub duration: f64,
+    pub trains: BTreeMap<i32, TrainStatistics>,
+    pub occupied: BTreeMap<(usize, usize), f64>,
+    pub entries: BTreeMap<usize, usize>,
+    pub throws: BTreeMap<usize, usize>,
+    pub incidents: Vec<Incident>,
+    switch_positions: HashMap<usize, SwitchPosition>,
+}
+
+///Train Statistics
+///Distance in meters, running time and time waiting at a red signal in seconds
+///Energy used and regenerated in kWh, power is the traction power in kW during the last tick
+#[derive(Debug, Clone, Default)]
+pub struct TrainStatistics {
+    pub distance: f64,
+    pub running_time: f64,
+    pub signal_wait: f64,
+    pub energy: f64,
+    pub regenerated: f64,
+    pub power: f64,
+    last_path: Vec<usize>,
+    last_position: Option<(usize, f64)>,
+    last_speed: f64,
+}
+
+///Record
+///Adds the state after a tick to the statistics
+pub fn record(data: &mut WorkerData, elapsed: f64) {
+    let system = &data.train_system;
+    let stats = &mut data.statistics;
+    stats.duration += elapsed;
+
+    for train in &data.trains {
+        let train_stats = stats.trains.entry(train.train_number).or_default();
+        let first = train_stats.last_position.is_none();
+
+        //Distance travelled since the last tick, the front may have moved onto the next structures
+        let moved = train_stats
+            .last_position
+            .map_or(0.0, |(last, offset)| travelled(train, last, offset));
+        train_stats.distance += moved;
+        train_stats.last_position = train
+            .path
+            .front()
+            .map(|front| (front.structure, train.head_offset));
+
+        let energy = physics::energy(system, train, train_stats.last_speed, moved, elapsed);
+        train_stats.energy += energy.traction;
+        train_stats.regenerated += energy.regenerated;
+        train_stats.power = match elapsed > 0.0 {
+            true => energy.traction * 3600.0 / elapsed,
+            false => 0.0,
+        };
+        train_stats.last_speed = train.speed;
+
+        if train.speed > 0.0 {
+            train_stats.running_time += elapsed;
+        }
+
+        //Standing at the end of a structure that is not its destination, e.g. a stop in the timetable
+        let at_signal = train.path.front().is_some_and(|front| {
+            train.head_offset >= front.length - ARRIVAL_MARGIN
+                && train.destination != Some(front.structure)
+        });
+        if train.speed == 0.0 && at_signal && dispatcher::waiting_signal(system, train).is_some() {
+            train_stats.signal_wait += elapsed;
+        }
+
+        let path: Vec<usize> = train.path.iter().map(|step| step.structure).collect();
+        for structure in &path {
+            if !first && !train_stats.last_path.contains(structure) {
+                *stats.entries.entry(*structure).or_insert(0) += 1;
+            }
+        }
+        train_stats.last_path = path;
+    }
+
+    for (idx, structure) in &system.structures {
+        for (i, section) in structure.sections().iter().enumerate() {
+            let occupied = stats.occupied.entry((*idx, i)).or_insert(0.0);
+            if section.active {
+                *occupied += elapsed;
+            }
+        }
+
+        if let Some(position) = structure.position() {
+            let previous = stats.switch_positions.insert(*idx, position);
+            let throws = stats.throws.entry(*idx).or_insert(0);
+            if previous.is_some_and(|previous| previous != position) {
+                *throws += 1;
+            }
+        }
+    }
+}
+
+///Travelled
+///Distance from a previous front position to the current front along the path of a train
+///Zero when the previous position is no longer on the path, e.g. after the train reversed
+fn travelled(train: &Train, last: usize, offset: f64) -> f64 {
+    let mut distance = train.head_offset;
+
+    for (i, step) in train.path.iter().enumerate() {
+        if step.structure == last {
+            return match i {
+                0 => (train.head_offset - offset).max(0.0),
+                _ => distance + step.length - offset,
+            };
+        }
+        if i > 0 {
+            distance += step.length;
+        }
+    }
+
+    0.0
+}
+
+///Report
+///The metrics of a run, ready to show or export
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Report {
+    pub duration: f64,
+    pub total_delay_minutes: f64,
+    pub total_energy_kwh: f64,
+    pub total_regenerated_kwh: f64,
+    pub incidents: Vec<Incident>,
+    pub trains: Vec<TrainReport>,
+    pub sections: Vec<SectionReport>,
+    pub structures: Vec<StructureReport>,
+    pub switches: Vec<SwitchReport>,
+}
+
+///Train Report
+///Speed in m/s, times in seconds and delays in minutes
+///Punctuality is the percentage of timetable stops reached in time, see the punctuality margin
+///Energy in kWh, net energy is what was used less what was regenerated, also per km travelled
+#[derive(Debug, Clone, Serialize)]
+pub struct TrainReport {
+    pub train_number: i32,
+    pub traction: Traction,
+    pub distance: f64,
+    pub average_speed: f64,
+    pub running_time: f64,
+    pub signal_wait: f64,
+    pub stops: usize,
+    pub punctual_stops: usize,
+    pub punctuality: f64,
+    pub delay_minutes: f64,
+    pub energy_kwh: f64,
+    pub regenerated_kwh: f64,
+    pub net_energy_kwh: f64,
+    pub energy_per_km: f64,
+    pub atp_interventions: usize,
+}
+
+///Section Report
+///Utilization is the percentage of the run the section was occupied
+#[derive(Debug, Clone, Serialize)]
+pub struct SectionReport {
+    pub structure: usize,
+    pub section: usize,
+    pub utilization: f64,
+}
+
+///Structure Report
+///Throughput is the number of trains that entered the structure, also per hour of the run
+#[derive(Debug, Clone, Serialize)]
+pub struct StructureReport {
+    pub structure: usize,
+    pub trains: usize,
+    pub trains_per_hour: f64,
+}
+
+///Switch Report
+///Number of times a switch changed position
+#[derive(Debug, Clone, Serialize)]
+pub struct SwitchReport {
+    pub structure: usize,
+    pub throws: usize,
+}
+
+///Report
+///Creates the report from the statistics and the timetables of the trains
+///A stop counts when the train arrived there, its delay is how late it arrived or departed, whichever is later
+pub fn report(data: &WorkerData) -> Report {
+    let stats = &data.statistics;
+    let duration = stats.duration;
+    let percentage = |time: f64| match duration > 0.0 {
+        true => time / duration * 100.0,
+        false => 0.0,
+    };
+
+    let trains: Vec<TrainReport> = data
+        .trains
+        .iter()
+        .map(|train| {
+            let train_stats = stats
+                .trains
+                .get(&train.train_number)
+                .cloned()
+                .unwrap_or_default();
+
+            let delays: Vec<f64> = train
+                .timetable
+                .iter()
+                .filter(|stop| stop.actual_arrival.is_some())
+                .map(|stop| {
+                    let arrival = delay(stop.arrival, stop.actual_arrival);
+                    let departure = delay(stop.departure, stop.actual_departure);
+                    arrival.max(departure)
+                })
+                .collect();
+            let punctual_stops = delays
+                .iter()
+                .filter(|delay| **delay < PUNCTUALITY_MARGIN)
+                .count();
+
+            let net_energy = train_stats.energy - train_stats.regenerated;
+
+            TrainReport {
+                train_number: train.train_number,
+                traction: train.traction,
+                distance: train_stats.distance,
+                average_speed: match train_stats.running_time > 0.0 {
+                    true => train_stats.distance / train_stats.running_time,
+                    false => 0.0,
+                },
+                running_time: train_stats.running_time,
+                signal_wait: train_stats.signal_wait,
+                stops: delays.len(),
+                punctual_stops,
+                punctuality: match delays.is_empty() {
+                    true => 100.0,
+                    false => punctual_stops as f64 / delays.len() as f64 * 100.0,
+                },
+                delay_minutes: delays.iter().sum::<f64>() / 60.0,
+                energy_kwh: train_stats.energy,
+                regenerated_kwh: train_stats.regenerated,
+                net_energy_kwh: net_energy,
+                energy_per_km: match train_stats.distance > 0.0 {
+                    true => net_energy / train_stats.distance * 1000.0,
+                    false => 0.0,
+                },
+                atp_interventions: atp::count(&stats.incidents, train.train_number),
+            }
+        })
+        .collect();
+
+    Report {
+        duration,
+        total_delay_minutes: trains.iter().map(|train| train.delay_minutes).sum(),
+        total_energy_kwh: trains.iter().map(|train| train.energy_kwh).sum(),
+        total_regenerated_kwh: trains.iter().map(|train| train.regenerated_kwh).sum(),
+        incidents: stats.incidents.clone(),
+        trains,
+        sections: stats
+            .occupied
+            .iter()
+            .map(|((structure, section), time)| SectionReport {
+                structure: *structure,
+                section: *section,
+                utilization: percentage(*time),
+            })
+            .collect(),
+        structures: stats
+            .entries
+            .iter()
+            .map(|(structure, trains)| StructureReport {
+                structure: *structure,
+                trains: *trains,
+                trains_per_hour: match duration > 0.0 {
+                    true => *trains as f64 / duration * 3600.0,
+                    false => 0.0,
+                },
+            })
+            .collect(),
+        switches: stats
+            .throws
+            .iter()
+            .map(|(structure, throws)| SwitchReport {
+                structure: *structure,
+                throws: *throws,
+            })
+            .collect(),
+    }
+}
+
+///Delay
+///Seconds a train was late, zero when it was early or there is no planned time
+fn delay(planned: Option<f64>, actual: Option<f64>) -> f64 {
+    match (planned, actual) {
+        (Some(planned), Some(actual)) => (actual - planned).max(0.0),
+        _ => 0.0,
+    }
+}
+
+impl Report {
+    ///To Json
+    ///The report as pretty printed json
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    ///To Csv
+    ///The report as one table with a row per metric: category, id, metric and value
+    ///The id is the train number, the structure or structure:section
+    pub fn to_csv(&self) -> String {
+        let mut csv = String::from("category,id,metric,value\n");
+        let mut row = |category: &str, id: String, metric: &str, value: f64| {
+            let _ = writeln!(csv, "{},{},{},{}", category, id, metric, value);
+        };
+
+        row("run", String::new(), "duration", self.duration);
+        row(
+            "run",
+            String::new(),
+            "total_delay_minutes",
+            self.total_delay_minutes,
+        );
+        row(
+            "run",
+            String::new(),
+            "total_energy_kwh",
+            self.total_energy_kwh,
+        );
+        row(
+            "run",
+            String::new(),
+            "total_regenerated_kwh",
+            self.total_regenerated_kwh,
+        );
+
+        for train in &self.trains {
+            let id = train.train_number.to_string();
+            row("train", id.clone(), "distance", train.distance);
+            row("train", id.clone(), "average_speed", train.average_speed);
+            row("train", id.clone(), "running_time", train.running_time);
+            row("train", id.clone(), "signal_wait", train.signal_wait);
+            row("train", id.clone(), "stops", train.stops as f64);
+            row("train", id.clone(), "punctuality", train.punctuality);
+            row("train", id.clone(), "delay_minutes", train.delay_minutes);
+            row("train", id.clone(), "energy_kwh", train.energy_kwh);
+            row(
+                "train",
+                id.clone(),
+                "regenerated_kwh",
+                train.regenerated_kwh,
+            );
+            row("train", id.clone(), "net_energy_kwh", train.net_energy_kwh);
+            row("train", id.clone(), "energy_per_km", train.energy_per_km);
+            row(
+                "train",
+                id,
+                "atp_interventions",
+                train.atp_interventions as f64,
+            );
+        }
+
+        for section in &self.sections {
+            let id = format!("{}:{}", section.structure, section.section);
+            row("section", id, "utilization", section.utilization);
+        }
+
+        for structure in &self.structures {
+            let id = structure.structure.to_string();
+            row("structure", id.clone(), "trains", structure.trains as f64);
+            row(
+                "structure",
+                id,
+                "trains_per_hour",
+                structure.trains_per_hour,
+            );
+        }
+
+        for switch in &self.switches {
+            row(
+                "switch",
+                switch.structure.to_string(),
+                "throws",
+                switch.throws as f64,
+            );
+        }
+
+        csv
+    }
+}
@@ -0,0 +1,127 @@
+use crate::train::train_structs::*;
+use crate::worker::{self, WorkerData};
+
+///Seconds a table needs to move from one track to the next
+pub const TRACK_TIME: f64 = 10.0;
+
+///Seconds a turntable needs to turn its bridge around
+pub const TURN_TIME: f64 = 60.0;
+
+///Align
+///Starts moving a table to a track, a turntable can turn its bridge around on the way
+///Returns false when the structure is no table, the track doesn't exist or the table is locked
+///A table is locked while it moves, while a train is partly on the bridge or moving on it and while
+///it is reserved for another train. A train that stands completely on the bridge moves with it
+pub fn align(data: &mut WorkerData, idx: usize, track: usize, turn: bool) -> bool {
+    let table = match data.train_system.structures.get(&idx) {
+        Some(StructureTypes::Table(table)) => table,
+        _ => return false,
+    };
+    if table.movement.is_some()
+        || track >= table.output_pins.len()
+        || (turn && table.table_type != TableType::Turntable)
+    {
+        return false;
+    }
+    if track == table.aligned && !turn {
+        return true;
+    }
+
+    let mut standing = None;
+    for train in data
+        .trains
+        .iter()
+        .filter(|train| train.path.iter().any(|step| step.structure == idx))
+    {
+        if train.path.len() > 1 || train.speed > 0.0 || standing.is_some() {
+            return false;
+        }
+        standing = Some(train.train_number);
+    }
+
+    let reserved = data
+        .train_system
+        .reservations
+        .get(&idx)
+        .is_some_and(|number| Some(*number) != standing);
+    let occupied = standing.is_none() && table.sections.iter().any(|section| section.active);
+    if reserved || occupied {
+        return false;
+    }
+
+    let tracks = (track as f64 - table.aligned as f64).abs();
+    let remaining = tracks * TRACK_TIME + if turn { TURN_TIME } else { 0.0 };
+
+    if let Some(StructureTypes::Table(table)) = data.train_system.structures.get_mut(&idx) {
+        table.movement = Some(TableMovement {
+            target: track,
+            turn,
+            remaining,
+        });
+    }
+
+    //The route of the train on the bridge led over the track the table leaves
+    for train in data
+        .trains
+        .iter_mut()
+        .filter(|train| Some(train.train_number) == standing)
+    {
+        train.route.clear();
+    }
+
+    true
+}
+
+///Update
+///Moves the tables for the elapsed seconds, a table that reaches its track is aligned to it
+///The train on the bridge now enters and leaves at the pins of the new track, after a turn the other way around
+pub fn update(data: &mut WorkerData, elapsed: f64) {
+    let mut arrived = vec![];
+
+    for (idx, structure) in data.train_system.structures.iter_mut() {
+        let table = match structure {
+            StructureTypes::Table(table) => table,
+            _ => continue,
+        };
+        let mut movement = match table.movement {
+            Some(movement) => movement,
+            None => continue,
+        };
+
+        movement.remaining -= elapsed;
+        if movement.remaining > 0.0 {
+            table.movement = Some(movement);
+            continue;
+        }
+
+        let from = table.alignment(table.aligned);
+        let to = table.alignment(movement.target);
+        table.aligned = movement.target;
+        table.movement = None;
+
+        if let (Some(from), Some(to)) = (from, to) {
+            arrived.push((*idx, from, to, movement.turn));
+        }
+    }
+
+    if arrived.is_empty() {
+        return;
+    }
+
+    let system = &data.train_system;
+    for (idx, from, to, turn) in arrived {
+        let to = if turn { (to.1, to.0) } else { to };
+        let moved = |pin: usize| if pin == from.0 { to.0 } else { to.1 };
+
+        for train in data.trains.iter_mut() {
+            if let Some(step) = train.path.iter_mut().find(|step| step.structure == idx) {
+                step.entry_pin = moved(step.entry_pin);
+                step.exit_pin = moved(step.exit_pin);
+                step.direction = system.structures[&idx].direction_from(step.entry_pin);
+                train.route.clear();
+            }
+        }
+    }
+
+    worker::update_sections(data);
+}
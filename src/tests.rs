@@ -1,13 +1,65 @@
 #[cfg(test)]
 use super::*;
 #[cfg(test)]
+use crate::api::{self, Api};
+#[cfg(test)]
+use crate::atp::{self, IncidentKind};
+#[cfg(test)]
+use crate::batch;
+#[cfg(test)]
+use crate::consist;
+#[cfg(test)]
+use crate::dcc;
+#[cfg(test)]
+use crate::deadlock;
+#[cfg(test)]
+use crate::diagram;
+#[cfg(test)]
+use crate::dispatcher;
+#[cfg(test)]
+use crate::driver;
+#[cfg(test)]
+use crate::faults::{self, Fault};
+#[cfg(test)]
+use crate::graph;
+#[cfg(test)]
+use crate::mqtt;
+#[cfg(test)]
+use crate::railml;
+#[cfg(test)]
+use crate::rest;
+#[cfg(test)]
+use crate::scenario;
+#[cfg(test)]
+use crate::scripting;
+#[cfg(test)]
+use crate::shunting;
+#[cfg(test)]
+use crate::stats;
+#[cfg(test)]
+use crate::tables;
+#[cfg(test)]
+use crate::train::geometry::Geometry;
+#[cfg(test)]
+use crate::train::physics;
+#[cfg(test)]
+use crate::train::routing;
+#[cfg(test)]
 use crate::train::train_structs::*;
 #[cfg(test)]
 use crate::utils::*;
 #[cfg(test)]
 use crate::worker::*;
 #[cfg(test)]
-use eframe::epaint::Color32;
+use eframe::emath::RectTransform;
+#[cfg(test)]
+use eframe::epaint::{Color32, Pos2, Rect, Vec2};
+#[cfg(test)]
+use futures::{SinkExt, StreamExt};
+#[cfg(test)]
+use std::collections::HashMap;
+#[cfg(test)]
+use std::path::Path;
 #[cfg(test)]
 use std::time::Duration;
 #[cfg(test)]
@@ -31,6 +83,7 @@ async fn test_set_interval() {
 ///Sections should always have a distance larger than 0 and no larger than the maximum distance
 ///Check item count as well
 #[test]
+#[allow(clippy::needless_range_loop, clippy::bool_assert_comparison)]
 fn test_generate_sections() {
     let collection = worker::generate_sections(2, true, 1000);
 
@@ -45,72 +98,2510 @@ fn test_generate_sections() {
     }
 }
 
+///Test Seeded Sections
+///The same seed should give the same sections, sections follow each other without gaps up to the total distance
+///Explicit boundaries replace the sections of a structure
+///A structure without length still gets a section, it is occupied by a train running over it
+#[test]
+fn test_seeded_sections() {
+    let collection = worker::seeded_sections(4, 42, 1000);
+    let again = worker::seeded_sections(4, 42, 1000);
+
+    assert_eq!(collection.len(), 4);
+    assert_eq!(collection[0].distance_start, 0);
+    assert_eq!(collection[3].distance_end, 1000);
+
+    for (item, other) in collection.iter().zip(again.iter()) {
+        assert_eq!(item.distance_start, other.distance_start);
+        assert_eq!(item.distance_end, other.distance_end);
+    }
+    for pair in collection.windows(2) {
+        assert_eq!(pair[0].distance_end, pair[1].distance_start);
+    }
+
+    let mut system = TrainSystem::new();
+    system.track(1000);
+    system.set_sections(0, &[100, 400], Detection::AxleCounter);
+
+    let sections = system.structures[&0].sections();
+    assert_eq!(sections.len(), 3);
+    assert_eq!(sections[1].distance_start, 100);
+    assert_eq!(sections[1].distance_end, 400);
+    assert_eq!(sections[2].detection, Detection::AxleCounter);
+
+    let sections = worker::boundary_sections(&[], 0, Detection::TrackCircuit);
+    assert_eq!(sections.len(), 1);
+    assert_eq!(sections[0].distance_end, 0);
+
+    let layout: scenario::Layout = serde_json::from_str(
+        r#"{
+            "structures": [
+                { "type": "track", "length": 100 },
+                { "type": "track", "length": 0, "sections": { "boundaries": [] } },
+                { "type": "track", "length": 100 }
+            ],
+            "connections": [["0:output", "1:input"], ["1:output", "2:input"]]
+        }"#,
+    )
+    .unwrap();
+    let mut train = Train::new(1, 50, 10.0);
+    let system = layout.build().unwrap();
+    train.place(&system, 2, Direction::Forward, 30.0);
+    let mut data = WorkerData {
+        train_system: system,
+        trains: vec![train],
+        ..Default::default()
+    };
+    update_sections(&mut data);
+    assert!(data.train_system.structures[&1].sections()[0].active);
+}
+
+///Test Axle Counter
+///An axle counter counts the axles of a 50M train in and out
+///When it is reset while the train is still inside, it counts out more axles than it counted in and becomes disturbed
+///A disturbed section stays active until it is reset again
+#[test]
+fn test_axle_counter() {
+    let mut system = TrainSystem::new();
+    system.track(1000);
+    system.set_sections(0, &[100, 400], Detection::AxleCounter);
+
+    let mut train = Train::new(1, 50, 100.0);
+    train.performance.acceleration = 1000.0;
+    train.performance.deceleration = 1000.0;
+    train.place(&system, 0, Direction::Forward, 60.0);
+
+    let mut data = WorkerData {
+        train_system: system,
+        trains: vec![train],
+        ..Default::default()
+    };
+    update_sections(&mut data);
+
+    let section = &data.train_system.structures[&0].sections()[0];
+    assert_eq!(section.axle_count, 8);
+    assert!(section.active);
+
+    //Reset while the train is on the section
+    if let Some(structure) = data.train_system.structures.get_mut(&0) {
+        structure.sections_mut()[0].reset();
+    }
+    update_sections(&mut data);
+    assert!(!data.train_system.structures[&0].sections()[0].active);
+
+    //The train moves to the next section, section 0 counts out 8 axles
+    update_train_positions(&mut data, 1.0);
+
+    let sections = data.train_system.structures[&0].sections();
+    assert!(sections[0].disturbed && sections[0].active);
+    assert_eq!(sections[1].axle_count, 8);
+    assert!(sections[1].active);
+
+    if let Some(structure) = data.train_system.structures.get_mut(&0) {
+        structure.sections_mut()[0].reset();
+    }
+    update_sections(&mut data);
+    assert!(!data.train_system.structures[&0].sections()[0].active);
+}
+
 ///Test Update Train Position
-///Train position and back of train should be positive values, within track length where position is a train length larger than back of train
-///We check that the train occupies the sections which are occupied with front or back of train and that all others are inactive
-///We check for correct train status at start, running and at the end of the track
-///Count = 0, train occupies first section, status = stopped
-///[---------|----------]
-///[|||||----|----------]
-///Count = 1, train occupies first and second section, status = running
-///[---------|----------]
-///[------|||||||-------]
-///Count = 2, train occupies second section, status = stopped
-///[---------|----------]
-///[---------|----||||||]
-///Count = 3 should be the same as previous so check for no changes
-//#[test]
-// fn test_update_train_position() {
-//     //Create our test data
-//     let mut data = WorkerData {
-//         count: 0,
-//         train_system: TrainSystem::new(),
-//     };
-
-//     //Go to next update so our sections are properly active
-//     update_train_position(&mut data);
-
-//     //Count = 0
-//     assert!(matches!(data.train.train_status, TrainStatus::Stopped));
-
-//     assert_eq!(data.track.sections[0].active, true);
-//     assert_eq!(data.track.sections[1].active, false);
-
-//     //Increment and go to next update
-//     data.count += 1;
-//     update_train_position(&mut data);
-
-//     //Count = 1
-//     assert!(matches!(data.train.train_status, TrainStatus::Running));
-
-//     assert_eq!(data.track.sections[0].active, true);
-//     assert_eq!(data.track.sections[1].active, true);
-
-//     //Go to final update
-//     data.count += 1;
-//     update_train_position(&mut data);
-
-//     //Count = 2
-//     assert!(matches!(data.train.train_status, TrainStatus::Stopped));
-
-//     assert_eq!(data.track.sections[0].active, false);
-//     assert_eq!(data.track.sections[1].active, true);
-
-//     //Check for same status
-//     data.count += 1;
-//     update_train_position(&mut data);
-
-//     //Count = 3
-//     assert!(matches!(data.train.train_status, TrainStatus::Stopped));
-
-//     assert_eq!(data.track.sections[0].active, false);
-//     assert_eq!(data.track.sections[1].active, true);
-// }
+///A train runs over a track, through a switch onto a dead end track, reverses and runs back
+///Layout, track 0 is 100M, the switch is 50M and tracks 2 and 3 are 200M
+///           /---[2]---|
+///[---0---]<S
+///           \---[3]---|
+///We check that the train occupies the sections under it, in both directions, also over structures of different lengths
+///At the terminus the train reverses, a trailing movement is only possible when the switch is set to our side
+#[test]
+fn test_update_train_position() {
+    let mut system = TrainSystem::new();
+    system.track(100);
+    system.switch(SwitchType::LeftSplitUp);
+    system.track(200);
+    system.track(200);
+    system.connect(1, 2);
+    system.connect(3, 5);
+    system.connect(4, 7);
+
+    //Accelerates and brakes almost instantly, so it runs at full speed every tick
+    let mut train = Train::new(1, 30, 50.0);
+    train.performance.acceleration = 1000.0;
+    train.performance.deceleration = 1000.0;
+    train.place(&system, 0, Direction::Forward, 40.0);
+
+    let mut data = WorkerData {
+        train_system: system,
+        trains: vec![train],
+        ..Default::default()
+    };
+    update_sections(&mut data);
+
+    //Train runs from 10 to 40 on track 0, sections are 20M
+    let sections = data.train_system.structures[&0].sections();
+    assert!(sections[0].active && sections[1].active);
+    assert!(!sections[2].active);
+    assert_eq!(sections[0].train_number, 1);
+
+    //A train 10M onto the 50M switch covers the last 20M of track 0
+    let mut other = Train::new(2, 30, 50.0);
+    other.place(&data.train_system, 1, Direction::Forward, 10.0);
+    let intervals: Vec<(usize, f64, f64)> = other
+        .occupied_intervals()
+        .iter()
+        .map(|(step, start, end)| (step.structure, *start, *end))
+        .collect();
+    assert_eq!(intervals, vec![(1, 0.0, 10.0), (0, 80.0, 100.0)]);
+    let (step, offset) = *other.axle_positions().last().unwrap();
+    assert_eq!((step.structure, offset), (0, 80.0));
+
+    //Two ticks bring the front of the train 40M onto the switch
+    update_train_positions(&mut data, 1.0);
+    update_train_positions(&mut data, 1.0);
+
+    let train = &data.trains[0];
+    assert!(matches!(train.train_status, TrainStatus::Running));
+    assert_eq!(train.path[0].structure, 1);
+    assert_eq!(train.direction(), Some(Direction::Forward));
+    assert!(data.train_system.structures[&0]
+        .sections()
+        .iter()
+        .all(|section| !section.active));
+    assert!(data.train_system.structures[&1]
+        .sections()
+        .iter()
+        .all(|section| section.active));
+
+    //Run to the end of track 2 and reverse
+    update_train_positions(&mut data, 4.0);
+    update_train_positions(&mut data, 1.0);
+
+    let train = &data.trains[0];
+    assert_eq!(train.path[0].structure, 2);
+    assert_eq!(train.direction(), Some(Direction::Reverse));
+    assert_eq!(train.head_offset, 30.0);
+    let sections = data.train_system.structures[&2].sections();
+    assert!(sections[4].active && !sections[3].active);
+
+    //The switch is set to the other track, so the train has to wait at the start of track 2
+    if let Some(StructureTypes::Switch(switch)) = data.train_system.structures.get_mut(&1) {
+        switch.position = SwitchPosition::Right;
+    }
+    update_train_positions(&mut data, 4.0);
+    update_train_positions(&mut data, 1.0);
+
+    let train = &data.trains[0];
+    assert!(matches!(train.train_status, TrainStatus::Stopped));
+    assert_eq!(train.path[0].structure, 2);
+    assert!(data.train_system.structures[&2].sections()[0].active);
+
+    //Set the switch back and the train continues over the switch towards track 0
+    if let Some(StructureTypes::Switch(switch)) = data.train_system.structures.get_mut(&1) {
+        switch.position = SwitchPosition::Left;
+    }
+    update_train_positions(&mut data, 1.0);
+
+    let train = &data.trains[0];
+    assert!(matches!(train.train_status, TrainStatus::Running));
+    assert_eq!(train.path[0].structure, 1);
+    assert_eq!(train.direction(), Some(Direction::Reverse));
+}
+
+///Test Find Route
+///Routes are searched regardless of the switch position and report the positions needed
+///Check a facing route over the right branch and a trailing route back from it
+#[test]
+fn test_find_route() {
+    let mut system = TrainSystem::new();
+    system.track(100);
+    system.switch(SwitchType::LeftSplitUp);
+    system.track(200);
+    system.track(200);
+    system.connect(1, 2);
+    system.connect(3, 5);
+    system.connect(4, 7);
+
+    let start = routing::step(&system, 0, 0).unwrap();
+    let route = routing::find_route(&system, &start, 3).unwrap();
+
+    let structures: Vec<usize> = route.steps.iter().map(|step| step.structure).collect();
+    assert_eq!(structures, vec![0, 1, 3]);
+    assert_eq!(route.switches, vec![(1, SwitchPosition::Right)]);
+    assert_eq!(route.length(), 350.0);
+
+    //Back from the end of track 3, this is a trailing movement over the switch
+    let start = routing::step(&system, 3, 8).unwrap();
+    let route = routing::find_route(&system, &start, 0).unwrap();
+
+    assert!(route
+        .steps
+        .iter()
+        .all(|step| step.direction == Direction::Reverse));
+    assert_eq!(route.switches, vec![(1, SwitchPosition::Right)]);
+    assert_eq!(route.steps[1].entry_pin, 4);
+}
+
+///Test Dispatch
+///Two approach tracks join over a switch onto a single track, both trains want to go there
+///           [---0---]\
+///                     S>[---3---]
+///           [---1---]/
+///Both trains wait at the signal at the end of their approach track, train 2 has the highest priority
+///Train 2 should get its route, the switch is thrown and reserved so train 1 stays in the queue
+///Once train 2 has left the switch it is released, but train 1 keeps waiting because track 3 is occupied
+#[test]
+fn test_dispatch() {
+    let mut system = TrainSystem::new();
+    system.track(300);
+    system.track(300);
+    system.switch(SwitchType::RightSplitDown);
+    system.track(300);
+    system.connect(1, 5);
+    system.connect(3, 6);
+    system.connect(4, 7);
+    system.signal(1);
+    system.signal(3);
+
+    let mut first = Train::new(1, 50, 20.0);
+    first.place(&system, 0, Direction::Forward, 290.0);
+    first.destination = Some(3);
+
+    let mut second = Train::new(2, 50, 20.0);
+    second.place(&system, 1, Direction::Forward, 290.0);
+    second.destination = Some(3);
+    second.priority = 5;
+    second.performance.acceleration = 1000.0;
+    second.performance.deceleration = 1000.0;
+
+    let mut data = WorkerData {
+        train_system: system,
+        trains: vec![first, second],
+        ..Default::default()
+    };
+    update_sections(&mut data);
+
+    dispatcher::dispatch(&mut data);
+
+    assert_eq!(data.dispatch_queue, vec![1]);
+    assert_eq!(data.train_system.reservations.get(&2), Some(&2));
+    assert_eq!(data.train_system.reservations.get(&3), Some(&2));
+    assert_eq!(data.train_system.signals[&1].aspect, Aspect::Proceed);
+    if let StructureTypes::Switch(switch) = &data.train_system.structures[&2] {
+        assert_eq!(switch.position, SwitchPosition::Right);
+    }
+
+    //Train 2 passes its signal which goes back to Stop, train 1 stops at its signal
+    update_train_positions(&mut data, 1.0);
+    assert_eq!(data.train_system.signals[&1].aspect, Aspect::Stop);
+
+    for _ in 0..20 {
+        dispatcher::dispatch(&mut data);
+        update_train_positions(&mut data, 1.0);
+    }
+
+    let first = &data.trains[0];
+    let second = &data.trains[1];
+    assert!(matches!(first.train_status, TrainStatus::Stopped));
+    assert_eq!(first.path[0].structure, 0);
+    assert_eq!(first.head_offset, 300.0);
+    assert!(matches!(second.train_status, TrainStatus::Stopped));
+    assert_eq!(second.path[0].structure, 3);
+
+    assert!(!data.train_system.reservations.contains_key(&2));
+    assert_eq!(data.dispatch_queue, vec![1]);
+}
+
+///Test Deadlock
+///Two stations joined by a single track, train 1 runs from west to east and train 2 from east to west
+///[---0---]S[-------1-------]S[---2---]
+///When train 1 is given the single track both trains wait for each other, which is detected as a deadlock
+///The dispatcher predicts this and gives the single track to neither of them
+#[test]
+fn test_deadlock() {
+    let mut system = TrainSystem::new();
+    system.track(300);
+    system.track(1000);
+    system.track(300);
+    system.connect(1, 2);
+    system.connect(3, 4);
+    system.signal(1);
+    system.signal(3);
+    system.signal(2);
+    system.signal(4);
+
+    let mut first = Train::new(1, 50, 20.0);
+    first.place(&system, 0, Direction::Forward, 290.0);
+    first.destination = Some(2);
+
+    let mut second = Train::new(2, 50, 20.0);
+    second.place(&system, 2, Direction::Reverse, 290.0);
+    second.destination = Some(0);
+
+    let mut data = WorkerData {
+        train_system: system,
+        trains: vec![first, second],
+        ..Default::default()
+    };
+    update_sections(&mut data);
+
+    //The dispatcher doesn't let either train onto the single track
+    dispatcher::dispatch(&mut data);
+    assert_eq!(data.dispatch_queue, vec![1, 2]);
+    assert!(data.train_system.reservations.is_empty());
+    assert!(deadlock::detect(&data).is_empty());
+
+    //Setting the route by hand leads to a deadlock
+    let segment = dispatcher::next_segment(&data.train_system, &data.trains[0]).unwrap();
+    assert!(deadlock::predicts_deadlock(
+        &data.train_system,
+        &data.trains,
+        1,
+        &segment,
+        0
+    ));
+    dispatcher::set_route(&mut data.train_system, &mut data.trains[0], &segment, 0);
+    assert_eq!(deadlock::detect(&data), vec![vec![1, 2]]);
+}
+
+///Test Strategies
+///The deadlock layout of the test above, both trains wait at the signals of the single track
+///First come first served gives the single track to the train that waited first and runs into the deadlock
+///The lookahead dispatcher doesn't, the priority dispatcher prefers train 2 which has the highest priority
+///A held train gets no route, a custom dispatcher only has to return commands
+#[test]
+fn test_strategies() {
+    let mut system = TrainSystem::new();
+    system.track(300);
+    system.track(1000);
+    system.track(300);
+    system.connect(1, 2);
+    system.connect(3, 4);
+    system.signal(1);
+    system.signal(3);
+    system.signal(2);
+    system.signal(4);
+
+    let mut first = Train::new(1, 50, 20.0);
+    first.place(&system, 0, Direction::Forward, 290.0);
+    first.destination = Some(2);
+
+    let mut second = Train::new(2, 50, 20.0);
+    second.place(&system, 2, Direction::Reverse, 290.0);
+    second.destination = Some(0);
+    second.priority = 1;
+
+    let mut start = WorkerData {
+        train_system: system,
+        trains: vec![first, second],
+        ..Default::default()
+    };
+    update_sections(&mut start);
+
+    let mut data = start.clone();
+    data.strategy = dispatcher::Strategy::FirstComeFirstServed;
+    dispatcher::dispatch(&mut data);
+    assert_eq!(data.train_system.reservations.get(&1), Some(&1));
+    assert_eq!(deadlock::detect(&data), vec![vec![1, 2]]);
+
+    let mut data = start.clone();
+    data.strategy = dispatcher::Strategy::Priority;
+    dispatcher::dispatch(&mut data);
+    assert_eq!(data.train_system.reservations.get(&1), Some(&2));
+    assert_eq!(data.dispatch_queue, vec![1]);
+
+    let mut data = start.clone();
+    dispatcher::dispatch(&mut data);
+    assert!(data.train_system.reservations.is_empty());
+
+    //Train 1 is held so first come first served gives the track to train 2
+    let mut data = start.clone();
+    data.strategy = dispatcher::Strategy::FirstComeFirstServed;
+    apply_command(&mut data, Command::HoldTrain(1, true));
+    dispatcher::dispatch(&mut data);
+    assert_eq!(data.train_system.reservations.get(&1), Some(&2));
+    assert!(data.dispatch_queue.is_empty());
+
+    struct Reverser;
+    impl dispatcher::Dispatcher for Reverser {
+        fn name(&self) -> &'static str {
+            "Reverser"
+        }
+        fn dispatch(&self, data: &WorkerData) -> Vec<Command> {
+            data.dispatch_queue
+                .iter()
+                .map(|number| Command::ReverseTrain(*number))
+                .collect()
+        }
+    }
+
+    let mut data = start.clone();
+    dispatcher::dispatch_with(&mut data, &Reverser);
+    assert_eq!(data.trains[0].direction(), Some(Direction::Reverse));
+    assert_eq!(data.trains[1].direction(), Some(Direction::Forward));
+}
+
+///Test Passing Loop
+///The same trains as the deadlock test, but now the single track has a passing loop halfway
+///                          /[---3---]\
+///[---0---]S[---1---]<S2          S5>[---6---]S[---7---]
+///                          \[---4---]/
+///The dispatcher should send the trains over different tracks of the loop so both reach their destination
+#[test]
+fn test_passing_loop() {
+    let mut system = TrainSystem::new();
+    system.track(300);
+    system.track(500);
+    system.switch(SwitchType::LeftSplitUp);
+    system.track(300);
+    system.track(300);
+    system.switch(SwitchType::LeftSplitUp);
+    system.track(500);
+    system.track(300);
+    system.connect(1, 2);
+    system.connect(3, 4);
+    system.connect(5, 7);
+    system.connect(6, 9);
+    system.connect(8, 12);
+    system.connect(10, 13);
+    system.connect(11, 14);
+    system.connect(15, 16);
+    //Signals for trains running east, then for trains running west
+    system.signal(1);
+    system.signal(8);
+    system.signal(10);
+    system.signal(16);
+    system.signal(7);
+    system.signal(9);
+
+    let mut first = Train::new(1, 50, 20.0);
+    first.place(&system, 0, Direction::Forward, 290.0);
+    first.destination = Some(7);
+
+    let mut second = Train::new(2, 50, 20.0);
+    second.place(&system, 7, Direction::Reverse, 290.0);
+    second.destination = Some(0);
+
+    let mut data = WorkerData {
+        train_system: system,
+        trains: vec![first, second],
+        ..Default::default()
+    };
+    update_sections(&mut data);
+
+    for _ in 0..150 {
+        dispatcher::dispatch(&mut data);
+        update_train_positions(&mut data, 1.0);
+        assert!(deadlock::detect(&data).is_empty());
+    }
+
+    assert_eq!(data.trains[0].path[0].structure, 7);
+    assert_eq!(data.trains[1].path[0].structure, 0);
+}
+
+///Test Scenario
+///Loads the passing loop example and runs it headless for ten minutes
+///Train 1 waits at its first stop until 08:01, train 2 departs at 08:00:30 and both reach the other end
+///The dispatcher is only switched on by a scripted event
+#[test]
+fn test_scenario() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios/passing_loop.json");
+    let mut data = scenario::load_scenario(&path).unwrap();
+
+    assert_eq!(data.time, parse_time("08:00").unwrap());
+    assert_eq!(data.trains.len(), 2);
+    assert!(!data.auto_dispatch);
+    if let StructureTypes::Switch(switch) = &data.train_system.structures[&2] {
+        assert_eq!(switch.position, SwitchPosition::Right);
+    }
+
+    run_headless(&mut data, 600.0, 1.0);
+
+    assert!(data.auto_dispatch);
+    assert!(data.events.is_empty());
+    assert_eq!(format_time(data.time), "08:10:00");
+
+    let first = &data.trains[0];
+    assert_eq!(first.path[0].structure, 7);
+    assert_eq!(first.timetable[0].actual_departure, parse_time("08:01"));
+    assert!(first.timetable[1].actual_arrival.is_some());
+
+    let second = &data.trains[1];
+    assert_eq!(second.path[0].structure, 0);
+    assert_eq!(second.timetable[0].actual_departure, parse_time("08:00:30"));
+    assert!(second.timetable[1].actual_arrival.is_some());
+}
+
+///Test Scenario Errors
+///Pins that don't exist, trains that can't be placed, positions a switch doesn't have, train numbers used twice
+///and trains that start on top of each other are reported as invalid
+#[test]
+fn test_scenario_errors() {
+    let json = r#"{
+        "layout": { "structures": [{ "type": "track", "length": 100 }], "connections": [["0:output", "1:input"]] }
+    }"#;
+    let scenario: scenario::Scenario = serde_json::from_str(json).unwrap();
+    assert!(matches!(
+        scenario.build(Path::new(".")),
+        Err(scenario::ScenarioError::Invalid(_))
+    ));
+
+    let json = r#"{
+        "layout": { "structures": [{ "type": "track", "length": 100 }] },
+        "trains": [{ "number": 1, "length": 50, "performance": { "max_speed": 10.0 }, "start": { "structure": 3 } }]
+    }"#;
+    let scenario: scenario::Scenario = serde_json::from_str(json).unwrap();
+    assert!(matches!(
+        scenario.build(Path::new(".")),
+        Err(scenario::ScenarioError::Invalid(_))
+    ));
+
+    let switches = |position: &str, structure: usize| {
+        let json = format!(
+            r#"{{
+                "layout": {{ "structures": [{{ "type": "track", "length": 100 }}, {{ "type": "switch", "switch_type": "LeftSplitUp" }}] }},
+                "switches": [{{ "structure": {}, "position": "{}" }}]
+            }}"#,
+            structure, position
+        );
+        let scenario: scenario::Scenario = serde_json::from_str(&json).unwrap();
+        match scenario.build(Path::new(".")) {
+            Err(scenario::ScenarioError::Invalid(error)) => error,
+            _ => String::new(),
+        }
+    };
+    assert_eq!(switches("Left", 1), "");
+    assert_eq!(switches("Left", 0), "structure 0 is not a switch");
+    assert_eq!(switches("Straight", 1), "switch 1 has no position Straight");
+
+    let json = r#"{
+        "layout": { "structures": [{ "type": "track", "length": 100 }, { "type": "track", "length": 100 }] },
+        "trains": [
+            { "number": 1, "length": 50, "performance": { "max_speed": 10.0 }, "start": { "structure": 0 } },
+            { "number": 1, "length": 50, "performance": { "max_speed": 10.0 }, "start": { "structure": 1 } }
+        ]
+    }"#;
+    let scenario: scenario::Scenario = serde_json::from_str(json).unwrap();
+    assert!(matches!(
+        scenario.build(Path::new(".")),
+        Err(scenario::ScenarioError::Invalid(error)) if error == "train number 1 is used more than once"
+    ));
+
+    //Trains may start end to end but not on top of each other
+    let overlapping = |offset: f64| {
+        let json = format!(
+            r#"{{
+                "layout": {{ "structures": [{{ "type": "track", "length": 100 }}, {{ "type": "track", "length": 100 }}], "connections": [["0:output", "1:input"]] }},
+                "trains": [
+                    {{ "number": 1, "length": 50, "performance": {{ "max_speed": 10.0 }}, "start": {{ "structure": 0, "offset": 90.0 }} }},
+                    {{ "number": 2, "length": 50, "performance": {{ "max_speed": 10.0 }}, "start": {{ "structure": 1, "offset": {} }} }}
+                ]
+            }}"#,
+            offset
+        );
+        let scenario: scenario::Scenario = serde_json::from_str(&json).unwrap();
+        matches!(
+            scenario.build(Path::new(".")),
+            Err(scenario::ScenarioError::Invalid(error)) if error == "trains 1 and 2 start on the same track"
+        )
+    };
+    assert!(overlapping(20.0));
+    assert!(!overlapping(40.0));
+
+    assert_eq!(parse_time("08:15:30"), Some(29730.0));
+    assert_eq!(parse_time("08:75"), None);
+}
+
+///Test Statistics
+///Runs the passing loop example where train 1 is planned to arrive at 07:59:30 instead of 08:03
+///Train 1 arrives more than three minutes late at its last stop so only half of its stops are punctual
+///Both trains travel the 1750M from their start to the end of the other side, the loop switches are thrown
+#[test]
+fn test_statistics() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios/passing_loop.json");
+    let mut data = scenario::load_scenario(&path).unwrap();
+    data.trains[0].timetable[1].arrival = parse_time("07:59:30");
+
+    run_headless(&mut data, 600.0, 1.0);
+    let report = stats::report(&data);
+
+    assert_eq!(report.duration, 600.0);
+    let first = &report.trains[0];
+    assert_eq!(first.stops, 2);
+    assert_eq!(first.punctual_stops, 1);
+    assert_eq!(first.punctuality, 50.0);
+    assert!(first.delay_minutes > 3.0);
+    assert_eq!(report.total_delay_minutes, first.delay_minutes);
+
+    for train in &report.trains {
+        assert!((train.distance - 1750.0).abs() < 1.0);
+        assert!(train.average_speed > 0.0 && train.average_speed <= 25.0);
+    }
+
+    assert!(report.switches.iter().any(|switch| switch.throws > 0));
+    assert!(report
+        .sections
+        .iter()
+        .all(|section| section.utilization >= 0.0 && section.utilization <= 100.0));
+    let track = report
+        .structures
+        .iter()
+        .find(|structure| structure.structure == 1)
+        .unwrap();
+    assert_eq!(track.trains, 2);
+
+    let csv = report.to_csv();
+    assert!(csv.starts_with("category,id,metric,value\n"));
+    assert!(csv.contains("train,1,punctuality,50\n"));
+    let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+    assert_eq!(json["trains"][1]["train_number"], 2);
+}
+
+///Test Batch
+///A sweep over 2 train counts, 2 lookahead depths and 2 seeds gives 8 runs, the results keep the order of the variants
+///Runs with the same seed give the same result, even though they run on different threads
+///A delayed train gets a start time instead of a fault, a seed sweep needs a delay
+#[test]
+fn test_batch() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios/passing_loop.json");
+    let data = scenario::load_scenario(&path).unwrap();
+
+    let batch = batch::Batch {
+        scenario: "passing_loop.json".to_string(),
+        duration: 300.0,
+        step: 1.0,
+        delay: 60.0,
+        sweep: batch::Sweep {
+            trains: vec![1, 2],
+            lookahead: vec![0, 4],
+            seed: vec![1, 2],
+            ..Default::default()
+        },
+    };
+
+    let variants = batch::variants(&batch.sweep);
+    assert_eq!(variants.len(), 8);
+    assert_eq!(variants[0].trains, Some(1));
+    assert_eq!(variants[0].headway, None);
+    assert_eq!(variants[7].seed, Some(2));
+
+    let results = batch::run_batch(&batch, &data);
+    assert_eq!(results.len(), 8);
+
+    for (i, result) in results.iter().enumerate() {
+        assert_eq!(result.run, i);
+        assert_eq!(result.variant, variants[i]);
+        assert!(!result.deadlock);
+        assert!(result.completed_trains <= result.variant.trains.unwrap());
+    }
+
+    //Lookahead doesn't matter for a single train, the seed does
+    assert_eq!(
+        results[0].total_delay_minutes,
+        results[2].total_delay_minutes
+    );
+    assert_eq!(results[4].completed_trains, 2);
+
+    let csv = batch::to_csv(&results);
+    assert_eq!(csv.lines().count(), 9);
+    assert!(csv.lines().nth(1).unwrap().starts_with("0,1,,,,,,0,1,"));
+
+    //Delayed trains stand still until their start time, they don't break down
+    let mut delayed = data.clone();
+    batch::apply_variant(&mut delayed, &variants[5], 60.0);
+    assert!(faults::active_faults(&delayed).is_empty());
+    assert_eq!(delayed.events.len(), data.events.len());
+    for train in &delayed.trains {
+        let start = train.start_at.unwrap();
+        assert!(start >= delayed.time && start < delayed.time + 60.0);
+        assert!(!train.broken_down);
+    }
+
+    //The seed only draws the start delays, a seed sweep without them is rejected
+    let file = std::env::temp_dir().join("trainsim_seed_batch.json");
+    let json = serde_json::json!({
+        "scenario": path.to_str().unwrap(),
+        "duration": 60.0,
+        "sweep": { "seed": [1, 2] }
+    });
+    std::fs::write(&file, json.to_string()).unwrap();
+    assert!(matches!(
+        batch::load_batch(&file),
+        Err(scenario::ScenarioError::Invalid(_))
+    ));
+    std::fs::remove_file(&file).unwrap();
+}
+
+///Test RailML
+///A main line with an outgoing and an incoming switch is split into three tracks and two switches
+///The branch between the switches becomes a track, the crossing, speed change and control points are reported
+///The passing loop exported to railML and imported again gives the same pins, connections and signals
+#[test]
+fn test_railml() {
+    let source = r#"<?xml version="1.0" encoding="UTF-8"?>
+<railml xmlns="https://www.railml.org/schemas/2013">
+  <infrastructure id="inf">
+    <tracks>
+      <track id="main">
+        <trackTopology>
+          <trackBegin id="main_begin" pos="0"><bufferStop id="bs1"/></trackBegin>
+          <trackEnd id="main_end" pos="1000"><openEnd id="oe1"/></trackEnd>
+          <connections>
+            <switch id="sw1" pos="200">
+              <connection id="sw1c" ref="bb" orientation="outgoing" course="right"/>
+            </switch>
+            <switch id="sw2" pos="800">
+              <connection id="sw2c" ref="be" orientation="incoming" course="left"/>
+            </switch>
+            <crossing id="cr1" pos="500"/>
+          </connections>
+        </trackTopology>
+        <trackElements>
+          <speedChanges><speedChange id="sc1" pos="0" dir="up" vMax="80"/></speedChanges>
+        </trackElements>
+        <ocsElements>
+          <signals>
+            <signal id="s1" pos="180" dir="up"/>
+            <signal id="s2" pos="820" dir="down"/>
+          </signals>
+        </ocsElements>
+      </track>
+      <track id="branch">
+        <trackTopology>
+          <trackBegin id="branch_begin" pos="0"><connection id="bb" ref="sw1c"/></trackBegin>
+          <trackEnd id="branch_end" pos="600"><connection id="be" ref="sw2c"/></trackEnd>
+        </trackTopology>
+      </track>
+    </tracks>
+    <operationControlPoints/>
+  </infrastructure>
+</railml>"#;
+
+    let import = railml::import(source).unwrap();
+    assert_eq!(import.system.structures.len(), 6);
+    assert_eq!(import.system.structures[&3].length(), 600);
+    assert_eq!(import.system.structures[&5].length(), 600);
+    assert!(matches!(
+        &import.system.structures[&0],
+        StructureTypes::Switch(Switch {
+            switch_type: SwitchType::RightSplitDown,
+            ..
+        })
+    ));
+
+    let connection = |a: &str, b: &str| {
+        import
+            .layout
+            .connections
+            .contains(&(a.to_string(), b.to_string()))
+            || import
+                .layout
+                .connections
+                .contains(&(b.to_string(), a.to_string()))
+    };
+    assert_eq!(import.layout.connections.len(), 6);
+    assert!(connection("2:output", "0:input"));
+    assert!(connection("0:left", "3:input"));
+    assert!(connection("3:output", "1:right"));
+    assert!(connection("1:input", "4:input"));
+    assert!(connection("0:right", "5:input"));
+    assert!(connection("5:output", "1:left"));
+    assert_eq!(import.layout.signals, vec!["2:output", "4:input"]);
+    assert_eq!(
+        import.unsupported,
+        vec![
+            "crossing cr1 on track main",
+            "speedChanges on track main",
+            "operationControlPoints"
+        ]
+    );
+
+    assert!(matches!(
+        railml::import("<railml><infrastructure><tracks><track id=\"t\"><trackTopology/></track></tracks></infrastructure></railml>"),
+        Err(scenario::ScenarioError::Railml(_))
+    ));
+
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("layouts/passing_loop.json");
+    let (system, unsupported) = scenario::load_layout(&path).unwrap();
+    assert!(unsupported.is_empty());
+    let export = railml::export(&system);
+    assert!(export.unsupported.is_empty());
+
+    let import = railml::import(&export.document).unwrap();
+    assert!(import.unsupported.is_empty());
+    assert_eq!(import.system.pins, system.pins);
+    assert_eq!(import.system.connections, system.connections);
+    let pins = |system: &TrainSystem| {
+        let mut pins: Vec<usize> = system.signals.values().map(|signal| signal.pin).collect();
+        pins.sort();
+        pins
+    };
+    assert_eq!(pins(&import.system), pins(&system));
+}
+
+///Test Diagrams
+///The passing loop graph has a node for each of the 8 structures and an edge for each of the 8 connections
+///Two minutes into the run both trains are drawn on occupied sections, the svg is valid xml
+///The app draws the same shapes as the svg
+#[test]
+fn test_diagrams() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios/passing_loop.json");
+    let mut data = scenario::load_scenario(&path).unwrap();
+
+    let dot = diagram::dot(&data.train_system);
+    assert!(dot.starts_with("graph layout {"));
+    assert_eq!(dot.matches("[label=").count(), 8);
+    assert_eq!(dot.matches(" -- ").count(), 8);
+    assert!(dot.contains("s0 -- s1 [taillabel=\"1 S\", headlabel=\"2\"];"));
+    assert!(dot.contains("s2 [label=\"Switch 2\\nLeftSplitUp\"];"));
+
+    run_headless(&mut data, 120.0, 1.0);
+    let svg = diagram::svg(&data);
+    let document = roxmltree::Document::parse(&svg).unwrap();
+    let count = |name: &str| {
+        document
+            .descendants()
+            .filter(|node| node.has_tag_name(name))
+            .count()
+    };
+    assert_eq!(count("polygon"), 2);
+    assert_eq!(count("circle"), 6);
+    assert!(svg.contains("stroke=\"#FFFF00\""));
+    assert!(svg.contains(&format!(">{}</text>", format_time(data.time))));
+
+    let geometry = Geometry::layout(&data.train_system);
+    let screen = Rect::from_min_size(Pos2::ZERO, Vec2::new(800.0, 400.0));
+    let drawing = diagram::draw(
+        &data,
+        &geometry,
+        &RectTransform::from_to(geometry.bounds(), screen),
+    );
+    assert_eq!(drawing.polygons.len(), 2);
+    assert_eq!(drawing.circles.len(), 6);
+    assert_eq!(count("line"), drawing.lines.len());
+    assert_eq!(
+        count("text"),
+        1 + drawing.labels.len() + data.train_system.structures.len()
+    );
+}
+
+///Test Train Graph
+///The corridor from structure 0 to 7 of the passing loop is 2000M long, structure 0 is travelled forward from 0M
+///Train 1 runs east so its line goes up the corridor, train 2 runs west so its line comes down
+///The planned line of train 1 departs at the end of structure 0 and arrives at the end of structure 7
+#[test]
+fn test_train_graph() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios/passing_loop.json");
+    let mut data = scenario::load_scenario(&path).unwrap();
+    run_headless(&mut data, 240.0, 1.0);
+
+    let corridor = graph::Corridor::new(&data.train_system, 0, 7).unwrap();
+    assert_eq!(corridor.length(), 2000.0);
+    assert_eq!(corridor.position(0, 100.0), Some(100.0));
+    assert_eq!(corridor.position(7, 300.0), Some(2000.0));
+    assert_eq!(corridor.span(1, 500.0, 0.0), Some((300.0, 800.0)));
+
+    let reversed = graph::Corridor::new(&data.train_system, 7, 0).unwrap();
+    assert_eq!(reversed.position(7, 300.0), Some(0.0));
+
+    //Samples at least every sample interval, more often when a train moves onto the next structure
+    let samples = &data.history.trains[&1];
+    assert!(samples.len() >= 240 / graph::SAMPLE_INTERVAL as usize);
+    assert!(samples
+        .windows(2)
+        .all(|pair| pair[1].time - pair[0].time <= graph::SAMPLE_INTERVAL));
+
+    //Train 1 takes track 4 of the loop, the corridor runs over track 3 so its line is broken in two
+    let first = corridor.actual(samples);
+    assert_eq!(first.len(), 2);
+    assert!(first[0].last().unwrap().1 <= 850.0);
+    assert!(first[1][0].1 >= 1150.0);
+    assert!(first
+        .iter()
+        .all(|line| line.windows(2).all(|pair| pair[1].1 >= pair[0].1)));
+
+    let second = corridor.actual(&data.history.trains[&2]);
+    assert!(second
+        .iter()
+        .all(|line| line.windows(2).all(|pair| pair[1].1 <= pair[0].1)));
+    assert!(second.last().unwrap().last().unwrap().1 < second[0][0].1);
+
+    assert_eq!(
+        corridor.planned(&data.trains[0]),
+        vec![
+            (parse_time("08:01").unwrap(), 300.0),
+            (parse_time("08:03").unwrap(), 2000.0)
+        ]
+    );
+
+    let blocks = corridor.occupied(&data.history.occupations(data.time), &data.train_system);
+    assert!(!blocks.is_empty());
+    assert!(blocks
+        .iter()
+        .all(|block| block.start <= block.end && block.low < block.high && block.high <= 2000.0));
+}
+
+///Test Crossings
+///A diamond crossing followed by a three way switch, built from a layout
+///          [---3---]\ /[---4---]
+///                    X
+///          [---0---]/ \[---2---]<[---6---]
+///                                 [---7---]
+///                                 [---8---]
+///Train 1 stands on the crossing, the cross path shares its section so train 2 can't get a route over it
+///Routes to the outputs of the three way switch need its three positions
+///A double slip switch has a position for each slip route, a single slip can't be set Right
+#[test]
+fn test_crossings() {
+    let json = r#"{
+        "structures": [
+            { "type": "track", "length": 100 },
+            { "type": "crossing" },
+            { "type": "track", "length": 100 },
+            { "type": "track", "length": 100 },
+            { "type": "track", "length": 100 },
+            { "type": "three_way" },
+            { "type": "track", "length": 100 },
+            { "type": "track", "length": 100 },
+            { "type": "track", "length": 100 }
+        ],
+        "connections": [
+            ["0:output", "1:input"], ["1:output", "2:input"],
+            ["3:output", "1:cross_input"], ["1:cross_output", "4:input"],
+            ["2:output", "5:input"], ["5:left", "6:input"],
+            ["5:straight", "7:input"], ["5:right", "8:input"]
+        ]
+    }"#;
+    let layout: scenario::Layout = serde_json::from_str(json).unwrap();
+    let system = layout.build().unwrap();
+
+    let crossing = &system.structures[&1];
+    assert_eq!(crossing.exits(2), vec![3]);
+    assert_eq!(crossing.exits(4), vec![5]);
+    assert_eq!(crossing.exit_pin(5), Some(4));
+    assert_eq!(crossing.position(), None);
+
+    let start = routing::step(&system, 0, 0).unwrap();
+    assert!(routing::find_route(&system, &start, 4).is_none());
+    let route = routing::find_route(&system, &start, 2).unwrap();
+    assert!(route.switches.is_empty());
+
+    for (target, position) in [
+        (6, SwitchPosition::Left),
+        (7, SwitchPosition::Straight),
+        (8, SwitchPosition::Right),
+    ] {
+        let route = routing::find_route(&system, &start, target).unwrap();
+        assert_eq!(route.switches, vec![(5, position)]);
+    }
+
+    let mut first = Train::new(1, 50, 20.0);
+    first.place(&system, 1, Direction::Forward, 30.0);
+    let mut data = WorkerData {
+        train_system: system,
+        trains: vec![first],
+        ..Default::default()
+    };
+    update_sections(&mut data);
+    assert!(!data.train_system.is_free(1, 2));
+
+    let mut second = Train::new(2, 50, 20.0);
+    second.place(&data.train_system, 3, Direction::Forward, 100.0);
+    let start = routing::step(&data.train_system, 3, 8).unwrap();
+    let route = routing::find_route(&data.train_system, &start, 4).unwrap();
+    assert!(!dispatcher::set_route(
+        &mut data.train_system,
+        &mut second,
+        &route,
+        0
+    ));
+
+    let system = &mut data.train_system;
+    assert!(!system.throw_switch(1, SwitchPosition::Left));
+    assert!(system.throw_switch(5, SwitchPosition::Left));
+    assert_eq!(system.structures[&5].exit_pin(12), Some(13));
+    assert_eq!(system.structures[&5].exit_pin(14), None);
+
+    assert!(diagram::dot(system).contains("Crossing 1"));
+    assert!(diagram::svg(&data).contains("<svg"));
+
+    let mut system = TrainSystem::new();
+    system.slip_switch(SlipType::Double);
+    system.slip_switch(SlipType::Single);
+
+    let slip = &system.structures[&0];
+    assert_eq!(
+        slip.positions(),
+        vec![
+            SwitchPosition::Straight,
+            SwitchPosition::Left,
+            SwitchPosition::Right
+        ]
+    );
+    assert_eq!(slip.exit_pin(0), Some(1));
+    assert_eq!(slip.exit_pin(2), Some(3));
+
+    assert!(system.throw_switch(0, SwitchPosition::Left));
+    assert_eq!(system.structures[&0].exit_pin(0), Some(3));
+    assert_eq!(system.structures[&0].exit_pin(2), None);
+    assert!(system.throw_switch(0, SwitchPosition::Right));
+    assert_eq!(system.structures[&0].exit_pin(2), Some(1));
+    assert_eq!(system.structures[&0].exit_pin(0), None);
+
+    assert!(!system.throw_switch(1, SwitchPosition::Right));
+    assert!(system.throw_switch(1, SwitchPosition::Left));
+}
+
+///Test Tables
+///A turntable with three tracks at the end of a track, it starts aligned to track 2
+///                   /[---2---]
+///[---0---]>(--1--)-[---3---]
+///                   \[---4---]
+///Train 1 runs onto the bridge and stops there, the table is locked while it is reserved for another train
+///The table turns around to track 4, which takes time in which the train can't move
+///Afterwards the train faces the input and leaves back onto track 0
+///A transfer table moves its bridge between parallel tracks and can't turn
+#[test]
+fn test_tables() {
+    let json = r#"{
+        "structures": [
+            { "type": "track", "length": 100 },
+            { "type": "turntable", "length": 30, "tracks": 3 },
+            { "type": "track", "length": 100 },
+            { "type": "track", "length": 100 },
+            { "type": "track", "length": 100 }
+        ],
+        "connections": [
+            ["0:output", "1:input"], ["1:output_0", "2:input"],
+            ["1:output_1", "3:input"], ["1:output_2", "4:input"]
+        ]
+    }"#;
+    let layout: scenario::Layout = serde_json::from_str(json).unwrap();
+    let system = layout.build().unwrap();
+
+    assert_eq!(system.structures[&1].paths(), vec![(2, 3, None)]);
+    assert_eq!(system.structures[&1].exit_pin(4), None);
+    let start = routing::step(&system, 0, 0).unwrap();
+    assert!(routing::find_route(&system, &start, 3).is_none());
+    assert!(routing::find_route(&system, &start, 2).is_some());
+
+    let mut train = Train::new(1, 20, 10.0);
+    train.place(&system, 0, Direction::Forward, 100.0);
+    train.destination = Some(1);
+    let mut data = WorkerData {
+        train_system: system,
+        trains: vec![train],
+        ..Default::default()
+    };
+
+    update_train_positions(&mut data, 1.0);
+    assert!(!tables::align(&mut data, 1, 2, true));
+    for _ in 0..30 {
+        update_train_positions(&mut data, 1.0);
+    }
+    assert_eq!(data.trains[0].path.len(), 1);
+    assert_eq!(data.trains[0].head_offset, 30.0);
+
+    data.train_system.reservations.insert(1, 7);
+    assert!(!tables::align(&mut data, 1, 2, true));
+    data.train_system.reservations.remove(&1);
+    assert!(!data.train_system.throw_switch(1, SwitchPosition::Left));
+
+    assert!(tables::align(&mut data, 1, 2, true));
+    assert!(!tables::align(&mut data, 1, 1, false));
+    assert!(data.train_system.structures[&1].is_moving());
+    assert!(!data.train_system.is_free(1, 1));
+    data.trains[0].destination = Some(0);
+    assert_eq!(
+        movement_authority(&data.train_system, &data.trains[0], 100.0),
+        0.0
+    );
+
+    tables::update(&mut data, 10.0);
+    assert!(data.train_system.structures[&1].paths().is_empty());
+    tables::update(&mut data, 70.0);
+    assert_eq!(data.train_system.structures[&1].paths(), vec![(2, 5, None)]);
+
+    let front = data.trains[0].path[0];
+    assert_eq!((front.entry_pin, front.exit_pin), (5, 2));
+    assert_eq!(front.direction, Direction::Reverse);
+
+    for _ in 0..30 {
+        update_train_positions(&mut data, 1.0);
+    }
+    assert_eq!(data.trains[0].path[0].structure, 0);
+    assert_eq!(data.trains[0].direction(), Some(Direction::Reverse));
+
+    let mut system = TrainSystem::new();
+    system.transfer_table(20, 2);
+    let mut data = WorkerData {
+        train_system: system,
+        ..Default::default()
+    };
+    let table = &data.train_system.structures[&0];
+    assert_eq!(table.named_pin("input_1"), Some(1));
+    assert_eq!(table.named_pin("output_1"), Some(3));
+    assert_eq!(table.paths(), vec![(0, 2, None)]);
+
+    assert!(!tables::align(&mut data, 0, 1, true));
+    assert!(tables::align(&mut data, 0, 1, false));
+    tables::update(&mut data, tables::TRACK_TIME);
+    assert_eq!(data.train_system.structures[&0].paths(), vec![(1, 3, None)]);
+    assert!(diagram::svg(&data).contains("<svg"));
+}
+
+///Test Consists
+///A locomotive with two coaches on a long track, the length and performance follow from the vehicles
+///The coaches are uncoupled into train 2, which can't accelerate without a locomotive
+///Train 1 can't couple to train 3 further down the track, but it couples to the coaches right behind it
+///The joined train travels towards the coaches with the locomotive at its back
+#[test]
+fn test_consists() {
+    let locomotive = Vehicle {
+        kind: VehicleKind::Locomotive,
+        length: 20,
+        mass: 80.0,
+        tractive_effort: 240.0,
+        brake_force: 160.0,
+        max_speed: 30.0,
+        traction: Traction::Electric,
+    };
+    let coach = Vehicle {
+        kind: VehicleKind::Coach,
+        length: 25,
+        mass: 40.0,
+        tractive_effort: 0.0,
+        brake_force: 40.0,
+        max_speed: 40.0,
+        traction: Traction::Electric,
+    };
+
+    let mut system = TrainSystem::new();
+    system.track(500);
+
+    let train = Train::with_consist(1, vec![locomotive, coach, coach]);
+    assert_eq!(train.train_length, 70);
+    assert_eq!(train.axles, 12);
+    assert_eq!(train.mass(), 160.0);
+    assert_eq!(train.performance.max_speed, 30.0);
+    assert_eq!(train.performance.acceleration, 1.5);
+    assert_eq!(train.performance.deceleration, 1.5);
+
+    let mut trains = vec![train, Train::with_consist(3, vec![coach])];
+    trains[0].place(&system, 0, Direction::Forward, 200.0);
+    trains[1].place(&system, 0, Direction::Forward, 450.0);
+    let mut data = WorkerData {
+        train_system: system,
+        trains,
+        ..Default::default()
+    };
+    update_sections(&mut data);
+
+    assert!(!consist::uncouple(&mut data, 1, 1, 3));
+    assert!(!consist::uncouple(&mut data, 1, 0, 2));
+    assert!(consist::uncouple(&mut data, 1, 1, 2));
+
+    let rear = &data.trains[2];
+    assert_eq!(rear.train_number, 2);
+    assert_eq!(rear.train_length, 50);
+    assert_eq!(rear.head_offset, 180.0);
+    assert_eq!(rear.performance.acceleration, 0.0);
+    assert_eq!(data.trains[0].train_length, 20);
+
+    assert!(!consist::couple(&mut data, 1, 3));
+    assert!(consist::couple(&mut data, 1, 2));
+    assert_eq!(data.trains.len(), 2);
+
+    let joined = &data.trains[0];
+    assert_eq!(joined.train_number, 1);
+    assert_eq!(joined.train_length, 70);
+    assert_eq!(joined.direction(), Some(Direction::Reverse));
+    assert_eq!(joined.path[0].structure_distance(joined.head_offset), 130.0);
+    assert_eq!(joined.consist.last().unwrap().kind, VehicleKind::Locomotive);
+
+    let json = r#"{ "number": 4, "start": { "structure": 0 } }"#;
+    let definition: scenario::TrainDefinition = serde_json::from_str(json).unwrap();
+    assert!(matches!(
+        definition.build(&data.train_system),
+        Err(scenario::ScenarioError::Invalid(_))
+    ));
+    let json = r#"{ "number": 4, "start": { "structure": 0, "offset": 100.0 }, "consist": [
+        { "kind": "Locomotive", "length": 20, "mass": 80.0, "tractive_effort": 240.0, "brake_force": 160.0, "max_speed": 30.0 },
+        { "kind": "Freight", "length": 15, "mass": 60.0, "brake_force": 40.0, "max_speed": 25.0 }
+    ] }"#;
+    let definition: scenario::TrainDefinition = serde_json::from_str(json).unwrap();
+    let train = definition.build(&data.train_system).unwrap();
+    assert_eq!(train.train_length, 35);
+    assert_eq!(train.performance.max_speed, 25.0);
+}
+
+///Test Shunting
+///Train 1 stands on siding 2 of a yard ladder and is moved to siding 3, where train 2 stands at the buffer stop
+///It runs into the headshunt, reverses and runs onto siding 3, never faster than the shunting speed
+///It stops just behind train 2 so the two can be coupled, the shunt signal only holds shunting trains
+#[test]
+fn test_shunting() {
+    let json = r#"{
+        "structures": [
+            { "type": "track", "length": 100 },
+            { "type": "switch", "switch_type": "LeftSplitUp" },
+            { "type": "track", "length": 150 },
+            { "type": "track", "length": 150 }
+        ],
+        "connections": [["0:output", "1:input"], ["1:left", "2:input"], ["1:right", "3:input"]],
+        "shunt_signals": ["0:output"]
+    }"#;
+    let layout: scenario::Layout = serde_json::from_str(json).unwrap();
+    let mut system = layout.build().unwrap();
+    assert!(system.throw_switch(1, SwitchPosition::Right));
+
+    let locomotive = Vehicle {
+        kind: VehicleKind::Locomotive,
+        length: 20,
+        mass: 80.0,
+        tractive_effort: 240.0,
+        brake_force: 160.0,
+        max_speed: 30.0,
+        traction: Traction::Electric,
+    };
+    let wagon = Vehicle {
+        kind: VehicleKind::Freight,
+        length: 15,
+        mass: 60.0,
+        tractive_effort: 0.0,
+        brake_force: 40.0,
+        max_speed: 25.0,
+        traction: Traction::Electric,
+    };
+
+    let mut shunter = Train::with_consist(1, vec![locomotive, wagon, wagon]);
+    shunter.place(&system, 2, Direction::Reverse, 100.0);
+    let mut standing = Train::with_consist(2, vec![wagon, wagon]);
+    standing.place(&system, 3, Direction::Forward, 150.0);
+    standing.destination = Some(3);
+
+    let legs = shunting::plan(&system, &shunter, 3).unwrap();
+    let structures = |leg: &routing::Route| -> Vec<usize> {
+        leg.steps.iter().map(|step| step.structure).collect()
+    };
+    assert_eq!(legs.len(), 2);
+    assert_eq!(structures(&legs[0]), vec![2, 1, 0]);
+    assert_eq!(structures(&legs[1]), vec![0, 1, 3]);
+    assert_eq!(legs[0].switches, vec![(1, SwitchPosition::Left)]);
+    assert_eq!(legs[1].switches, vec![(1, SwitchPosition::Right)]);
+
+    let mut data = WorkerData {
+        train_system: system,
+        trains: vec![shunter, standing],
+        ..Default::default()
+    };
+    update_sections(&mut data);
+
+    apply_command(&mut data, Command::Shunt(1, 3));
+    assert!(data.trains[0].shunting);
+
+    let mut reversed = false;
+    for _ in 0..300 {
+        tick(&mut data, 1.0);
+        assert!(data.trains[0].speed <= SHUNT_SPEED);
+        reversed |= data.trains[0].path[0].structure == 0
+            && data.train_system.signals[&0].aspect == Aspect::Shunt;
+    }
+    assert!(reversed);
+
+    let shunter = &data.trains[0];
+    assert!(shunter.shunt_move.is_none());
+    assert_eq!(shunter.path[0].structure, 3);
+    assert_eq!(shunter.direction(), Some(Direction::Forward));
+    assert!((shunter.head_offset - (120.0 - shunting::SHUNT_MARGIN)).abs() < 0.01);
+    assert_eq!(data.train_system.signals[&0].aspect, Aspect::Stop);
+
+    assert!(consist::couple(&mut data, 1, 2));
+    assert_eq!(data.trains.len(), 1);
+    assert_eq!(data.trains[0].train_length, 80);
+
+    let signal = data.train_system.signals[&0];
+    assert_eq!(signal.kind, SignalKind::Shunt);
+    assert!(signal.stops(true));
+    assert!(!signal.stops(false));
+    let main = Signal {
+        kind: SignalKind::Main,
+        aspect: Aspect::Shunt,
+        ..signal
+    };
+    assert!(!main.stops(true));
+    assert!(main.stops(false));
+}
+
+///Test Profile
+///A heavy freight train climbing 30 per mille runs slower than on the level and needs longer for the same track
+///On 40 per mille it can't start at all, running downhill it needs a longer distance to stop
+///The gradient is averaged over the length of the train, a curve adds to the resistance
+///The profile survives a railML export and import
+#[test]
+fn test_profile() {
+    let locomotive = Vehicle {
+        kind: VehicleKind::Locomotive,
+        length: 20,
+        mass: 80.0,
+        tractive_effort: 240.0,
+        brake_force: 160.0,
+        max_speed: 30.0,
+        traction: Traction::Electric,
+    };
+    let wagon = Vehicle {
+        kind: VehicleKind::Freight,
+        length: 15,
+        mass: 60.0,
+        tractive_effort: 0.0,
+        brake_force: 40.0,
+        max_speed: 25.0,
+        traction: Traction::Electric,
+    };
+    let consist = [vec![locomotive], vec![wagon; 10]].concat();
+
+    let layout = |profile: &[ProfileSegment]| {
+        let mut system = TrainSystem::new();
+        system.track(3000);
+        system.set_profile(0, profile);
+        system
+    };
+    let level = |gradient: f64| ProfileSegment {
+        start: 0.0,
+        gradient,
+        radius: None,
+    };
+
+    //Seconds to the end of the track and the top speed on the way
+    let run = |gradient: f64| {
+        let system = layout(&[level(gradient)]);
+        let mut train = Train::with_consist(1, consist.clone());
+        train.place(&system, 0, Direction::Forward, 200.0);
+        train.destination = Some(0);
+        let mut data = WorkerData {
+            train_system: system,
+            trains: vec![train],
+            ..Default::default()
+        };
+
+        let mut top: f64 = 0.0;
+        for _ in 0..1000 {
+            tick(&mut data, 1.0);
+            top = top.max(data.trains[0].speed);
+            if data.trains[0].head_offset >= 3000.0 - ARRIVAL_MARGIN && data.trains[0].speed == 0.0
+            {
+                return (data.time, top);
+            }
+        }
+        (f64::INFINITY, top)
+    };
+
+    let (flat_time, flat_top) = run(0.0);
+    let (climb_time, climb_top) = run(30.0);
+    assert_eq!(flat_top, 25.0);
+    assert!(climb_top < 20.0);
+    assert!(climb_time > flat_time + 60.0);
+    assert_eq!(run(40.0), (f64::INFINITY, 0.0));
+
+    let mut train = Train::with_consist(1, consist.clone());
+    let system = layout(&[level(-20.0)]);
+    train.place(&system, 0, Direction::Forward, 1000.0);
+    let downhill = physics::effective_performance(&system, &train);
+    train.place(&system, 0, Direction::Reverse, 1000.0);
+    let uphill = physics::effective_performance(&system, &train);
+    let system = layout(&[]);
+    train.place(&system, 0, Direction::Forward, 1000.0);
+    let flat = physics::effective_performance(&system, &train);
+    assert!(downhill.braking_distance(20.0) > flat.braking_distance(20.0));
+    assert!(uphill.braking_distance(20.0) < flat.braking_distance(20.0));
+
+    //Half of the train is on the level, half on 10 per mille
+    let system = layout(&[
+        level(0.0),
+        ProfileSegment {
+            start: 100.0,
+            ..level(10.0)
+        },
+    ]);
+    train.place(&system, 0, Direction::Forward, 185.0);
+    let resistance = physics::profile_resistance(&system, &train);
+    assert!((resistance - physics::GRAVITY * 0.005).abs() < 1e-9);
+
+    let curve = ProfileSegment {
+        radius: Some(500.0),
+        ..level(0.0)
+    };
+    let system = layout(&[curve]);
+    train.place(&system, 0, Direction::Forward, 1000.0);
+    assert!(
+        (physics::profile_resistance(&system, &train) - physics::GRAVITY / 1000.0).abs() < 1e-9
+    );
+
+    let profile = [
+        level(10.0),
+        ProfileSegment {
+            start: 100.0,
+            gradient: -5.0,
+            radius: Some(400.0),
+        },
+    ];
+    let system = layout(&profile);
+    let import = railml::import(&railml::export(&system).document).unwrap();
+    assert!(import.unsupported.is_empty());
+    assert_eq!(import.system.structures[&0].profile(), profile);
+}
+
+///Test Energy
+///An electric freight train that runs 3 km and stops at the end of the track needs at least the energy to get to speed,
+///braking it feeds part of that back. A diesel train burns more than twice as much and regenerates nothing
+///An electric train on a track without overhead line has no power and can't start, a diesel train can
+///A train that is stopped in a single tick regenerates no more than its brakes could, one without power draws nothing
+#[test]
+fn test_energy() {
+    let locomotive = Vehicle {
+        kind: VehicleKind::Locomotive,
+        length: 20,
+        mass: 80.0,
+        tractive_effort: 240.0,
+        brake_force: 160.0,
+        max_speed: 30.0,
+        traction: Traction::Electric,
+    };
+    let wagon = Vehicle {
+        kind: VehicleKind::Freight,
+        length: 15,
+        mass: 60.0,
+        tractive_effort: 0.0,
+        brake_force: 40.0,
+        max_speed: 25.0,
+        traction: Traction::Electric,
+    };
+
+    let run = |traction: Traction, electrified: bool| {
+        let json = match electrified {
+            true => r#"{ "structures": [{ "type": "track", "length": 3000 }] }"#,
+            false => {
+                r#"{ "structures": [{ "type": "track", "length": 3000 }], "non_electrified": [0] }"#
+            }
+        };
+        let layout: scenario::Layout = serde_json::from_str(json).unwrap();
+        let system = layout.build().unwrap();
+
+        let locomotive = Vehicle {
+            traction,
+            ..locomotive
+        };
+        let mut train = Train::with_consist(1, [vec![locomotive], vec![wagon; 10]].concat());
+        train.place(&system, 0, Direction::Forward, 200.0);
+        train.destination = Some(0);
+        let mut data = WorkerData {
+            train_system: system,
+            trains: vec![train],
+            ..Default::default()
+        };
+        run_headless(&mut data, 600.0, 1.0);
+        stats::report(&data)
+    };
+
+    let electric = run(Traction::Electric, true);
+    let train = &electric.trains[0];
+    let kinetic = 0.5 * 680_000.0 * 25.0 * 25.0 / 3_600_000.0;
+    assert_eq!(train.traction, Traction::Electric);
+    assert!(train.distance > 2790.0);
+    assert!(train.energy_kwh * physics::ELECTRIC_EFFICIENCY > kinetic);
+    assert!(train.regenerated_kwh > 0.0);
+    assert!(train.regenerated_kwh < kinetic * physics::REGENERATION_EFFICIENCY);
+    assert!(train.net_energy_kwh < train.energy_kwh);
+    assert!((train.energy_per_km - train.net_energy_kwh / train.distance * 1000.0).abs() < 1e-9);
+    assert_eq!(electric.total_energy_kwh, train.energy_kwh);
+    assert!(electric.to_csv().contains("train,1,net_energy_kwh,"));
+
+    let diesel = run(Traction::Diesel, true);
+    assert_eq!(diesel.trains[0].regenerated_kwh, 0.0);
+    assert!(diesel.trains[0].energy_kwh > 2.0 * train.energy_kwh);
+
+    assert_eq!(run(Traction::Electric, false).trains[0].distance, 0.0);
+    assert!(run(Traction::Diesel, false).trains[0].distance > 2790.0);
+
+    let layout: scenario::Layout = serde_json::from_str(
+        r#"{ "structures": [{ "type": "track", "length": 3000 }, { "type": "track", "length": 3000 }], "connections": [["0:output", "1:input"]], "non_electrified": [1] }"#,
+    )
+    .unwrap();
+    let system = layout.build().unwrap();
+    let mut train = Train::with_consist(1, [vec![locomotive], vec![wagon; 10]].concat());
+    train.place(&system, 0, Direction::Forward, 1000.0);
+
+    //Stopped from 20 m/s in one tick, only what the brakes can do over the 10m is regenerated
+    let braked = 680_000.0 * train.performance.deceleration * 10.0 / 3_600_000.0;
+    let energy = physics::energy(&system, &train, 20.0, 10.0, 1.0);
+    assert!(energy.regenerated > 0.0);
+    assert!(energy.regenerated <= braked * physics::REGENERATION_EFFICIENCY + 1e-9);
+
+    train.place(&system, 1, Direction::Forward, 1000.0);
+    train.speed = 10.0;
+    assert_eq!(
+        physics::energy(&system, &train, 9.0, 9.5, 1.0),
+        physics::Energy::default()
+    );
+}
+
+///Test Driver
+///An eco driver cruises below the maximum speed, brakes softly and uses less energy than an aggressive one
+///A timetable driver coasts when it is ahead and still arrives on time
+#[test]
+fn test_driver() {
+    let locomotive = Vehicle {
+        kind: VehicleKind::Locomotive,
+        length: 20,
+        mass: 80.0,
+        tractive_effort: 240.0,
+        brake_force: 160.0,
+        max_speed: 30.0,
+        traction: Traction::Electric,
+    };
+    let wagon = Vehicle {
+        kind: VehicleKind::Coach,
+        length: 20,
+        mass: 40.0,
+        tractive_effort: 0.0,
+        brake_force: 40.0,
+        max_speed: 30.0,
+        traction: Traction::Electric,
+    };
+
+    //Arrival time, highest speed, hardest braking and the report of a run along a 3 km track
+    let run = |driver: Driver, due: Option<f64>| {
+        let layout: scenario::Layout =
+            serde_json::from_str(r#"{ "structures": [{ "type": "track", "length": 3000 }] }"#)
+                .unwrap();
+        let system = layout.build().unwrap();
+
+        let mut train = Train::with_consist(1, [vec![locomotive], vec![wagon; 5]].concat());
+        train.place(&system, 0, Direction::Forward, 120.0);
+        train.destination = Some(0);
+        train.driver = driver;
+        if let Some(due) = due {
+            train.timetable = vec![TimetableStop {
+                structure: 0,
+                arrival: Some(due),
+                departure: None,
+                actual_arrival: None,
+                actual_departure: None,
+            }];
+        }
+        let mut data = WorkerData {
+            train_system: system,
+            trains: vec![train],
+            ..Default::default()
+        };
+
+        let mut arrival = None;
+        let mut top: f64 = 0.0;
+        let mut braking: f64 = 0.0;
+        for _ in 0..1200 {
+            let last = data.trains[0].speed;
+            tick(&mut data, 0.5);
+            let train = &data.trains[0];
+            top = top.max(train.speed);
+            braking = braking.max((last - train.speed) / 0.5);
+            if arrival.is_none() && train.speed == 0.0 && train.head_offset > 2990.0 {
+                arrival = Some(data.time);
+            }
+        }
+        (arrival.unwrap(), top, braking, stats::report(&data))
+    };
+
+    let (aggressive, top, hard, report) = run(Driver::Aggressive, None);
+    assert!(top > 29.0);
+
+    let (eco, cruise, soft, eco_report) = run(Driver::Eco, None);
+    assert!(eco > aggressive);
+    assert!(cruise <= 30.0 * driver::ECO_CRUISE + 0.5);
+    assert!(soft < hard);
+    assert!(eco_report.trains[0].net_energy_kwh < report.trains[0].net_energy_kwh);
+
+    let due = aggressive + 60.0;
+    let (arrival, _, _, timetable_report) = run(Driver::Timetable, Some(due));
+    assert!(arrival > aggressive);
+    assert!(arrival <= due + 5.0);
+    assert!(timetable_report.trains[0].net_energy_kwh < report.trains[0].net_energy_kwh);
+
+    let (late, _, _, _) = run(Driver::Timetable, Some(0.0));
+    assert_eq!(late, aggressive);
+}
+
+///Test ATP
+///A scenario runs without interventions, a train braking with its driver never gets too fast
+///A speed limit that drops while the train runs fast applies the emergency brake until the train stands still
+///A signal that fails right in front of a train can't be stopped for, the train passes it at danger
+///An aggressive driver that brakes too late downhill is stopped by the emergency brake
+#[test]
+fn test_atp() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios/passing_loop.json");
+    let mut data = scenario::load_scenario(&path).unwrap();
+    run_headless(&mut data, 600.0, 1.0);
+    assert!(data.statistics.incidents.is_empty());
+
+    let mut system = TrainSystem::new();
+    system.track(2000);
+    system.track(1000);
+    system.connect(1, 2);
+    system.signal(1);
+    system.signals.get_mut(&0).unwrap().aspect = Aspect::Proceed;
+
+    let mut train = Train::new(1, 50, 20.0);
+    train.place(&system, 0, Direction::Forward, 100.0);
+    train.destination = Some(1);
+    let start = WorkerData {
+        train_system: system,
+        trains: vec![train],
+        ..Default::default()
+    };
+
+    let mut data = start.clone();
+    while data.trains[0].speed < 20.0 {
+        tick(&mut data, 0.5);
+    }
+    apply_command(&mut data, Command::SetSpeed(1, Some(5.0)));
+    tick(&mut data, 0.5);
+    assert_eq!(data.trains[0].atp, AtpStatus::Emergency);
+    assert_eq!(data.statistics.incidents[0].kind, IncidentKind::Overspeed);
+    assert!(data.notifications.iter().any(|notification| matches!(
+        notification,
+        Notification::AtpIntervention { incident } if incident.train == 1
+    )));
+
+    let mut last = data.trains[0].speed;
+    while data.trains[0].speed > 0.0 {
+        tick(&mut data, 0.5);
+        let deceleration = (last - data.trains[0].speed) / 0.5;
+        //Emergency braking with the default deceleration, the running resistance adds a little
+        assert!(deceleration <= atp::EMERGENCY_FACTOR * 1.05);
+        last = data.trains[0].speed;
+    }
+    for _ in 0..60 {
+        tick(&mut data, 0.5);
+    }
+    assert_eq!(data.trains[0].atp, AtpStatus::Normal);
+    assert!(data.trains[0].speed > 4.0 && data.trains[0].speed <= 5.0);
+    assert_eq!(stats::report(&data).trains[0].atp_interventions, 1);
+
+    let mut data = start;
+    while data.trains[0].head_offset < 1900.0 {
+        tick(&mut data, 0.5);
+    }
+    apply_command(&mut data, Command::InjectFault(Fault::SignalFailure(0)));
+    for _ in 0..120 {
+        tick(&mut data, 0.5);
+    }
+    let kinds: Vec<IncidentKind> = data
+        .statistics
+        .incidents
+        .iter()
+        .map(|incident| incident.kind)
+        .collect();
+    assert_eq!(
+        kinds,
+        vec![
+            IncidentKind::Overspeed,
+            IncidentKind::SignalPassedAtDanger(0)
+        ]
+    );
+    assert_eq!(data.trains[0].path[0].structure, 1);
+    assert_eq!(data.trains[0].speed, 0.0);
+    assert_eq!(data.trains[0].atp, AtpStatus::Tripped);
+    assert!(stats::report(&data)
+        .to_csv()
+        .contains("train,1,atp_interventions,2"));
+
+    apply_command(&mut data, Command::ResetAtp(1));
+    tick(&mut data, 0.5);
+    assert_eq!(data.trains[0].atp, AtpStatus::Normal);
+    assert!(data.trains[0].speed > 0.0);
+
+    //Downhill an aggressive driver counts on its full brakes and brakes too late for the end of the track
+    let descent = |driver: Driver| {
+        let mut system = TrainSystem::new();
+        system.track(3000);
+        system.set_profile(
+            0,
+            &[ProfileSegment {
+                start: 0.0,
+                gradient: -40.0,
+                radius: None,
+            }],
+        );
+        let mut train = Train::new(1, 50, 20.0);
+        train.place(&system, 0, Direction::Forward, 100.0);
+        train.destination = Some(0);
+        train.driver = driver;
+        let mut data = WorkerData {
+            train_system: system,
+            trains: vec![train],
+            ..Default::default()
+        };
+
+        let mut emergency_stop = false;
+        for _ in 0..600 {
+            let braking = data.trains[0].atp == AtpStatus::Emergency;
+            tick(&mut data, 0.5);
+            emergency_stop |= braking && data.trains[0].speed == 0.0;
+        }
+        (data.statistics.incidents, emergency_stop)
+    };
+
+    let (incidents, emergency_stop) = descent(Driver::Aggressive);
+    assert_eq!(incidents.len(), 1);
+    assert_eq!(incidents[0].kind, IncidentKind::Overspeed);
+    assert!(incidents[0].speed > incidents[0].permitted + atp::INTERVENTION_MARGIN);
+    assert!(emergency_stop);
+
+    assert_eq!(descent(Driver::Eco), (vec![], false));
+}
+
+///Test Notifications
+///Arrivals, departures and routes show up as notifications in the tick they happened
+#[test]
+fn test_notifications() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios/passing_loop.json");
+    let mut data = scenario::load_scenario(&path).unwrap();
+
+    let mut notifications = vec![];
+    for _ in 0..600 {
+        tick(&mut data, 1.0);
+        notifications.append(&mut data.notifications);
+    }
+
+    assert!(notifications.contains(&Notification::Departed {
+        train: 2,
+        structure: 7,
+        time: parse_time("08:00:30").unwrap(),
+    }));
+    assert!(notifications
+        .iter()
+        .any(|n| matches!(n, Notification::RouteSet { train: 1, .. })));
+    assert!(notifications.iter().any(|n| matches!(
+        n,
+        Notification::Arrived {
+            train: 1,
+            structure: 7,
+            ..
+        }
+    )));
+
+    apply_command(&mut data, Command::InjectFault(Fault::SignalFailure(0)));
+    assert_eq!(
+        data.notifications,
+        vec![Notification::FaultInjected {
+            fault: Fault::SignalFailure(0)
+        }]
+    );
+}
+
+///Test Api
+///A WebSocket client sends commands as json to the worker channel and receives the published state
+///Quit and invalid commands are answered with an error
+#[tokio::test]
+async fn test_api() {
+    use tokio_tungstenite::tungstenite::Message;
+
+    let (transmitter, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let api = Api::new(transmitter);
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(api::serve(listener, api.clone()));
+
+    let (mut socket, _) = tokio_tungstenite::connect_async(format!("ws://{}", address))
+        .await
+        .unwrap();
+
+    let command = r#"{ "ThrowSwitch": [2, "Left"] }"#;
+    socket
+        .send(Message::Text(command.to_string()))
+        .await
+        .unwrap();
+    assert!(matches!(
+        receiver.recv().await,
+        Some(Command::ThrowSwitch(2, SwitchPosition::Left))
+    ));
+
+    let mut replies = vec![];
+    for command in [r#""Quit""#, r#"{ "Jump": 1 }"#] {
+        socket
+            .send(Message::Text(command.to_string()))
+            .await
+            .unwrap();
+        let reply = socket.next().await.unwrap().unwrap();
+        replies.push(serde_json::from_str::<serde_json::Value>(reply.to_text().unwrap()).unwrap());
+    }
+    assert!(replies.iter().all(|reply| reply["type"] == "error"));
+
+    //The client is subscribed now, a notification is sent before the state
+    let mut data = WorkerData::default();
+    data.train_system.track(100);
+    data.notifications
+        .push(Notification::Deadlock { trains: vec![1, 2] });
+    api.publish(&data);
+
+    let mut messages = vec![];
+    for _ in 0..2 {
+        let message = socket.next().await.unwrap().unwrap();
+        messages
+            .push(serde_json::from_str::<serde_json::Value>(message.to_text().unwrap()).unwrap());
+    }
+    assert_eq!(messages[0]["type"], "notification");
+    assert_eq!(messages[0]["notification"]["Deadlock"]["trains"][1], 2);
+    assert_eq!(messages[1]["type"], "state");
+    assert_eq!(messages[1]["state"]["structures"][0]["length"], 100);
+}
+
+///Test Rest
+///The endpoints answer from the published data, control requests are passed to the worker channel
+///A speed limit caps the speed of a train until it is removed
+///Request lines, headers and bodies that are too long, too many headers and clients that stall are refused
+///Query values are percent-decoded
+#[tokio::test]
+async fn test_rest() {
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let (transmitter, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let api = Api::new(transmitter);
+    let request = |method: &str, path: &str, body: &str| {
+        let (path, query) = path.split_once('?').unwrap_or((path, ""));
+        rest::Request {
+            method: method.to_string(),
+            path: path.to_string(),
+            query: query
+                .split_once('=')
+                .map(|(key, value)| vec![(key.to_string(), value.to_string())])
+                .unwrap_or_default(),
+            body: body.to_string(),
+        }
+    };
+    assert_eq!(
+        rest::handle(&api, &request("GET", "/clock", "")).status,
+        503
+    );
+
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios/passing_loop.json");
+    let mut data = scenario::load_scenario(&path).unwrap();
+    api.publish(&data);
+
+    let json = |response: rest::Response| {
+        assert_eq!(response.status, 200);
+        serde_json::from_str::<serde_json::Value>(&response.body).unwrap()
+    };
+    assert_eq!(
+        json(rest::handle(&api, &request("GET", "/clock", "")))["time_of_day"],
+        "08:00:00"
+    );
+    assert_eq!(
+        json(rest::handle(&api, &request("GET", "/trains", "")))
+            .as_array()
+            .unwrap()
+            .len(),
+        2
+    );
+    assert_eq!(
+        json(rest::handle(&api, &request("GET", "/structures/2", "")))["kind"],
+        "switch"
+    );
+    assert_eq!(
+        json(rest::handle(&api, &request("GET", "/switches", "")))[0]["position"],
+        "Right"
+    );
+    let occupied = json(rest::handle(
+        &api,
+        &request("GET", "/sections?occupied=true", ""),
+    ));
+    assert!(occupied
+        .as_array()
+        .unwrap()
+        .iter()
+        .all(|section| section["active"] == true));
+    assert!(!occupied.as_array().unwrap().is_empty());
+
+    assert_eq!(
+        rest::handle(&api, &request("GET", "/structures/99", "")).status,
+        404
+    );
+    assert_eq!(
+        rest::handle(&api, &request("DELETE", "/clock", "")).status,
+        405
+    );
+    assert_eq!(
+        rest::handle(&api, &request("POST", "/switches/0", "{}")).status,
+        404
+    );
+    assert_eq!(
+        rest::handle(&api, &request("POST", "/switches/2", "{}")).status,
+        400
+    );
+
+    let response = rest::handle(
+        &api,
+        &request("POST", "/switches/2", r#"{"position":"Left"}"#),
+    );
+    assert_eq!(response.status, 202);
+    assert!(matches!(
+        receiver.recv().await,
+        Some(Command::ThrowSwitch(2, SwitchPosition::Left))
+    ));
+
+    let response = rest::handle(
+        &api,
+        &request("POST", "/trains/2/speed", r#"{"speed":5.0}"#),
+    );
+    assert_eq!(response.status, 202);
+    let command = receiver.recv().await.unwrap();
+    assert!(matches!(command, Command::SetSpeed(2, Some(_))));
+
+    apply_command(&mut data, command);
+    for _ in 0..60 {
+        tick(&mut data, 1.0);
+        assert!(data.trains[1].speed <= 5.0);
+    }
+    apply_command(&mut data, Command::SetSpeed(2, None));
+    for _ in 0..30 {
+        tick(&mut data, 1.0);
+    }
+    assert!(data.trains[1].speed > 5.0);
+
+    //A real request over the socket
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(rest::serve(listener, api.clone()));
+
+    let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+    stream
+        .write_all(b"GET /clock HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .await
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).await.unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK"));
+    assert!(response.ends_with(r#""time_of_day":"08:00:00","count":0}"#));
+
+    //Lines and headers beyond the limits are not buffered, the request is refused
+    let send = |request: Vec<u8>| async move {
+        let mut stream = tokio::net::TcpStream::connect(address).await.unwrap();
+        stream.write_all(&request).await.unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    };
+    let long_line = [b"GET /".to_vec(), vec![b'a'; rest::MAX_LINE - 4]].concat();
+    assert!(send(long_line)
+        .await
+        .starts_with("HTTP/1.1 414 URI Too Long"));
+    let long_header = [
+        b"GET /clock HTTP/1.1\r\nX-Long: ".to_vec(),
+        vec![b'a'; rest::MAX_LINE - 7],
+    ]
+    .concat();
+    assert!(send(long_header)
+        .await
+        .starts_with("HTTP/1.1 431 Request Header Fields Too Large"));
+    let many_headers = [
+        b"GET /clock HTTP/1.1\r\n".to_vec(),
+        b"X-Header: a\r\n".repeat(rest::MAX_HEADERS),
+    ]
+    .concat();
+    assert!(send(many_headers)
+        .await
+        .starts_with("HTTP/1.1 431 Request Header Fields Too Large"));
+    let large_body = b"POST /switches/2 HTTP/1.1\r\nContent-Length: 1000000\r\n".to_vec();
+    assert!(send(large_body)
+        .await
+        .starts_with("HTTP/1.1 413 Payload Too Large"));
+
+    //A client that stalls is answered once the timeout passed
+    tokio::time::pause();
+    assert!(send(b"GET /clock HTTP/1.1\r\n".to_vec())
+        .await
+        .starts_with("HTTP/1.1 408 Request Timeout"));
+
+    assert_eq!(rest::decode("a%20b+c%2Fd%zz"), "a b c/d%zz");
+}
+
+///Test Dcc Turnouts
+///A three way switch drives two turnouts, one thrown for Left and one for Right, both closed for Straight
+///Without a second address the mapping is rejected
+#[test]
+fn test_dcc_turnouts() {
+    let json = r#"{
+        "structures": [
+            { "type": "track", "length": 100 },
+            { "type": "three_way" },
+            { "type": "track", "length": 100 },
+            { "type": "track", "length": 100 },
+            { "type": "track", "length": 100 }
+        ],
+        "connections": [
+            ["0:output", "1:input"], ["1:left", "2:input"],
+            ["1:straight", "3:input"], ["1:right", "4:input"]
+        ]
+    }"#;
+    let layout: scenario::Layout = serde_json::from_str(json).unwrap();
+    let mut data = WorkerData {
+        train_system: layout.build().unwrap(),
+        ..Default::default()
+    };
+    let config: dcc::DccConfig = serde_json::from_value(serde_json::json!({
+        "connection": { "type": "tcp", "address": "127.0.0.1:2560" },
+        "turnouts": [{ "switch": 1, "address": 20, "second_address": 21 }]
+    }))
+    .unwrap();
+    assert!(config.validate(&data.train_system).is_ok());
+
+    let mut turnouts = vec![];
+    for position in [
+        SwitchPosition::Left,
+        SwitchPosition::Straight,
+        SwitchPosition::Right,
+    ] {
+        assert!(data.train_system.throw_switch(1, position));
+        let outputs = config.outputs(&data);
+        turnouts.push((outputs.turnouts[&20], outputs.turnouts[&21]));
+    }
+    assert_eq!(turnouts, vec![(true, false), (false, false), (false, true)]);
+
+    let single: dcc::DccConfig = serde_json::from_value(serde_json::json!({
+        "connection": { "type": "tcp", "address": "127.0.0.1:2560" },
+        "turnouts": [{ "switch": 1, "address": 20 }]
+    }))
+    .unwrap();
+    assert!(matches!(
+        single.validate(&data.train_system),
+        Err(scenario::ScenarioError::Invalid(_))
+    ));
+    assert!(single.outputs(&data).turnouts.is_empty());
+}
+
+///Test Dcc
+///The bridge mirrors switches and trains onto a fake command station over TCP
+///Sensor reports of the layout come back as commands and keep their section occupied
+///The loco of a train that is gone is stopped, noise from the station is dropped
+#[tokio::test]
+async fn test_dcc() {
+    use tokio::io::AsyncWriteExt;
+
+    assert_eq!(dcc::parse_sensor("<Q 7>"), Some((7, true)));
+    assert_eq!(dcc::parse_sensor("q 12"), Some((12, false)));
+    assert_eq!(dcc::parse_sensor("<H 1 0>"), None);
+    assert_eq!(dcc::speed_step(10.0, 20.0), 63);
+    assert_eq!(dcc::speed_step(30.0, 20.0), dcc::MAX_SPEED_STEP);
+    assert_eq!(dcc::turnout_command(10, true), "<T 10 1>");
+    assert_eq!(dcc::throttle_command(1, 3, 63, false), "<t 1 3 63 0>");
+
+    let station = dcc::FakeStation::start("127.0.0.1:0").await.unwrap();
+    let config: dcc::DccConfig = serde_json::from_value(serde_json::json!({
+        "connection": { "type": "tcp", "address": station.address.to_string() },
+        "turnouts": [{ "switch": 2, "address": 10 }, { "switch": 5, "address": 11, "inverted": true }],
+        "locos": [{ "train": 1, "address": 3 }],
+        "sensors": [{ "sensor": 7, "structure": 1, "section": 1 }]
+    }))
+    .unwrap();
+
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios/passing_loop.json");
+    let mut data = scenario::load_scenario(&path).unwrap();
+    let (transmitter, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let bridge = dcc::start(config, transmitter);
+
+    //Wait until the station got what we expect, the connection runs in the background
+    async fn until(check: impl Fn() -> bool) {
+        for _ in 0..200 {
+            if check() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+        panic!("The command station didn't get the expected commands");
+    }
+
+    bridge.publish(&data);
+    until(|| station.throttles.lock().unwrap().contains_key(&3)).await;
+    assert!(station.turnouts.lock().unwrap()[&10]);
+    assert!(station.turnouts.lock().unwrap()[&11]);
+    assert_eq!(station.throttles.lock().unwrap()[&3], (0, true));
+    assert_eq!(station.received.lock().unwrap()[0], "<1>");
+
+    apply_command(&mut data, Command::ThrowSwitch(2, SwitchPosition::Left));
+    for _ in 0..5 {
+        tick(&mut data, 1.0);
+    }
+    bridge.publish(&data);
+    until(|| !station.turnouts.lock().unwrap()[&10]).await;
+    until(|| station.throttles.lock().unwrap()[&3].0 > 0).await;
+
+    //Only changes are sent, publishing the same state again sends nothing
+    let received = station.received.lock().unwrap().len();
+    bridge.publish(&data);
+    tokio::time::sleep(Duration::from_millis(50)).await;
+    assert_eq!(station.received.lock().unwrap().len(), received);
+
+    station.report_sensor(7, true);
+    let command = receiver.recv().await.unwrap();
+    assert!(matches!(command, Command::SetSensor(1, 1, true)));
+    apply_command(&mut data, command);
+    assert!(data.train_system.structures[&1].sections()[1].active);
+
+    station.report_sensor(7, false);
+    apply_command(&mut data, receiver.recv().await.unwrap());
+    assert!(!data.train_system.structures[&1].sections()[1].active);
+
+    //The loco of a train that is gone is stopped
+    data.trains.retain(|train| train.train_number != 1);
+    bridge.publish(&data);
+    until(|| station.throttles.lock().unwrap()[&3].0 == 0).await;
+
+    //Noise without the end of a frame is dropped, the next report still comes through
+    let config: dcc::DccConfig = serde_json::from_value(serde_json::json!({
+        "connection": { "type": "tcp", "address": station.address.to_string() },
+        "sensors": [{ "sensor": 7, "structure": 1, "section": 1 }]
+    }))
+    .unwrap();
+    let (stream, mut other) = tokio::io::duplex(1024);
+    let (transmitter, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+    let (_outputs, outputs) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move { dcc::run(stream, &config, transmitter, outputs).await });
+    other
+        .write_all(&vec![b'x'; dcc::MAX_FRAME * 40])
+        .await
+        .unwrap();
+    other.write_all(b"<Q 7>").await.unwrap();
+    assert!(matches!(
+        receiver.recv().await,
+        Some(Command::SetSensor(1, 1, true))
+    ));
+}
+
+///Test Mqtt
+///The state is published to retained topics and command topics turn into commands
+///The topic of a train that is gone is cleared, runs against a broker in this process
+#[tokio::test]
+async fn test_mqtt() {
+    use rumqttc::{AsyncClient, Event, MqttOptions, Packet, QoS};
+
+    assert_eq!(
+        mqtt::MqttConfig::parse("broker").unwrap().port,
+        mqtt::DEFAULT_PORT
+    );
+    assert!(mqtt::MqttConfig::parse(":1883").is_none());
+    assert!(mqtt::MqttConfig::parse("broker:port").is_none());
+
+    let port = 20000 + (std::process::id() % 10000) as u16;
+    let config = mqtt::MqttConfig::parse(&format!("127.0.0.1:{}", port)).unwrap();
+
+    assert!(matches!(
+        config.command("trainsim/switches/2/set", b"Left"),
+        Some(Command::ThrowSwitch(2, SwitchPosition::Left))
+    ));
+    assert!(matches!(
+        config.command("trainsim/trains/1/speed/set", b"12.5"),
+        Some(Command::SetSpeed(1, Some(_)))
+    ));
+    assert!(matches!(
+        config.command("trainsim/trains/1/destination/set", b"none"),
+        Some(Command::SetDestination(1, None))
+    ));
+    assert!(matches!(
+        config.command("trainsim/command", br#"{ "HoldTrain": [2, true] }"#),
+        Some(Command::HoldTrain(2, true))
+    ));
+    assert!(config.command("trainsim/command", br#""Quit""#).is_none());
+    assert!(config.command("trainsim/switches/2/set", b"Up").is_none());
+    assert!(config.command("other/switches/2/set", b"Left").is_none());
+
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios/passing_loop.json");
+    let data = scenario::load_scenario(&path).unwrap();
+    let topics = config.topics(&data);
+    assert_eq!(topics["trainsim/clock"], "08:00:00");
+    assert_eq!(topics["trainsim/switches/2"], "Right");
+    assert_eq!(topics["trainsim/signals/0"], "Stop");
+    assert_eq!(topics["trainsim/sections/0/4"], "occupied");
+    let train: serde_json::Value = serde_json::from_str(&topics["trainsim/trains/2"]).unwrap();
+    assert_eq!(train["structure"], 7);
+
+    let broker: rumqttd::Config = serde_json::from_value(serde_json::json!({
+        "id": 0,
+        "router": {
+            "max_connections": 10,
+            "max_outgoing_packet_count": 200,
+            "max_segment_size": 1048576,
+            "max_segment_count": 10
+        },
+        "v4": {
+            "1": {
+                "name": "v4-1",
+                "listen": format!("127.0.0.1:{}", port),
+                "next_connection_delay_ms": 1,
+                "connections": {
+                    "connection_timeout_ms": 60000,
+                    "max_payload_size": 20480,
+                    "max_inflight_count": 100,
+                    "dynamic_filters": true
+                }
+            }
+        }
+    }))
+    .unwrap();
+    std::thread::spawn(move || {
+        let _ = rumqttd::Broker::new(broker).start();
+    });
+
+    let (transmitter, mut commands) = tokio::sync::mpsc::unbounded_channel();
+    let publisher = mqtt::start(config, transmitter);
+
+    //A display that subscribes to everything
+    let options = MqttOptions::new("display", "127.0.0.1", port);
+    let (client, mut eventloop) = AsyncClient::new(options, 10);
+    client
+        .subscribe("trainsim/#", QoS::AtMostOnce)
+        .await
+        .unwrap();
+    let (messages, mut received) = tokio::sync::mpsc::unbounded_channel();
+    tokio::spawn(async move {
+        loop {
+            match eventloop.poll().await {
+                Ok(Event::Incoming(Packet::Publish(message))) => {
+                    let payload = String::from_utf8_lossy(&message.payload).to_string();
+                    let _ = messages.send((message.topic, payload));
+                }
+                Ok(_) => {}
+                Err(_) => tokio::time::sleep(Duration::from_millis(50)).await,
+            }
+        }
+    });
+
+    //Publish until the display has the position of the switch, the connections are made in the background
+    let mut topics = HashMap::new();
+    for _ in 0..100 {
+        publisher.publish(&data);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        while let Ok((topic, payload)) = received.try_recv() {
+            topics.insert(topic, payload);
+        }
+        if topics.contains_key("trainsim/switches/2") {
+            break;
+        }
+    }
+    assert_eq!(topics["trainsim/switches/2"], "Right");
+    assert_eq!(topics["trainsim/clock"], "08:00:00");
+
+    //Send the command until the simulation is subscribed
+    let mut command = None;
+    for _ in 0..100 {
+        client
+            .publish("trainsim/switches/2/set", QoS::AtMostOnce, false, "Left")
+            .await
+            .unwrap();
+        if let Ok(Some(received)) =
+            tokio::time::timeout(Duration::from_millis(50), commands.recv()).await
+        {
+            command = Some(received);
+            break;
+        }
+    }
+    assert!(matches!(
+        command,
+        Some(Command::ThrowSwitch(2, SwitchPosition::Left))
+    ));
+
+    //The topic of a train that is gone is cleared
+    let mut data = data;
+    data.trains.retain(|train| train.train_number != 2);
+    publisher.publish(&data);
+    let mut cleared = false;
+    for _ in 0..100 {
+        match tokio::time::timeout(Duration::from_millis(50), received.recv()).await {
+            Ok(Some((topic, payload))) if topic == "trainsim/trains/2" && payload.is_empty() => {
+                cleared = true;
+                break;
+            }
+            Ok(Some(_)) => {}
+            _ => publisher.publish(&data),
+        }
+    }
+    assert!(cleared);
+}
+
+///Test Scripting
+///The scripted passing loop has no dispatcher, a script sets the routes for the waiting trains
+///A script sees the state of the simulation and issues commands, errors are kept and don't stop the simulation
+///Clones of a script, as in a batch, run side by side without picking up each other's commands
+#[test]
+fn test_scripting() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scenarios/passing_loop_scripted.json");
+    let mut data = scenario::load_scenario(&path).unwrap();
+    assert!(!data.auto_dispatch);
+
+    run_headless(&mut data, 600.0, 1.0);
+    assert!(data.script.as_ref().unwrap().error.is_none());
+    assert_eq!(data.trains[0].path[0].structure, 7);
+    assert_eq!(data.trains[1].path[0].structure, 0);
+
+    //Throw a switch once train 1 has arrived on track 7
+    let source = r#"
+        fn on_tick(state) {
+            if state.trains[0].structure == 7 && state.structures[5].position == "Left" {
+                throw_switch(5, "Right");
+            }
+        }
+    "#;
+    data.script = Some(scripting::compile("test", source).unwrap());
+    tick(&mut data, 1.0);
+    if let StructureTypes::Switch(switch) = &data.train_system.structures[&5] {
+        assert_eq!(switch.position, SwitchPosition::Right);
+    }
+
+    data.script =
+        Some(scripting::compile("test", "fn on_tick(state) { state.trains[9].number }").unwrap());
+    tick(&mut data, 1.0);
+    assert!(data.script.as_ref().unwrap().error.is_some());
+
+    //A typo is an error, the switch is not thrown
+    let source = r#"fn on_tick(state) { throw_switch(5, "Stright"); }"#;
+    data.script = Some(scripting::compile("test", source).unwrap());
+    tick(&mut data, 1.0);
+    assert!(data.script.as_ref().unwrap().error.is_some());
+    assert_eq!(
+        data.train_system.structures[&5].position(),
+        Some(SwitchPosition::Right)
+    );
+
+    //Endless loops and recursion are stopped, the worker keeps running
+    for source in [
+        "fn on_tick(state) { loop {} }",
+        "fn deeper(n) { deeper(n + 1) } fn on_tick(state) { deeper(0) }",
+    ] {
+        data.script = Some(scripting::compile("test", source).unwrap());
+        tick(&mut data, 1.0);
+        assert!(data.script.as_ref().unwrap().error.is_some());
+    }
+
+    assert!(scripting::compile("test", "fn on_tick(state) {").is_err());
+
+    //Clones run side by side keep their commands apart
+    let source = r#"
+        fn on_tick(state) {
+            if state.time < 43200.0 { set_driver(1, "Eco"); } else { set_driver(1, "Aggressive"); }
+        }
+    "#;
+    data.script = Some(scripting::compile("test", source).unwrap());
+    let mut morning = data.clone();
+    morning.time = 0.0;
+    let mut evening = data.clone();
+    evening.time = 50000.0;
+    std::thread::scope(|scope| {
+        for (data, driver) in [
+            (&mut morning, Driver::Eco),
+            (&mut evening, Driver::Aggressive),
+        ] {
+            scope.spawn(move || {
+                for _ in 0..500 {
+                    scripting::run(data);
+                    assert_eq!(data.trains[0].driver, driver);
+                }
+            });
+        }
+    });
+}
+
+///Test Faults
+///Uses the passing loop layout, the loop tracks are 3 and 4 between switches 2 and 5
+///A stuck switch or one without detection can't be thrown, a failed signal can't be cleared
+///A false occupancy blocks a section without a train, a train routes around a closed track
+///A train that breaks down at speed brakes to a stand and doesn't move until the fault is cleared
+///Faults can be scripted as commands
+#[test]
+fn test_faults() {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("layouts/passing_loop.json");
+    let (system, _) = scenario::load_layout(&path).unwrap();
+
+    let mut train = Train::new(1, 50, 20.0);
+    train.place(&system, 0, Direction::Forward, 200.0);
+    train.destination = Some(7);
+
+    let mut data = WorkerData {
+        train_system: system,
+        trains: vec![train],
+        ..Default::default()
+    };
+    update_sections(&mut data);
+
+    assert!(faults::inject(&mut data, Fault::SwitchStuck(2)));
+    assert!(!data.train_system.throw_switch(2, SwitchPosition::Right));
+    assert!(faults::clear(&mut data, Fault::SwitchStuck(2)));
+    assert!(data.train_system.throw_switch(2, SwitchPosition::Right));
+
+    faults::inject(&mut data, Fault::SwitchDetectionLost(2));
+    assert!(!data.train_system.throw_switch(2, SwitchPosition::Right));
+    assert!(routing::step(&data.train_system, 2, 4).is_none());
+    faults::clear(&mut data, Fault::SwitchDetectionLost(2));
+
+    faults::inject(&mut data, Fault::FalseOccupancy(1, 0));
+    assert!(data.train_system.structures[&1].sections()[0].active);
+    assert!(!data.train_system.is_free(1, 1));
+    faults::clear(&mut data, Fault::FalseOccupancy(1, 0));
+    assert!(data.train_system.is_free(1, 1));
+
+    //No route while the signal failed
+    faults::inject(&mut data, Fault::SignalFailure(0));
+    for _ in 0..20 {
+        tick(&mut data, 1.0);
+    }
+    assert_eq!(data.trains[0].path[0].structure, 0);
+    assert_eq!(data.train_system.signals[&0].aspect, Aspect::Stop);
+
+    //With track 3 closed the route goes over track 4
+    data.auto_dispatch = true;
+    faults::clear(&mut data, Fault::SignalFailure(0));
+    faults::inject(&mut data, Fault::TrackClosed(3));
+    assert_eq!(faults::active_faults(&data), vec![Fault::TrackClosed(3)]);
+    tick(&mut data, 1.0);
+    assert_eq!(data.train_system.reservations.get(&4), Some(&1));
+    assert!(!data.train_system.reservations.contains_key(&3));
+
+    //The train breaks down at speed and brakes to a stand over its braking distance
+    while data.trains[0].speed < 10.0 {
+        tick(&mut data, 1.0);
+    }
+    faults::inject(&mut data, Fault::TrainBreakdown(1));
+    let speed = data.trains[0].speed;
+    let braking_distance =
+        physics::effective_performance(&data.train_system, &data.trains[0]).braking_distance(speed);
+    let distance = data.statistics.trains[&1].distance;
+    while data.trains[0].speed > 0.0 {
+        tick(&mut data, 1.0);
+    }
+    let stopping = data.statistics.trains[&1].distance - distance;
+    assert!(stopping >= braking_distance - speed && stopping <= braking_distance * 1.5 + speed);
+
+    //It stands still until a scripted command clears it
+    let command: Command = serde_json::from_str(r#"{"ClearFault":{"TrainBreakdown":1}}"#).unwrap();
+    data.events.push(ScriptedEvent {
+        time: data.time + 10.0,
+        command,
+    });
+    let offset = data.trains[0].head_offset;
+    for _ in 0..5 {
+        tick(&mut data, 1.0);
+    }
+    assert_eq!(data.trains[0].head_offset, offset);
+    assert_eq!(data.trains[0].speed, 0.0);
+
+    for _ in 0..10 {
+        tick(&mut data, 1.0);
+    }
+    assert!(!data.trains[0].broken_down);
+    assert!(data.trains[0].head_offset != offset);
+}
 
 ///Test Train System Struct
 ///This struct does some manual adding to hashmaps for structures pins and connections
 /// Check that the assignments correspond to the idx's generated for the structures and they match up with pin idx's in our hashmaps
 #[test]
+#[allow(clippy::collapsible_match)]
 fn test_train_system_struct() {
     let mut system = TrainSystem::new();
 
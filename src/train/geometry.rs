@@ -0,0 +1,221 @@
+use std::collections::{HashMap, VecDeque};
+
+use eframe::epaint::{Pos2, Rect, Vec2};
+
+use crate::train::train_structs::*;
+
+///Distance in meters between two parallel tracks in our drawing
+pub const TRACK_SPACING: f32 = 20.0;
+
+///Geometry
+///Schematic positions for every pin in the train system
+///Structures are laid out along the x axis, a switch moves its split off output one track spacing up or down
+#[derive(Debug, Clone, Default)]
+pub struct Geometry {
+    pub pins: HashMap<usize, Pos2>,
+}
+
+///Local Pins
+///Pin positions of a structure relative to its input pin, when drawn from left to right
+fn local_pins(structure: &StructureTypes) -> Vec<(usize, Vec2)> {
+    match structure {
+        StructureTypes::Track(track) => vec![
+            (track.input_pin, Vec2::ZERO),
+            (track.output_pin, Vec2::new(track.track_length as f32, 0.0)),
+        ],
+        StructureTypes::Switch(switch) => {
+            let length = switch.switch_length as f32;
+            let split = match switch.switch_type {
+                SwitchType::LeftSplitUp | SwitchType::RightSplitUp => -TRACK_SPACING,
+                SwitchType::LeftSplitDown | SwitchType::RightSplitDown => TRACK_SPACING,
+            };
+            let (left, right) = match switch.switch_type {
+                SwitchType::LeftSplitUp | SwitchType::LeftSplitDown => (split, 0.0),
+                SwitchType::RightSplitUp | SwitchType::RightSplitDown => (0.0, split),
+            };
+
+            vec![
+                (switch.input_pin, Vec2::ZERO),
+                (switch.left_output_pin, Vec2::new(length, left)),
+                (switch.right_output_pin, Vec2::new(length, right)),
+            ]
+        }
+        StructureTypes::ThreeWay(switch) => {
+            let length = switch.switch_length as f32;
+            vec![
+                (switch.input_pin, Vec2::ZERO),
+                (switch.left_output_pin, Vec2::new(length, -TRACK_SPACING)),
+                (switch.straight_output_pin, Vec2::new(length, 0.0)),
+                (switch.right_output_pin, Vec2::new(length, TRACK_SPACING)),
+            ]
+        }
+        //A crossing and a slip switch are drawn as an X, the cross path runs from the lower left to the upper right
+        StructureTypes::Crossing(_) | StructureTypes::Slip(_) => {
+            let pins = structure.pins();
+            let length = structure.length() as f32;
+            vec![
+                (pins[0], Vec2::ZERO),
+                (pins[1], Vec2::new(length, TRACK_SPACING)),
+                (pins[2], Vec2::new(0.0, TRACK_SPACING)),
+                (pins[3], Vec2::new(length, 0.0)),
+            ]
+        }
+        //The exits of a turntable fan out around the middle of the bridge, a transfer table has parallel tracks
+        StructureTypes::Table(table) => {
+            let length = table.bridge_length as f32;
+            let middle = (table.output_pins.len() as f32 - 1.0) / 2.0;
+            let row = |track: usize| match table.table_type {
+                TableType::Turntable => (track as f32 - middle) * TRACK_SPACING,
+                TableType::TransferTable => track as f32 * TRACK_SPACING,
+            };
+
+            let inputs = table.input_pins.iter().enumerate().map(|(track, pin)| {
+                let y = match table.table_type {
+                    TableType::Turntable => 0.0,
+                    TableType::TransferTable => row(track),
+                };
+                (*pin, Vec2::new(0.0, y))
+            });
+            let outputs = table
+                .output_pins
+                .iter()
+                .enumerate()
+                .map(|(track, pin)| (*pin, Vec2::new(length, row(track))));
+            inputs.chain(outputs).collect()
+        }
+    }
+}
+
+impl Geometry {
+    ///Layout
+    ///Places all structures, starting with the lowest idx and following the connections from there
+    ///A structure connected at its input pin continues in the direction the pin it is connected to points at
+    ///Structures that are not connected to anything placed so far start a new row below the others
+    pub fn layout(system: &TrainSystem) -> Geometry {
+        let mut geometry = Geometry::default();
+
+        let mut idxs: Vec<&usize> = system.structures.keys().collect();
+        idxs.sort();
+
+        for idx in idxs {
+            if geometry
+                .pins
+                .contains_key(&system.structures[idx].pins()[0])
+            {
+                continue;
+            }
+
+            let row = match geometry.pins.is_empty() {
+                true => 0.0,
+                false => geometry.bounds().max.y + TRACK_SPACING * 2.0,
+            };
+
+            let mut queue = VecDeque::new();
+            queue.push_back((*idx, Pos2::new(0.0, row), 1.0));
+
+            while let Some((idx, origin, sign)) = queue.pop_front() {
+                let structure = &system.structures[&idx];
+                let pins = local_pins(structure);
+
+                if geometry.pins.contains_key(&pins[0].0) {
+                    continue;
+                }
+
+                for (pin, local) in &pins {
+                    geometry
+                        .pins
+                        .insert(*pin, origin + Vec2::new(local.x * sign, local.y));
+                }
+
+                //Follow the connections of all our pins to the structures next to us
+                for (pin, local) in &pins {
+                    let outward = match structure.direction_from(*pin) {
+                        Direction::Forward => -sign,
+                        Direction::Reverse => sign,
+                    };
+                    let position = origin + Vec2::new(local.x * sign, local.y);
+
+                    let other_pin = match system.connections.get(pin) {
+                        Some(other_pin) => *other_pin,
+                        None => continue,
+                    };
+                    let other = match system.pins.get(&other_pin) {
+                        Some(other) => *other,
+                        None => continue,
+                    };
+                    if geometry.pins.contains_key(&other_pin) {
+                        continue;
+                    }
+
+                    let other_structure = &system.structures[&other];
+                    let other_pins = local_pins(other_structure);
+                    let (_, other_local) = other_pins
+                        .iter()
+                        .find(|(pin, _)| *pin == other_pin)
+                        .copied()
+                        .unwrap_or((other_pin, Vec2::ZERO));
+
+                    //The connected pin has to point back at us
+                    let other_sign = match other_structure.direction_from(other_pin) {
+                        Direction::Forward => outward,
+                        Direction::Reverse => -outward,
+                    };
+                    let other_origin =
+                        position - Vec2::new(other_local.x * other_sign, other_local.y);
+
+                    queue.push_back((other, other_origin, other_sign));
+                }
+            }
+        }
+
+        geometry
+    }
+
+    ///Bounds
+    ///Rectangle containing all pins
+    pub fn bounds(&self) -> Rect {
+        let mut bounds = Rect::NOTHING;
+        for position in self.pins.values() {
+            bounds.extend_with(*position);
+        }
+        bounds
+    }
+
+    ///Point On Step
+    ///Position of a point at an offset from the entry pin of a step
+    pub fn point_on_step(&self, step: &TrainStep, offset: f64) -> Option<Pos2> {
+        let entry = self.pins.get(&step.entry_pin)?;
+        let exit = self.pins.get(&step.exit_pin)?;
+        let fraction = match step.length > 0.0 {
+            true => (offset / step.length).clamp(0.0, 1.0) as f32,
+            false => 0.0,
+        };
+        Some(*entry + (*exit - *entry) * fraction)
+    }
+
+    ///Point On Structure
+    ///Position of a point at a distance from the input pin along the path a structure is currently set to
+    pub fn point_on_structure(&self, structure: &StructureTypes, distance: f64) -> Option<Pos2> {
+        let input = structure.pins()[0];
+        self.point_on_path(structure, input, structure.exit_pin(input)?, distance)
+    }
+
+    ///Point On Path
+    ///Position of a point at a distance from the input side pin of a path through a structure
+    pub fn point_on_path(
+        &self,
+        structure: &StructureTypes,
+        input: usize,
+        output: usize,
+        distance: f64,
+    ) -> Option<Pos2> {
+        let step = TrainStep {
+            structure: structure.get_idx(),
+            entry_pin: input,
+            exit_pin: output,
+            direction: Direction::Forward,
+            length: structure.length() as f64,
+        };
+        self.point_on_step(&step, distance)
+    }
+}
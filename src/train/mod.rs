@@ -1 +1,4 @@
+pub mod geometry;
+pub mod physics;
+pub mod routing;
 pub mod train_structs;
@@ -0,0 +1,198 @@
+use crate::train::train_structs::*;
+
+///Gravity in m/s2
+pub const GRAVITY: f64 = 9.81;
+
+///Curve resistance in N per kN of train weight is this divided by the radius in meters
+pub const CURVE_RESISTANCE: f64 = 500.0;
+
+///Deceleration in m/s2 a train keeps braking with on a steep descent
+pub const MIN_DECELERATION: f64 = 0.1;
+
+///Tonnes per meter of a train without a consist, used for its energy
+pub const ESTIMATED_MASS: f64 = 2.0;
+
+///Share of the energy drawn from the overhead line that reaches the wheels
+pub const ELECTRIC_EFFICIENCY: f64 = 0.85;
+
+///Share of the energy in the fuel of a diesel train that reaches the wheels
+pub const DIESEL_EFFICIENCY: f64 = 0.35;
+
+///Share of the braking energy an electric train feeds back into the overhead line
+pub const REGENERATION_EFFICIENCY: f64 = 0.65;
+
+///Joules in a kWh
+const JOULES_PER_KWH: f64 = 3_600_000.0;
+
+///Energy
+///Traction is the energy a train used in kWh, from the overhead line or from its fuel
+///Regenerated is what an electric train fed back into the overhead line while braking
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Energy {
+    pub traction: f64,
+    pub regenerated: f64,
+}
+
+///Profile Resistance
+///The resistance of the gradients and curves under a train as a deceleration in m/s2, negative when it runs downhill
+///The weight of the train is spread over its length, so the profile is averaged over the structures it occupies
+pub fn profile_resistance(system: &TrainSystem, train: &Train) -> f64 {
+    let mut total = 0.0;
+    let mut covered = 0.0;
+
+    for (step, start, end) in train.occupied_intervals() {
+        let profile = match system.structures.get(&step.structure) {
+            Some(structure) => structure.profile(),
+            None => continue,
+        };
+        let a = step.structure_distance(start);
+        let b = step.structure_distance(end);
+        let (low, high) = (a.min(b), a.max(b));
+
+        let (gradient, curve) = integrate(profile, low, high);
+        //Gradients rise towards the output pin, a train travelling the other way runs downhill
+        let gradient = match step.direction {
+            Direction::Forward => gradient,
+            Direction::Reverse => -gradient,
+        };
+        total += gradient + curve;
+        covered += high - low;
+    }
+
+    match covered > 0.0 {
+        true => total / covered,
+        false => 0.0,
+    }
+}
+
+///Integrate
+///The gradient and curve resistance summed over the meters from low to high of a profile
+fn integrate(profile: &[ProfileSegment], low: f64, high: f64) -> (f64, f64) {
+    let mut gradient = 0.0;
+    let mut curve = 0.0;
+
+    for (i, segment) in profile.iter().enumerate() {
+        let end = profile.get(i + 1).map_or(f64::INFINITY, |next| next.start);
+        let overlap = high.min(end) - low.max(segment.start);
+        if overlap <= 0.0 {
+            continue;
+        }
+
+        gradient += GRAVITY * segment.gradient / 1000.0 * overlap;
+        if let Some(radius) = segment.radius.filter(|radius| *radius > 0.0) {
+            curve += GRAVITY * CURVE_RESISTANCE / radius / 1000.0 * overlap;
+        }
+    }
+
+    (gradient, curve)
+}
+
+///Powered
+///Whether a train has power, an electric train has none when its front is on a structure that is not electrified
+pub fn powered(system: &TrainSystem, train: &Train) -> bool {
+    match train.traction {
+        Traction::Electric => train
+            .path
+            .front()
+            .is_none_or(|front| system.is_electrified(front.structure)),
+        Traction::Diesel => true,
+    }
+}
+
+///Forces
+///What acts on a train where it is, per unit of mass in m/s2: full traction, the resistance it runs against
+///and full braking. Resistance is negative when the train runs downhill
+#[derive(Debug, Copy, Clone, Default, PartialEq)]
+pub struct Forces {
+    pub traction: f64,
+    pub resistance: f64,
+    pub braking: f64,
+}
+
+impl Forces {
+    ///Acceleration
+    ///What the train accelerates with for a share of traction and a share of braking, negative when it slows down
+    pub fn acceleration(&self, throttle: f64, brake: f64) -> f64 {
+        throttle * self.traction - brake * self.braking - self.resistance
+    }
+}
+
+///Forces
+///The forces on a train at its speed and position, a train without power has no traction
+pub fn forces(system: &TrainSystem, train: &Train) -> Forces {
+    Forces {
+        traction: match powered(system, train) {
+            true => train.performance.acceleration,
+            false => 0.0,
+        },
+        resistance: train.performance.resistance(train.speed) + profile_resistance(system, train),
+        braking: train.performance.deceleration,
+    }
+}
+
+///Effective Performance
+///The performance of a train where it is, with its speed limit and the resistance it runs against
+///Resistance takes from the acceleration and helps braking, downhill the train accelerates by itself and brakes
+///worse, though never below the minimum deceleration. A train without power coasts
+pub fn effective_performance(system: &TrainSystem, train: &Train) -> Performance {
+    let performance = train.performance.limited(train.speed_cap());
+    let forces = forces(system, train);
+
+    Performance {
+        acceleration: forces.acceleration(1.0, 0.0),
+        deceleration: (forces.braking + forces.resistance).max(MIN_DECELERATION),
+        ..performance
+    }
+}
+
+///Mass
+///The mass of a train in tonnes, estimated from its length when it has no consist
+pub fn mass(train: &Train) -> f64 {
+    match train.mass() {
+        mass if mass > 0.0 => mass,
+        _ => train.train_length as f64 * ESTIMATED_MASS,
+    }
+}
+
+///Energy
+///The energy a train used and regenerated while it ran a distance in the elapsed seconds, its speed went from
+///the last speed to its current speed. The force on the wheels is what changed the speed plus the resistance
+///When that force pulls the train, it is traction, when it holds the train back, it is braking
+///Braking is no more than the brakes of the train can do, speed lost beyond that, e.g. when it is stopped at the end
+///of its authority, is not regenerated. An electric train without power neither draws nor regenerates energy
+pub fn energy(
+    system: &TrainSystem,
+    train: &Train,
+    last_speed: f64,
+    distance: f64,
+    elapsed: f64,
+) -> Energy {
+    if elapsed <= 0.0 || distance <= 0.0 {
+        return Energy::default();
+    }
+
+    let acceleration = (train.speed - last_speed) / elapsed;
+    let resistance = train
+        .performance
+        .resistance((train.speed + last_speed) / 2.0)
+        + profile_resistance(system, train);
+    let force = (acceleration + resistance).max(-train.performance.deceleration);
+    let work = mass(train) * 1000.0 * force * distance / JOULES_PER_KWH;
+    let powered = powered(system, train);
+
+    match (work > 0.0, train.traction) {
+        (true, Traction::Electric) if powered => Energy {
+            traction: work / ELECTRIC_EFFICIENCY,
+            regenerated: 0.0,
+        },
+        (true, Traction::Diesel) => Energy {
+            traction: work / DIESEL_EFFICIENCY,
+            regenerated: 0.0,
+        },
+        (false, Traction::Electric) if powered => Energy {
+            traction: 0.0,
+            regenerated: -work * REGENERATION_EFFICIENCY,
+        },
+        _ => Energy::default(),
+    }
+}
@@ -0,0 +1,231 @@
+use std::cmp::Ordering;
+use std::collections::{BinaryHeap, HashMap};
+
+use crate::train::train_structs::*;
+
+///Route
+///A list of steps through the train system and the switch positions needed to travel them
+#[derive(Debug, Clone, Default)]
+pub struct Route {
+    pub steps: Vec<TrainStep>,
+    pub switches: Vec<(usize, SwitchPosition)>,
+}
+
+impl Route {
+    ///Length
+    ///Total length of all steps in meters
+    pub fn length(&self) -> f64 {
+        self.steps.iter().map(|step| step.length).sum()
+    }
+}
+
+///Step
+///Creates the step for entering a structure at the given pin, using the current switch positions
+pub fn step(system: &TrainSystem, structure: usize, entry_pin: usize) -> Option<TrainStep> {
+    let item = system.structures.get(&structure)?;
+    let exit_pin = item.exit_pin(entry_pin)?;
+
+    Some(TrainStep {
+        structure,
+        entry_pin,
+        exit_pin,
+        direction: item.direction_from(entry_pin),
+        length: item.length() as f64,
+    })
+}
+
+///Is Terminus
+///A pin without a connection is the end of the line
+pub fn is_terminus(system: &TrainSystem, pin: usize) -> bool {
+    !system.connections.contains_key(&pin)
+}
+
+///Next Step
+///Follows the connection at the exit pin of a step into the next structure
+///Returns None at a terminus or when the next structure can't be passed with its current state
+pub fn next_step(system: &TrainSystem, current: &TrainStep) -> Option<TrainStep> {
+    let pin = *system.connections.get(&current.exit_pin)?;
+    let structure = *system.pins.get(&pin)?;
+    step(system, structure, pin)
+}
+
+///Previous Step
+///Follows the connection at the entry pin of a step back into the structure we came from
+pub fn previous_step(system: &TrainSystem, current: &TrainStep) -> Option<TrainStep> {
+    let pin = *system.connections.get(&current.entry_pin)?;
+    let structure = *system.pins.get(&pin)?;
+    step(system, structure, pin).map(|step| step.reversed())
+}
+
+///Search State
+///Entry in the priority queue of our route search, ordered by lowest distance first
+pub(crate) struct SearchState {
+    pub(crate) distance: f64,
+    pub(crate) structure: usize,
+    pub(crate) entry_pin: usize,
+}
+
+impl PartialEq for SearchState {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for SearchState {}
+
+impl PartialOrd for SearchState {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for SearchState {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.distance.total_cmp(&self.distance)
+    }
+}
+
+///Find Route
+///Searches the shortest route from a step to a target structure, ignoring the current switch positions
+///A structure can be travelled in both directions, so we search over structure and entry pin
+///The route starts with the given step, the switch positions needed are returned alongside the steps
+pub fn find_route(system: &TrainSystem, from: &TrainStep, target: usize) -> Option<Route> {
+    find_route_avoiding(system, from, target, &[])
+}
+
+///Penalty in meters for passing a structure that should be avoided
+pub const AVOID_PENALTY: f64 = 1_000_000.0;
+
+///Find Route Avoiding
+///Same as find route, but structures to avoid are only used when there is no other way
+///Used to route around structures held by other trains, e.g. through the other track of a passing loop
+pub fn find_route_avoiding(
+    system: &TrainSystem,
+    from: &TrainStep,
+    target: usize,
+    avoid: &[usize],
+) -> Option<Route> {
+    let mut distances: HashMap<(usize, usize), f64> = HashMap::new();
+    let mut previous: HashMap<(usize, usize), (usize, usize, usize)> = HashMap::new();
+    let mut queue = BinaryHeap::new();
+
+    //The first step keeps its own exit, the train can't turn around on it
+    let start = (from.structure, from.entry_pin);
+    distances.insert(start, 0.0);
+    queue.push(SearchState {
+        distance: 0.0,
+        structure: from.structure,
+        entry_pin: from.entry_pin,
+    });
+
+    while let Some(SearchState {
+        distance,
+        structure,
+        entry_pin,
+    }) = queue.pop()
+    {
+        if structure == target {
+            return Some(build_route(system, from, &previous, (structure, entry_pin)));
+        }
+
+        if distance
+            > *distances
+                .get(&(structure, entry_pin))
+                .unwrap_or(&f64::INFINITY)
+        {
+            continue;
+        }
+
+        let item = match system.structures.get(&structure) {
+            Some(item) => item,
+            None => continue,
+        };
+
+        let exits = if (structure, entry_pin) == start {
+            vec![from.exit_pin]
+        } else {
+            item.exits(entry_pin)
+        };
+
+        for exit in exits {
+            let next_pin = match system.connections.get(&exit) {
+                Some(pin) => *pin,
+                None => continue,
+            };
+            let next_structure = match system.pins.get(&next_pin) {
+                Some(structure) => *structure,
+                None => continue,
+            };
+
+            let penalty = match avoid.contains(&next_structure) {
+                true => AVOID_PENALTY,
+                false => 0.0,
+            };
+            let next_distance = distance + item.length() as f64 + penalty;
+            let key = (next_structure, next_pin);
+
+            if next_distance < *distances.get(&key).unwrap_or(&f64::INFINITY) {
+                distances.insert(key, next_distance);
+                previous.insert(key, (structure, entry_pin, exit));
+                queue.push(SearchState {
+                    distance: next_distance,
+                    structure: next_structure,
+                    entry_pin: next_pin,
+                });
+            }
+        }
+    }
+
+    None
+}
+
+///Build Route
+///Walks back through the search results and turns them into steps
+///The target structure is added with the exit it would take travelling straight on
+fn build_route(
+    system: &TrainSystem,
+    from: &TrainStep,
+    previous: &HashMap<(usize, usize), (usize, usize, usize)>,
+    target: (usize, usize),
+) -> Route {
+    let mut route = Route::default();
+    let mut current = target;
+    let mut exit_pin = None;
+
+    loop {
+        let (structure, entry_pin) = current;
+        let item = &system.structures[&structure];
+
+        let exit = match exit_pin {
+            Some(exit) => exit,
+            None if current == (from.structure, from.entry_pin) => from.exit_pin,
+            None => item
+                .exit_pin(entry_pin)
+                .unwrap_or_else(|| item.exits(entry_pin).first().copied().unwrap_or(entry_pin)),
+        };
+
+        if let Some(position) = item.required_position(entry_pin, exit) {
+            route.switches.push((structure, position));
+        }
+
+        route.steps.push(TrainStep {
+            structure,
+            entry_pin,
+            exit_pin: exit,
+            direction: item.direction_from(entry_pin),
+            length: item.length() as f64,
+        });
+
+        match previous.get(&current) {
+            Some((structure, entry_pin, exit)) => {
+                current = (*structure, *entry_pin);
+                exit_pin = Some(*exit);
+            }
+            None => break,
+        }
+    }
+
+    route.steps.reverse();
+    route.switches.reverse();
+    route
+}
@@ -1,61 +1,747 @@
-use std::collections::HashMap;
-use std::hash::Hash;
+use std::collections::{HashMap, HashSet, VecDeque};
 
-use eframe::egui::output;
+use serde::{Deserialize, Serialize};
 
-use crate::worker::generate_sections;
+use crate::train::routing;
+use crate::worker::{boundary_sections, generate_sections, seeded_sections};
+
+///Length of a switch in meters
+pub const SWITCH_LENGTH: usize = 50;
+
+///Default number of sections on a track and on a switch
+pub const TRACK_SECTIONS: usize = 5;
+pub const SWITCH_SECTIONS: usize = 2;
+
+///A train without consist counts a vehicle for every started 25 meters of its length
+pub const AXLE_SPACING: usize = 25;
+
+///Axles of every vehicle
+pub const VEHICLE_AXLES: usize = 4;
+
+///Maximum speed of a train in shunting mode in m/s
+pub const SHUNT_SPEED: f64 = 7.0;
+
+///Default acceleration and deceleration of a train in m/s2
+pub const DEFAULT_ACCELERATION: f64 = 1.0;
+pub const DEFAULT_DECELERATION: f64 = 1.0;
 
 ///Train structure
 ///This struct holds the data that is passed between worker and UI
-#[derive(Debug, Copy, Clone)]
+///The path holds the structures the train occupies, the first step holds the front of the train
+///Head offset is the distance of the front of the train from the entry pin of the first step
+///Speed is the current speed in m/s, the performance limits how fast it can change
+///A train with a timetable gets the structure of its next stop as destination
+///A train that broke down can't move until the fault is cleared
+///A held train gets no routes and doesn't depart from its stop until it is released
+///A train with a start time stands still until the clock reaches it
+///A speed limit set by an operator caps the maximum speed of its performance
+///A train made of vehicles has them in its consist from the front to the back, its length, axles and performance
+///follow from them. A train without a consist is a single unit with its own length and performance
+///A train in shunting mode runs at shunting speed under shunt signals, its shunt move holds the legs still to go
+#[derive(Debug, Clone)]
 pub struct Train {
     pub train_number: i32,
     pub train_status: TrainStatus,
     pub train_length: usize,
+    pub axles: usize,
+    pub speed: f64,
+    pub performance: Performance,
+    pub head_offset: f64,
+    pub path: VecDeque<TrainStep>,
+    pub destination: Option<usize>,
+    pub priority: i32,
+    pub route: VecDeque<TrainStep>,
+    pub timetable: Vec<TimetableStop>,
+    pub next_stop: usize,
+    pub broken_down: bool,
+    pub held: bool,
+    pub start_at: Option<f64>,
+    pub speed_limit: Option<f64>,
+    pub consist: Vec<Vehicle>,
+    pub traction: Traction,
+    pub shunting: bool,
+    pub shunt_move: Option<ShuntMove>,
+    pub driver: Driver,
+    pub atp: AtpStatus,
+}
+
+///Shunt Move
+///A move of a shunting train to a target structure, every leg ends where the train reverses or at the target
+///Started is set once the switches of the first leg are thrown and the train is on its way
+#[derive(Debug, Clone)]
+pub struct ShuntMove {
+    pub target: usize,
+    pub legs: VecDeque<routing::Route>,
+    pub started: bool,
+}
+
+///Vehicle
+///A locomotive, coach or freight wagon, length in meters, mass in tonnes, maximum speed in m/s
+///Tractive effort and brake force are in kN, only locomotives have tractive effort and their traction counts
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Vehicle {
+    pub kind: VehicleKind,
+    pub length: usize,
+    pub mass: f64,
+    #[serde(default)]
+    pub tractive_effort: f64,
+    pub brake_force: f64,
+    pub max_speed: f64,
+    #[serde(default)]
+    pub traction: Traction,
+}
+
+///Vehicle Kind Enumerator
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VehicleKind {
+    Locomotive,
+    Coach,
+    Freight,
+}
+
+///Traction Enumerator
+///An electric train draws its power from the overhead line and feeds it back when braking, a diesel train can't
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Traction {
+    #[default]
+    Electric,
+    Diesel,
+}
+
+///Driver Enumerator
+///How a train is driven. Aggressive runs at full throttle and brakes as late as it can
+///Timetable coasts when the train is ahead of its timetable, Eco accelerates gently, cruises below the maximum speed
+///and coasts before it brakes softly
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Driver {
+    #[default]
+    Aggressive,
+    Timetable,
+    Eco,
+}
+
+///ATP Status Enumerator
+///What the train protection shows the driver: normal, a warning when the train runs too fast
+///or emergency while it applies the emergency brake. A train that passed a signal at danger is tripped,
+///its emergency brake stays applied until the train protection is reset
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum AtpStatus {
+    #[default]
+    Normal,
+    Warning,
+    Emergency,
+    Tripped,
+}
+
+impl AtpStatus {
+    ///Braking
+    ///Whether the emergency brake is applied
+    pub fn braking(&self) -> bool {
+        matches!(self, AtpStatus::Emergency | AtpStatus::Tripped)
+    }
+}
+
+///Performance
+///Maximum speed in m/s, acceleration and deceleration in m/s2
+///Davis holds the running resistance of the train, the acceleration is what is left of the tractive effort without it
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Performance {
+    pub max_speed: f64,
+    #[serde(default = "default_acceleration")]
+    pub acceleration: f64,
+    #[serde(default = "default_deceleration")]
+    pub deceleration: f64,
+    #[serde(default)]
+    pub davis: Davis,
+}
+
+///Davis
+///Coefficients of the Davis equation for the running resistance a + b v + c v2 in N per tonne of train, v in m/s
+///A is the rolling resistance, b the resistance of flanges and bearings and c the air resistance
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Davis {
+    pub a: f64,
+    pub b: f64,
+    pub c: f64,
+}
+
+impl Default for Davis {
+    fn default() -> Self {
+        Davis {
+            a: 15.0,
+            b: 0.3,
+            c: 0.03,
+        }
+    }
+}
+
+fn default_acceleration() -> f64 {
+    DEFAULT_ACCELERATION
+}
+
+fn default_deceleration() -> f64 {
+    DEFAULT_DECELERATION
+}
+
+impl Performance {
+    ///Limited
+    ///The performance with its maximum speed capped by a speed limit
+    pub fn limited(&self, speed_limit: Option<f64>) -> Performance {
+        Performance {
+            max_speed: self
+                .max_speed
+                .min(speed_limit.unwrap_or(f64::INFINITY).max(0.0)),
+            ..*self
+        }
+    }
+
+    ///Of Consist
+    ///The slowest vehicle sets the maximum speed, acceleration and deceleration are the forces divided by the mass
+    ///A consist without brakes gets the default deceleration, one without a locomotive can't accelerate
+    pub fn of_consist(consist: &[Vehicle]) -> Performance {
+        let mass: f64 = consist.iter().map(|vehicle| vehicle.mass).sum();
+        let tractive_effort: f64 = consist.iter().map(|vehicle| vehicle.tractive_effort).sum();
+        let brake_force: f64 = consist.iter().map(|vehicle| vehicle.brake_force).sum();
+
+        let per_mass = |force: f64| match mass > 0.0 {
+            true => force / mass,
+            false => 0.0,
+        };
+        let deceleration = match per_mass(brake_force) {
+            deceleration if deceleration > 0.0 => deceleration,
+            _ => DEFAULT_DECELERATION,
+        };
+
+        Performance {
+            max_speed: consist
+                .iter()
+                .map(|vehicle| vehicle.max_speed)
+                .reduce(f64::min)
+                .unwrap_or(0.0),
+            acceleration: per_mass(tractive_effort),
+            deceleration,
+            davis: Davis::default(),
+        }
+    }
+
+    ///Resistance
+    ///The running resistance at a speed as a deceleration in m/s2, a N per tonne slows a train by a mm/s2
+    pub fn resistance(&self, speed: f64) -> f64 {
+        (self.davis.a + self.davis.b * speed + self.davis.c * speed * speed) / 1000.0
+    }
+
+    ///Braking Distance
+    ///Distance needed to stop from the given speed
+    pub fn braking_distance(&self, speed: f64) -> f64 {
+        speed * speed / (2.0 * self.deceleration)
+    }
+}
+
+///Timetable Stop
+///A stop of a train at a structure, times are seconds since midnight
+///The train waits at the stop until its departure time, the actual times are filled in while running
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TimetableStop {
+    pub structure: usize,
+    pub arrival: Option<f64>,
+    pub departure: Option<f64>,
+    pub actual_arrival: Option<f64>,
+    pub actual_departure: Option<f64>,
+}
+
+///Train Step
+///A single structure a train travels over, it is entered at the entry pin and left at the exit pin
+///The length of the path between both pins is kept so a train can measure its own path
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TrainStep {
+    pub structure: usize,
+    pub entry_pin: usize,
+    pub exit_pin: usize,
+    pub direction: Direction,
+    pub length: f64,
+}
+
+impl TrainStep {
+    ///Structure Distance
+    ///Converts a distance from the entry pin to a distance from the input pin of the structure
+    ///Sections are defined from the input pin so this is needed to check occupancy in both directions
+    pub fn structure_distance(&self, offset: f64) -> f64 {
+        match self.direction {
+            Direction::Forward => offset,
+            Direction::Reverse => self.length - offset,
+        }
+    }
+
+    ///Reversed
+    ///The same step travelled the other way around
+    pub fn reversed(&self) -> TrainStep {
+        TrainStep {
+            structure: self.structure,
+            entry_pin: self.exit_pin,
+            exit_pin: self.entry_pin,
+            direction: self.direction.opposite(),
+            length: self.length,
+        }
+    }
+}
+
+impl Train {
+    pub fn new(train_number: i32, train_length: usize, max_speed: f64) -> Self {
+        Train {
+            train_number,
+            train_status: TrainStatus::Stopped,
+            train_length,
+            axles: train_length.div_ceil(AXLE_SPACING) * VEHICLE_AXLES,
+            speed: 0.0,
+            performance: Performance {
+                max_speed,
+                acceleration: DEFAULT_ACCELERATION,
+                deceleration: DEFAULT_DECELERATION,
+                davis: Davis::default(),
+            },
+            head_offset: 0.0,
+            path: VecDeque::new(),
+            destination: None,
+            priority: 0,
+            route: VecDeque::new(),
+            timetable: vec![],
+            next_stop: 0,
+            broken_down: false,
+            held: false,
+            start_at: None,
+            speed_limit: None,
+            consist: vec![],
+            traction: Traction::default(),
+            shunting: false,
+            shunt_move: None,
+            driver: Driver::default(),
+            atp: AtpStatus::default(),
+        }
+    }
+
+    ///With Consist
+    ///A train made of vehicles, its length and performance follow from the consist
+    pub fn with_consist(train_number: i32, consist: Vec<Vehicle>) -> Self {
+        let mut train = Train::new(train_number, 0, 0.0);
+        train.set_consist(consist);
+        train
+    }
+
+    ///Set Consist
+    ///Replaces the vehicles of the train and derives its length, axles and performance from them
+    ///The traction is that of the first locomotive, a consist without one keeps the traction of the train
+    pub fn set_consist(&mut self, consist: Vec<Vehicle>) {
+        self.train_length = consist.iter().map(|vehicle| vehicle.length).sum();
+        self.axles = consist.len() * VEHICLE_AXLES;
+        self.performance = Performance::of_consist(&consist);
+        if let Some(locomotive) = consist
+            .iter()
+            .find(|vehicle| vehicle.kind == VehicleKind::Locomotive)
+        {
+            self.traction = locomotive.traction;
+        }
+        self.consist = consist;
+    }
+
+    ///Started
+    ///Whether the train may move at the given time, see start at
+    pub fn started(&self, time: f64) -> bool {
+        self.start_at.is_none_or(|start| start <= time)
+    }
+
+    ///Speed Cap
+    ///The speed limit of the train, in shunting mode it never runs faster than the shunting speed
+    pub fn speed_cap(&self) -> Option<f64> {
+        match self.shunting {
+            true => Some(self.speed_limit.unwrap_or(SHUNT_SPEED).min(SHUNT_SPEED)),
+            false => self.speed_limit,
+        }
+    }
+
+    ///Mass
+    ///Total mass of the vehicles in tonnes, zero for a train without a consist
+    pub fn mass(&self) -> f64 {
+        self.consist.iter().map(|vehicle| vehicle.mass).sum()
+    }
+
+    ///Place
+    ///Puts the front of the train on a structure, travelling in the given direction
+    ///Offset is the distance of the front of the train from the pin it entered the structure at
+    ///The rest of the path is built by walking back through the system until the train length is covered
+    pub fn place(
+        &mut self,
+        system: &TrainSystem,
+        structure: usize,
+        direction: Direction,
+        offset: f64,
+    ) -> Option<()> {
+        let entry_pin = system.structures.get(&structure)?.entry_pin(direction)?;
+        let step = routing::step(system, structure, entry_pin)?;
+
+        self.head_offset = offset.min(step.length);
+        self.path.clear();
+        self.path.push_back(step);
+
+        let mut covered = self.head_offset;
+        while covered < self.train_length as f64 {
+            match routing::previous_step(system, self.path.back()?) {
+                Some(previous) => {
+                    covered += previous.length;
+                    self.path.push_back(previous);
+                }
+                None => break,
+            }
+        }
+
+        Some(())
+    }
+
+    ///Direction
+    ///Direction of travel on the structure the front of the train is on
+    pub fn direction(&self) -> Option<Direction> {
+        self.path.front().map(|step| step.direction)
+    }
+
+    ///Occupied Intervals
+    ///Walks back from the front of the train and returns the part of each step it covers
+    ///Start and end are distances from the entry pin of the step, start is always the smallest
+    pub fn occupied_intervals(&self) -> Vec<(TrainStep, f64, f64)> {
+        let mut intervals = vec![];
+        let mut remaining = self.train_length as f64;
+
+        for (i, step) in self.path.iter().enumerate() {
+            //Behind the front the train covers a structure up to its exit
+            let end = match i {
+                0 => self.head_offset,
+                _ => step.length,
+            };
+            let start = (end - remaining).max(0.0);
+            intervals.push((*step, start, end));
+            remaining -= end - start;
+
+            if remaining <= 0.0 {
+                break;
+            }
+        }
+
+        intervals
+    }
+
+    ///Overlaps
+    ///Whether two trains occupy part of the same structure, trains that only touch end to end don't overlap
+    pub fn overlaps(&self, other: &Train) -> bool {
+        let spans = |train: &Train| -> Vec<(usize, f64, f64)> {
+            train
+                .occupied_intervals()
+                .iter()
+                .map(|(step, start, end)| {
+                    let a = step.structure_distance(*start);
+                    let b = step.structure_distance(*end);
+                    (step.structure, a.min(b), a.max(b))
+                })
+                .collect()
+        };
+        let others = spans(other);
+
+        spans(self).iter().any(|(structure, low, high)| {
+            others.iter().any(|(other, other_low, other_high)| {
+                structure == other && low < other_high && other_low < high
+            })
+        })
+    }
+
+    ///Axle Positions
+    ///Axles are spread evenly from the front to the back of the train
+    ///Returns the step each axle is on with its distance from the entry pin of that step
+    pub fn axle_positions(&self) -> Vec<(TrainStep, f64)> {
+        let mut positions = vec![];
+        let spacing = match self.axles > 1 {
+            true => self.train_length as f64 / (self.axles - 1) as f64,
+            false => 0.0,
+        };
+
+        for axle in 0..self.axles {
+            let mut remaining = axle as f64 * spacing;
+            let mut position = None;
+
+            for (i, step) in self.path.iter().enumerate() {
+                let offset = match i {
+                    0 => self.head_offset,
+                    _ => step.length,
+                };
+                position = Some((*step, (offset - remaining).max(0.0)));
+                if remaining <= offset {
+                    break;
+                }
+                remaining -= offset;
+            }
+
+            positions.extend(position);
+        }
+
+        positions
+    }
+
+    ///Trim Path
+    ///Drop the steps the back of the train has left
+    pub fn trim_path(&mut self) {
+        let needed = self.occupied_intervals().len().max(1);
+        self.path.truncate(needed);
+    }
+
+    ///Reverse
+    ///The back of the train becomes the front, the train will travel the path it came from
+    ///The consist is turned around with it, so it still lists the vehicles from the front
+    pub fn reverse(&mut self) {
+        let intervals = self.occupied_intervals();
+        self.consist.reverse();
+
+        if let Some((last, start, _)) = intervals.last() {
+            self.head_offset = last.length - start;
+            self.path = intervals
+                .iter()
+                .rev()
+                .map(|(step, _, _)| step.reversed())
+                .collect();
+        }
+    }
 }
 
 ///Train Track
 ///Struct to hold a simple train track
+///The profile holds its gradients and curves, a track without profile is level and straight
 #[derive(Debug, Clone)]
 pub struct Track {
     pub track_length: usize,
     pub sections: Vec<Section>,
+    pub profile: Vec<ProfileSegment>,
     pub input_pin: usize,
     pub output_pin: usize,
     pub idx: usize,
 }
 
+///Profile Segment
+///The gradient in per mille and the curve radius in meters of a track from a distance from its input pin on
+///A positive gradient rises towards the output pin, a segment without radius is straight
+///A segment lasts until the next one starts, the track before the first segment is level and straight
+#[derive(Debug, Copy, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProfileSegment {
+    pub start: f64,
+    #[serde(default)]
+    pub gradient: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub radius: Option<f64>,
+}
+
 ///Switch
 ///Struct to hold a simple switch
+///A train can enter at the input pin and leave at the output the switch is set to
+///Trailing movements go from the output the switch is set to back to the input pin
+///A stuck switch can't be thrown, a switch that lost detection can't be thrown or passed
 #[derive(Debug, Clone)]
 pub struct Switch {
     pub sections: Vec<Section>,
     pub switch_type: SwitchType,
+    pub switch_length: usize,
+    pub position: SwitchPosition,
+    pub stuck: bool,
+    pub detection_lost: bool,
     pub input_pin: usize,
     pub left_output_pin: usize,
     pub right_output_pin: usize,
     pub idx: usize,
 }
 
+impl Switch {
+    ///Selected Output
+    ///The output pin the switch is currently set to
+    pub fn selected_output(&self) -> usize {
+        match self.position {
+            SwitchPosition::Left => self.left_output_pin,
+            _ => self.right_output_pin,
+        }
+    }
+}
+
+///Three Way Switch
+///A switch with a left, a straight and a right output, all three paths have the same length
+///It can be stuck or lose detection like a switch
+#[derive(Debug, Clone)]
+pub struct ThreeWaySwitch {
+    pub sections: Vec<Section>,
+    pub switch_length: usize,
+    pub position: SwitchPosition,
+    pub stuck: bool,
+    pub detection_lost: bool,
+    pub input_pin: usize,
+    pub left_output_pin: usize,
+    pub straight_output_pin: usize,
+    pub right_output_pin: usize,
+    pub idx: usize,
+}
+
+///Crossing
+///A diamond crossing, one path runs from the input to the output and the other from the cross input to the cross output
+///Both paths share the sections of the crossing, so a train on one path keeps trains off the other
+#[derive(Debug, Clone)]
+pub struct Crossing {
+    pub sections: Vec<Section>,
+    pub crossing_length: usize,
+    pub input_pin: usize,
+    pub output_pin: usize,
+    pub cross_input_pin: usize,
+    pub cross_output_pin: usize,
+    pub idx: usize,
+}
+
+///Slip Switch
+///A crossing with slip routes between its paths, a single slip has a route from the input to the cross output
+///and a double slip also one from the cross input to the output
+///Straight sets the crossing paths, Left the slip route from the input and Right the one from the cross input
+///It can be stuck or lose detection like a switch
+#[derive(Debug, Clone)]
+pub struct SlipSwitch {
+    pub sections: Vec<Section>,
+    pub slip_type: SlipType,
+    pub switch_length: usize,
+    pub position: SwitchPosition,
+    pub stuck: bool,
+    pub detection_lost: bool,
+    pub input_pin: usize,
+    pub output_pin: usize,
+    pub cross_input_pin: usize,
+    pub cross_output_pin: usize,
+    pub idx: usize,
+}
+
+///Table
+///A turntable or transfer table, a bridge track that is aligned to one of its tracks at a time
+///A turntable has a single input pin and an exit pin for every track, a transfer table an input and an output pin
+///for every track. While the table moves to another track the bridge connects nothing
+///A turntable can turn its bridge around, a train standing on it then leaves the other way
+#[derive(Debug, Clone)]
+pub struct Table {
+    pub sections: Vec<Section>,
+    pub table_type: TableType,
+    pub bridge_length: usize,
+    pub input_pins: Vec<usize>,
+    pub output_pins: Vec<usize>,
+    pub aligned: usize,
+    pub movement: Option<TableMovement>,
+    pub idx: usize,
+}
+
+impl Table {
+    ///Alignment
+    ///The input and output pin the bridge connects when it is aligned to a track
+    pub fn alignment(&self, track: usize) -> Option<(usize, usize)> {
+        let input = match self.table_type {
+            TableType::Turntable => self.input_pins.first()?,
+            TableType::TransferTable => self.input_pins.get(track)?,
+        };
+        Some((*input, *self.output_pins.get(track)?))
+    }
+}
+
+///Table Type Enumerator
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TableType {
+    Turntable,
+    TransferTable,
+}
+
+///Table Movement
+///A table on its way to the target track, remaining is the time left in seconds
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct TableMovement {
+    pub target: usize,
+    pub turn: bool,
+    pub remaining: f64,
+}
+
+///Section
+///Part of a structure with its own train detection, distances are measured from the input pin
+///A track circuit is active while any axle is inside the section
+///An axle counter counts axles in and out at its boundaries and is active while its count is not zero
+///When it counts out more axles than it counted in it is disturbed and stays active until it is reset
+///A false occupancy keeps the section active without a train on it
+///A sensor of a model layout can report it occupied too, see dcc
 #[derive(Debug, Clone)]
 pub struct Section {
     pub active: bool,
     pub train_number: i32,
     pub distance_start: usize,
     pub distance_end: usize,
+    pub detection: Detection,
+    pub axle_count: i32,
+    pub counted_axles: usize,
+    pub disturbed: bool,
+    pub false_occupancy: bool,
+    pub sensor_occupied: bool,
+}
+
+impl Section {
+    pub fn new(distance_start: usize, distance_end: usize, detection: Detection) -> Self {
+        Section {
+            active: false,
+            train_number: 0,
+            distance_start,
+            distance_end,
+            detection,
+            axle_count: 0,
+            counted_axles: 0,
+            disturbed: false,
+            false_occupancy: false,
+            sensor_occupied: false,
+        }
+    }
+
+    ///Reset
+    ///Resets an axle counter to zero and clears the disturbance
+    ///If axles are still inside the section the counter will be disturbed again once they leave
+    pub fn reset(&mut self) {
+        self.axle_count = 0;
+        self.disturbed = false;
+    }
+}
+
+///Detection Enumerator
+///The equipment used to detect trains in a section
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Detection {
+    TrackCircuit,
+    AxleCounter,
 }
 
 ///Train Status Enumerator
 ///Status indicators for driving braking etc
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum TrainStatus {
     Stopped,
     Running,
 }
 
+///Direction Enumerator
+///Forward travels from the input pin of a structure to its output, Reverse from output to input
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Direction {
+    Forward,
+    Reverse,
+}
+
+impl Direction {
+    pub fn opposite(&self) -> Direction {
+        match self {
+            Direction::Forward => Direction::Reverse,
+            Direction::Reverse => Direction::Forward,
+        }
+    }
+}
+
 ///Switch Type Enumerator
 ///switch types and directions
-#[derive(Debug, Copy, Clone)]
+///Left or Right is the output that splits off, Up or Down is the side it is drawn on
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum SwitchType {
     LeftSplitUp,
     LeftSplitDown,
@@ -63,6 +749,67 @@ pub enum SwitchType {
     RightSplitDown,
 }
 
+///Slip Type Enumerator
+///A single slip has one slip route, a double slip two
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SlipType {
+    Single,
+    Double,
+}
+
+///Signal
+///A signal stands at a pin and faces the trains travelling towards that pin
+///Trains stop before the pin while the signal shows Stop
+///A failed signal shows Stop and can't be cleared
+///A shunt signal only applies to shunting trains, other trains pass it whatever it shows
+#[derive(Debug, Copy, Clone)]
+pub struct Signal {
+    pub idx: usize,
+    pub pin: usize,
+    pub kind: SignalKind,
+    pub aspect: Aspect,
+    pub failed: bool,
+}
+
+impl Signal {
+    ///Stops
+    ///Whether a train has to stop at the signal, a shunting train may pass a signal showing Shunt
+    pub fn stops(&self, shunting: bool) -> bool {
+        match (self.kind, self.aspect) {
+            (SignalKind::Shunt, _) if !shunting => false,
+            (_, Aspect::Stop) => true,
+            (_, Aspect::Shunt) => !shunting,
+            (_, Aspect::Proceed) => false,
+        }
+    }
+}
+
+///Signal Kind Enumerator
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SignalKind {
+    Main,
+    Shunt,
+}
+
+///Aspect Enumerator
+///What a signal shows to an approaching train, Shunt lets only shunting trains pass
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Aspect {
+    Stop,
+    Proceed,
+    Shunt,
+}
+
+///Switch Position Enumerator
+///The output a switch is set to, Straight is the middle output of a three way switch
+///A slip switch set Straight lets trains over the crossing, Left and Right set its slip routes
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SwitchPosition {
+    Left,
+    Right,
+    Straight,
+}
+
 pub trait Identifiable {
     fn get_idx(&self) -> usize;
 }
@@ -71,14 +818,399 @@ pub trait Identifiable {
 pub enum StructureTypes {
     Track(Track),
     Switch(Switch),
+    ThreeWay(ThreeWaySwitch),
+    Crossing(Crossing),
+    Slip(SlipSwitch),
+    Table(Table),
 }
 
 impl Identifiable for StructureTypes {
     fn get_idx(&self) -> usize {
-        match &self {
-            &StructureTypes::Track(track) => track.idx,
-            &StructureTypes::Switch(switch) => switch.idx,
+        match self {
+            StructureTypes::Track(track) => track.idx,
+            StructureTypes::Switch(switch) => switch.idx,
+            StructureTypes::ThreeWay(switch) => switch.idx,
+            StructureTypes::Crossing(crossing) => crossing.idx,
+            StructureTypes::Slip(slip) => slip.idx,
+            StructureTypes::Table(table) => table.idx,
+        }
+    }
+}
+
+impl StructureTypes {
+    ///Pins
+    ///All pin idx's of this structure, the input pin is always first
+    pub fn pins(&self) -> Vec<usize> {
+        match self {
+            StructureTypes::Track(track) => vec![track.input_pin, track.output_pin],
+            StructureTypes::Switch(switch) => vec![
+                switch.input_pin,
+                switch.left_output_pin,
+                switch.right_output_pin,
+            ],
+            StructureTypes::ThreeWay(switch) => vec![
+                switch.input_pin,
+                switch.left_output_pin,
+                switch.straight_output_pin,
+                switch.right_output_pin,
+            ],
+            StructureTypes::Crossing(crossing) => vec![
+                crossing.input_pin,
+                crossing.output_pin,
+                crossing.cross_input_pin,
+                crossing.cross_output_pin,
+            ],
+            StructureTypes::Slip(slip) => vec![
+                slip.input_pin,
+                slip.output_pin,
+                slip.cross_input_pin,
+                slip.cross_output_pin,
+            ],
+            StructureTypes::Table(table) => {
+                [table.input_pins.clone(), table.output_pins.clone()].concat()
+            }
+        }
+    }
+
+    ///Named Pin
+    ///Looks up a pin by name, a track has an input and output, a switch an input, left and right
+    ///A three way switch has an input, left, straight and right
+    ///A crossing and a slip switch have an input, output, cross_input and cross_output
+    ///A table has input_0, input_1 .. and output_0, output_1 .. for its tracks, input is the first input
+    pub fn named_pin(&self, name: &str) -> Option<usize> {
+        match (self, name) {
+            (StructureTypes::Track(track), "input") => Some(track.input_pin),
+            (StructureTypes::Track(track), "output") => Some(track.output_pin),
+            (StructureTypes::Switch(switch), "input") => Some(switch.input_pin),
+            (StructureTypes::Switch(switch), "left") => Some(switch.left_output_pin),
+            (StructureTypes::Switch(switch), "right") => Some(switch.right_output_pin),
+            (StructureTypes::ThreeWay(switch), "input") => Some(switch.input_pin),
+            (StructureTypes::ThreeWay(switch), "left") => Some(switch.left_output_pin),
+            (StructureTypes::ThreeWay(switch), "straight") => Some(switch.straight_output_pin),
+            (StructureTypes::ThreeWay(switch), "right") => Some(switch.right_output_pin),
+            (StructureTypes::Crossing(_) | StructureTypes::Slip(_), _) => {
+                let names = ["input", "output", "cross_input", "cross_output"];
+                let i = names.iter().position(|pin| *pin == name)?;
+                Some(self.pins()[i])
+            }
+            (StructureTypes::Table(table), "input") => table.input_pins.first().copied(),
+            (StructureTypes::Table(table), _) => {
+                let (pins, track) = match name.split_once('_')? {
+                    ("input", track) => (&table.input_pins, track),
+                    ("output", track) => (&table.output_pins, track),
+                    _ => return None,
+                };
+                pins.get(track.parse::<usize>().ok()?).copied()
+            }
+            _ => None,
+        }
+    }
+
+    ///Length
+    ///Length of the structure in meters, all paths through a structure are the same length
+    pub fn length(&self) -> usize {
+        match self {
+            StructureTypes::Track(track) => track.track_length,
+            StructureTypes::Switch(switch) => switch.switch_length,
+            StructureTypes::ThreeWay(switch) => switch.switch_length,
+            StructureTypes::Crossing(crossing) => crossing.crossing_length,
+            StructureTypes::Slip(slip) => slip.switch_length,
+            StructureTypes::Table(table) => table.bridge_length,
+        }
+    }
+
+    ///Profile
+    ///The gradients and curves of a track, every other structure is level and straight
+    pub fn profile(&self) -> &[ProfileSegment] {
+        match self {
+            StructureTypes::Track(track) => &track.profile,
+            _ => &[],
+        }
+    }
+
+    pub fn sections(&self) -> &Vec<Section> {
+        match self {
+            StructureTypes::Track(track) => &track.sections,
+            StructureTypes::Switch(switch) => &switch.sections,
+            StructureTypes::ThreeWay(switch) => &switch.sections,
+            StructureTypes::Crossing(crossing) => &crossing.sections,
+            StructureTypes::Slip(slip) => &slip.sections,
+            StructureTypes::Table(table) => &table.sections,
+        }
+    }
+
+    pub fn sections_mut(&mut self) -> &mut Vec<Section> {
+        match self {
+            StructureTypes::Track(track) => &mut track.sections,
+            StructureTypes::Switch(switch) => &mut switch.sections,
+            StructureTypes::ThreeWay(switch) => &mut switch.sections,
+            StructureTypes::Crossing(crossing) => &mut crossing.sections,
+            StructureTypes::Slip(slip) => &mut slip.sections,
+            StructureTypes::Table(table) => &mut table.sections,
+        }
+    }
+
+    ///Paths
+    ///Every way through the structure from a pin on its input side to a pin on its output side
+    ///with the position it has to be set to, sections are measured from the input side
+    ///A table only has the path over its bridge to the track it is aligned to, none while it moves
+    pub fn paths(&self) -> Vec<(usize, usize, Option<SwitchPosition>)> {
+        match self {
+            StructureTypes::Track(track) => vec![(track.input_pin, track.output_pin, None)],
+            StructureTypes::Switch(switch) => vec![
+                (
+                    switch.input_pin,
+                    switch.left_output_pin,
+                    Some(SwitchPosition::Left),
+                ),
+                (
+                    switch.input_pin,
+                    switch.right_output_pin,
+                    Some(SwitchPosition::Right),
+                ),
+            ],
+            StructureTypes::ThreeWay(switch) => vec![
+                (
+                    switch.input_pin,
+                    switch.left_output_pin,
+                    Some(SwitchPosition::Left),
+                ),
+                (
+                    switch.input_pin,
+                    switch.straight_output_pin,
+                    Some(SwitchPosition::Straight),
+                ),
+                (
+                    switch.input_pin,
+                    switch.right_output_pin,
+                    Some(SwitchPosition::Right),
+                ),
+            ],
+            StructureTypes::Crossing(crossing) => vec![
+                (crossing.input_pin, crossing.output_pin, None),
+                (crossing.cross_input_pin, crossing.cross_output_pin, None),
+            ],
+            StructureTypes::Slip(slip) => {
+                let mut paths = vec![
+                    (
+                        slip.input_pin,
+                        slip.output_pin,
+                        Some(SwitchPosition::Straight),
+                    ),
+                    (
+                        slip.cross_input_pin,
+                        slip.cross_output_pin,
+                        Some(SwitchPosition::Straight),
+                    ),
+                    (
+                        slip.input_pin,
+                        slip.cross_output_pin,
+                        Some(SwitchPosition::Left),
+                    ),
+                ];
+                if slip.slip_type == SlipType::Double {
+                    paths.push((
+                        slip.cross_input_pin,
+                        slip.output_pin,
+                        Some(SwitchPosition::Right),
+                    ));
+                }
+                paths
+            }
+            StructureTypes::Table(table) => match table.movement {
+                Some(_) => vec![],
+                None => table
+                    .alignment(table.aligned)
+                    .map(|(input, output)| (input, output, None))
+                    .into_iter()
+                    .collect(),
+            },
+        }
+    }
+
+    ///Idle Paths
+    ///The paths that are drawn dimmed: those a switch is not set to, all paths of a switch that lost detection
+    ///and the tracks a table is not aligned to
+    pub fn idle_paths(&self) -> Vec<(usize, usize)> {
+        let open = match self.is_detection_lost() {
+            true => vec![],
+            false => self.open_paths(),
+        };
+
+        let paths: Vec<(usize, usize)> = match self {
+            StructureTypes::Table(table) => (0..table.output_pins.len())
+                .filter_map(|track| table.alignment(track))
+                .collect(),
+            _ => self
+                .paths()
+                .into_iter()
+                .map(|(input, output, _)| (input, output))
+                .collect(),
+        };
+        paths
+            .into_iter()
+            .filter(|path| !open.contains(path))
+            .collect()
+    }
+
+    ///Open Paths
+    ///The paths that can be travelled with the current position
+    pub fn open_paths(&self) -> Vec<(usize, usize)> {
+        let position = self.position();
+        self.paths()
+            .into_iter()
+            .filter(|(_, _, required)| required.is_none() || *required == position)
+            .map(|(input, output, _)| (input, output))
+            .collect()
+    }
+
+    ///Position
+    ///The position of a structure that can be thrown, None for tracks and crossings
+    pub fn position(&self) -> Option<SwitchPosition> {
+        match self {
+            StructureTypes::Switch(switch) => Some(switch.position),
+            StructureTypes::ThreeWay(switch) => Some(switch.position),
+            StructureTypes::Slip(slip) => Some(slip.position),
+            _ => None,
+        }
+    }
+
+    ///Positions
+    ///All positions a structure can be thrown to, in the order of its paths
+    pub fn positions(&self) -> Vec<SwitchPosition> {
+        let mut positions = vec![];
+        for (_, _, position) in self.paths() {
+            if let Some(position) = position.filter(|position| !positions.contains(position)) {
+                positions.push(position);
+            }
+        }
+        positions
+    }
+
+    ///Is Moving
+    ///A table that moves to another track connects nothing, trains on it stand still
+    pub fn is_moving(&self) -> bool {
+        matches!(self, StructureTypes::Table(table) if table.movement.is_some())
+    }
+
+    ///Is Stuck
+    ///A stuck structure can't be thrown, only structures with a position can get stuck
+    pub fn is_stuck(&self) -> bool {
+        match self {
+            StructureTypes::Switch(switch) => switch.stuck,
+            StructureTypes::ThreeWay(switch) => switch.stuck,
+            StructureTypes::Slip(slip) => slip.stuck,
+            _ => false,
+        }
+    }
+
+    ///Is Detection Lost
+    ///The position of a structure that lost detection is unknown, it can't be thrown or passed
+    pub fn is_detection_lost(&self) -> bool {
+        match self {
+            StructureTypes::Switch(switch) => switch.detection_lost,
+            StructureTypes::ThreeWay(switch) => switch.detection_lost,
+            StructureTypes::Slip(slip) => slip.detection_lost,
+            _ => false,
+        }
+    }
+
+    ///Set Stuck
+    ///Returns false when the structure has no position that could get stuck
+    pub fn set_stuck(&mut self, stuck: bool) -> bool {
+        match self {
+            StructureTypes::Switch(switch) => switch.stuck = stuck,
+            StructureTypes::ThreeWay(switch) => switch.stuck = stuck,
+            StructureTypes::Slip(slip) => slip.stuck = stuck,
+            _ => return false,
+        }
+        true
+    }
+
+    ///Set Detection Lost
+    ///Returns false when the structure has no position to lose detection of
+    pub fn set_detection_lost(&mut self, detection_lost: bool) -> bool {
+        match self {
+            StructureTypes::Switch(switch) => switch.detection_lost = detection_lost,
+            StructureTypes::ThreeWay(switch) => switch.detection_lost = detection_lost,
+            StructureTypes::Slip(slip) => slip.detection_lost = detection_lost,
+            _ => return false,
+        }
+        true
+    }
+
+    fn position_mut(&mut self) -> Option<&mut SwitchPosition> {
+        match self {
+            StructureTypes::Switch(switch) => Some(&mut switch.position),
+            StructureTypes::ThreeWay(switch) => Some(&mut switch.position),
+            StructureTypes::Slip(slip) => Some(&mut slip.position),
+            _ => None,
+        }
+    }
+
+    ///Direction From
+    ///Entering at a pin on the input side means travelling Forward, entering at any output means Reverse
+    pub fn direction_from(&self, entry_pin: usize) -> Direction {
+        let input_side = match self {
+            StructureTypes::Table(table) => table.input_pins.contains(&entry_pin),
+            _ => self.paths().iter().any(|(input, _, _)| *input == entry_pin),
+        };
+        if input_side {
+            Direction::Forward
+        } else {
+            Direction::Reverse
+        }
+    }
+
+    ///Entry Pin
+    ///The pin a train enters at when travelling in the given direction with the current state
+    pub fn entry_pin(&self, direction: Direction) -> Option<usize> {
+        match direction {
+            Direction::Forward => self.open_paths().first().map(|(input, _)| *input),
+            Direction::Reverse => self.open_paths().first().map(|(_, output)| *output),
+        }
+    }
+
+    ///Exits
+    ///All pins that can be reached from the entry pin, regardless of the current switch position
+    pub fn exits(&self, entry_pin: usize) -> Vec<usize> {
+        self.paths()
+            .into_iter()
+            .filter_map(|(input, output, _)| match entry_pin {
+                pin if pin == input => Some(output),
+                pin if pin == output => Some(input),
+                _ => None,
+            })
+            .collect()
+    }
+
+    ///Exit Pin
+    ///The pin a train leaves at when entering at the entry pin with the current switch position
+    ///None if the structure can't be passed, e.g. a trailing movement from the output a switch is not set to
+    ///A switch that lost detection can't be passed at all, its position is unknown
+    pub fn exit_pin(&self, entry_pin: usize) -> Option<usize> {
+        if self.is_detection_lost() {
+            return None;
         }
+
+        self.exits(entry_pin).into_iter().find(|exit| {
+            match self.required_position(entry_pin, *exit) {
+                Some(position) => self.position() == Some(position),
+                None => true,
+            }
+        })
+    }
+
+    ///Required Position
+    ///The switch position needed to travel between two pins, None for structures without a position
+    pub fn required_position(&self, entry_pin: usize, exit_pin: usize) -> Option<SwitchPosition> {
+        self.paths()
+            .into_iter()
+            .find(|(input, output, _)| {
+                (*input, *output) == (entry_pin, exit_pin)
+                    || (*output, *input) == (entry_pin, exit_pin)
+            })
+            .and_then(|(_, _, position)| position)
     }
 }
 
@@ -87,11 +1219,18 @@ impl Identifiable for StructureTypes {
 /// Each structure has the trait identifiable because they all have a unique 'idx'
 /// Each structure has a number of pins, either input or output, the idx's are all stored in the pins hashmap
 /// Connections contain the connections between the structures pin's
+/// Signals stand at pins, reservations hold the structures that are part of a route set for a train
+/// Closures are structures closed for maintenance, no train may enter them
+/// Structures are electrified unless they are in non electrified, electric trains can't draw power there
 #[derive(Debug, Clone, Default)]
 pub struct TrainSystem {
     pub pins: HashMap<usize, usize>,
     pub connections: HashMap<usize, usize>,
     pub structures: HashMap<usize, StructureTypes>,
+    pub signals: HashMap<usize, Signal>,
+    pub reservations: HashMap<usize, i32>,
+    pub closures: HashSet<usize>,
+    pub non_electrified: HashSet<usize>,
 }
 
 impl TrainSystem {
@@ -110,7 +1249,8 @@ impl TrainSystem {
 
         let track = StructureTypes::Track(Track {
             track_length: length,
-            sections: generate_sections(5, false, length),
+            sections: generate_sections(TRACK_SECTIONS, false, length),
+            profile: vec![],
             input_pin: input_idx,
             output_pin: output_idx,
             idx: self.structures.len(),
@@ -133,15 +1273,281 @@ impl TrainSystem {
         self.pins.insert(output_right_idx, idx);
 
         let switch = StructureTypes::Switch(Switch {
-            sections: generate_sections(2, false, 50),
-            switch_type: switch_type,
+            sections: generate_sections(SWITCH_SECTIONS, false, SWITCH_LENGTH),
+            switch_type,
+            switch_length: SWITCH_LENGTH,
+            position: SwitchPosition::Left,
+            stuck: false,
+            detection_lost: false,
             input_pin: input_idx,
             left_output_pin: output_left_idx,
             right_output_pin: output_right_idx,
-            idx: idx,
+            idx,
+        });
+
+        self.structures.insert(idx, switch);
+        self
+    }
+
+    ///Three Way Switch
+    ///Adds a switch with a left, straight and right output, it starts set straight
+    pub fn three_way_switch(&mut self) -> &Self {
+        let idx = self.structures.len();
+        let pins = self.add_pins(idx, 4);
+
+        let switch = StructureTypes::ThreeWay(ThreeWaySwitch {
+            sections: generate_sections(SWITCH_SECTIONS, false, SWITCH_LENGTH),
+            switch_length: SWITCH_LENGTH,
+            position: SwitchPosition::Straight,
+            stuck: false,
+            detection_lost: false,
+            input_pin: pins[0],
+            left_output_pin: pins[1],
+            straight_output_pin: pins[2],
+            right_output_pin: pins[3],
+            idx,
         });
 
         self.structures.insert(idx, switch);
         self
     }
+
+    ///Crossing
+    ///Adds a diamond crossing, both paths share a single section
+    pub fn crossing(&mut self) -> &Self {
+        let idx = self.structures.len();
+        let pins = self.add_pins(idx, 4);
+
+        let crossing = StructureTypes::Crossing(Crossing {
+            sections: generate_sections(1, false, SWITCH_LENGTH),
+            crossing_length: SWITCH_LENGTH,
+            input_pin: pins[0],
+            output_pin: pins[1],
+            cross_input_pin: pins[2],
+            cross_output_pin: pins[3],
+            idx,
+        });
+
+        self.structures.insert(idx, crossing);
+        self
+    }
+
+    ///Slip Switch
+    ///Adds a single or double slip switch, it starts set straight over the crossing
+    pub fn slip_switch(&mut self, slip_type: SlipType) -> &Self {
+        let idx = self.structures.len();
+        let pins = self.add_pins(idx, 4);
+
+        let slip = StructureTypes::Slip(SlipSwitch {
+            sections: generate_sections(1, false, SWITCH_LENGTH),
+            slip_type,
+            switch_length: SWITCH_LENGTH,
+            position: SwitchPosition::Straight,
+            stuck: false,
+            detection_lost: false,
+            input_pin: pins[0],
+            output_pin: pins[1],
+            cross_input_pin: pins[2],
+            cross_output_pin: pins[3],
+            idx,
+        });
+
+        self.structures.insert(idx, slip);
+        self
+    }
+
+    ///Turntable
+    ///Adds a turntable with a bridge of the given length and an exit for every track, it starts aligned to the first
+    pub fn turntable(&mut self, length: usize, tracks: usize) -> &Self {
+        self.table(TableType::Turntable, length, tracks)
+    }
+
+    ///Transfer Table
+    ///Adds a transfer table with a bridge of the given length that shifts between parallel tracks
+    pub fn transfer_table(&mut self, length: usize, tracks: usize) -> &Self {
+        self.table(TableType::TransferTable, length, tracks)
+    }
+
+    fn table(&mut self, table_type: TableType, length: usize, tracks: usize) -> &Self {
+        let idx = self.structures.len();
+        let inputs = match table_type {
+            TableType::Turntable => 1,
+            TableType::TransferTable => tracks,
+        };
+        let input_pins = self.add_pins(idx, inputs);
+        let output_pins = self.add_pins(idx, tracks);
+
+        let table = StructureTypes::Table(Table {
+            sections: generate_sections(1, false, length),
+            table_type,
+            bridge_length: length,
+            input_pins,
+            output_pins,
+            aligned: 0,
+            movement: None,
+            idx,
+        });
+
+        self.structures.insert(idx, table);
+        self
+    }
+
+    fn add_pins(&mut self, idx: usize, amount: usize) -> Vec<usize> {
+        (0..amount)
+            .map(|_| {
+                let pin = self.pins.len();
+                self.pins.insert(pin, idx);
+                pin
+            })
+            .collect()
+    }
+
+    ///Set Sections
+    ///Replaces the sections of a structure, boundaries are the distances from the input pin where a new section starts
+    ///E.g. boundaries [100, 250] on a 1000M track give the sections 0-100, 100-250 and 250-1000
+    pub fn set_sections(
+        &mut self,
+        idx: usize,
+        boundaries: &[usize],
+        detection: Detection,
+    ) -> &Self {
+        if let Some(structure) = self.structures.get_mut(&idx) {
+            let length = structure.length();
+            *structure.sections_mut() = boundary_sections(boundaries, length, detection);
+        }
+        self
+    }
+
+    ///Set Random Sections
+    ///Replaces the sections of a structure with an amount of sections of random length
+    ///The same seed always gives the same sections
+    pub fn set_random_sections(
+        &mut self,
+        idx: usize,
+        amount: usize,
+        seed: u64,
+        detection: Detection,
+    ) -> &Self {
+        if let Some(structure) = self.structures.get_mut(&idx) {
+            let length = structure.length();
+            let mut sections = seeded_sections(amount, seed, length);
+            for section in &mut sections {
+                section.detection = detection;
+            }
+            *structure.sections_mut() = sections;
+        }
+        self
+    }
+
+    ///Set Profile
+    ///Replaces the gradients and curves of a track, the segments are sorted by their start
+    pub fn set_profile(&mut self, idx: usize, profile: &[ProfileSegment]) -> &Self {
+        if let Some(StructureTypes::Track(track)) = self.structures.get_mut(&idx) {
+            track.profile = profile.to_vec();
+            track.profile.sort_by(|a, b| a.start.total_cmp(&b.start));
+        }
+        self
+    }
+
+    ///Connect
+    ///Connects two pins, connections are stored both ways so they can be followed in either direction
+    pub fn connect(&mut self, pin_a: usize, pin_b: usize) -> &Self {
+        self.connections.insert(pin_a, pin_b);
+        self.connections.insert(pin_b, pin_a);
+        self
+    }
+
+    ///Signal
+    ///Places a signal at a pin, it faces trains travelling towards that pin and starts at Stop
+    pub fn signal(&mut self, pin: usize) -> &Self {
+        self.add_signal(pin, SignalKind::Main)
+    }
+
+    ///Shunt Signal
+    ///Adds a shunt signal at a pin, it shows Stop until a shunting move is set over it
+    pub fn shunt_signal(&mut self, pin: usize) -> &Self {
+        self.add_signal(pin, SignalKind::Shunt)
+    }
+
+    fn add_signal(&mut self, pin: usize, kind: SignalKind) -> &Self {
+        let idx = self.signals.len();
+
+        self.signals.insert(
+            idx,
+            Signal {
+                idx,
+                pin,
+                kind,
+                aspect: Aspect::Stop,
+                failed: false,
+            },
+        );
+        self
+    }
+
+    ///Signal At
+    ///The signal standing at a pin, if any
+    pub fn signal_at(&self, pin: usize) -> Option<&Signal> {
+        self.signals.values().find(|signal| signal.pin == pin)
+    }
+
+    ///Is Electrified
+    ///Whether a structure has an overhead line
+    pub fn is_electrified(&self, idx: usize) -> bool {
+        !self.non_electrified.contains(&idx)
+    }
+
+    ///Is Free
+    ///A structure is free for a train when it is not reserved by another train and no other train is on it
+    ///A closed structure and a table that is moving are never free
+    pub fn is_free(&self, idx: usize, train_number: i32) -> bool {
+        if self.closures.contains(&idx) {
+            return false;
+        }
+
+        let reserved =
+            matches!(self.reservations.get(&idx), Some(number) if *number != train_number);
+        let moving = self
+            .structures
+            .get(&idx)
+            .is_some_and(|structure| structure.is_moving());
+
+        let occupied = self.structures.get(&idx).is_none_or(|structure| {
+            structure.sections().iter().any(|section| {
+                section.disturbed || (section.active && section.train_number != train_number)
+            })
+        });
+
+        !reserved && !occupied && !moving
+    }
+
+    ///Throw Switch
+    ///Sets a switch to a position, a switch that is occupied, reserved or stuck can't be thrown
+    ///Returns true when the switch is in the requested position afterwards
+    ///A switch that lost detection never reports its position
+    ///Three way and slip switches are thrown the same way, to one of their positions
+    pub fn throw_switch(&mut self, idx: usize, position: SwitchPosition) -> bool {
+        let locked = self.reservations.contains_key(&idx)
+            || self
+                .structures
+                .get(&idx)
+                .is_none_or(|structure| structure.sections().iter().any(|section| section.active));
+
+        let structure = match self.structures.get_mut(&idx) {
+            Some(structure) if structure.positions().contains(&position) => structure,
+            _ => return false,
+        };
+        let failed = structure.is_stuck() || structure.is_detection_lost();
+        let detection_lost = structure.is_detection_lost();
+
+        match structure.position_mut() {
+            Some(current) => {
+                if *current != position && !locked && !failed {
+                    *current = position;
+                }
+                *current == position && !detection_lost
+            }
+            None => false,
+        }
+    }
 }
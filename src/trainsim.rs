@@ -1,11 +1,19 @@
 use std::sync::{Arc, Mutex};
 
-use crate::utils::ColorHex;
+use crate::diagram;
+use crate::dispatcher::Strategy;
+use crate::faults;
+use crate::graph::{Corridor, Sample};
+use crate::interfaces::Interfaces;
+use crate::stats;
+use crate::train::geometry::{Geometry, TRACK_SPACING};
+use crate::train::train_structs::*;
+use crate::utils::{format_time, ColorHex};
 use crate::worker::{self, Command, WorkerData};
 use eframe::egui::style::Margin;
-use eframe::egui::Sense;
-use eframe::emath;
-use eframe::epaint::PathShape;
+use eframe::egui::{DragValue, Painter, Sense};
+use eframe::emath::{self, Align2};
+use eframe::epaint::{PathShape, Shape};
 use eframe::{
     egui::CentralPanel,
     egui::Color32,
@@ -19,11 +27,28 @@ use tokio::sync::mpsc::UnboundedSender;
 use tokio::task::JoinHandle;
 
 type Data = Arc<Mutex<Option<WorkerData>>>;
+
+///Seconds of the past and the future the train graph shows around now
+const GRAPH_PAST: f64 = 1200.0;
+const GRAPH_FUTURE: f64 = 600.0;
+
+///Seconds between two time lines of the train graph
+const GRAPH_GRID: f64 = 300.0;
+
 ///Application struct used by Egui
 ///Contains two join handles so worker is kept in scope
 /// UI transmitter is used so our struct can send messages to our worker loop
+/// Initial data is what the worker loop starts with, e.g. a loaded scenario
+/// Interfaces like the WebSocket API or a model layout see and control the same worker loop, see interfaces
+/// Export status tells where the last diagrams were written, or why that failed
+/// The train graph shows the route between the graph from and graph to structures
 pub struct TrainSim {
     pub worker_data: Data,
+    initial_data: Option<WorkerData>,
+    export_status: Option<String>,
+    graph_from: usize,
+    graph_to: usize,
+    interfaces: Interfaces,
     worker_handle: Option<JoinHandle<()>>,
     join_handle: Option<JoinHandle<()>>,
     ui_transmitter: Option<UnboundedSender<Command>>,
@@ -47,12 +72,22 @@ impl App for TrainSim {
         let (ui_transmitter, worker_receiver) = tokio::sync::mpsc::unbounded_channel();
         let (worker_transmitter, mut ui_receiver) = tokio::sync::mpsc::unbounded_channel();
 
+        //start the interfaces, they send commands to the worker loop like the UI does
+        let publishers = match self.interfaces.start(ui_transmitter.clone()) {
+            Ok(publishers) => publishers,
+            Err(error) => {
+                eprintln!("Failed to start the API on {}", error);
+                Default::default()
+            }
+        };
+
         //assign ui transmitter so we can talk to worker loop from our update function
         self.ui_transmitter = Some(ui_transmitter);
 
         //start worker loop
+        let initial_data = self.initial_data.take().unwrap_or_default();
         self.worker_handle = Some(tokio::spawn(async move {
-            worker::worker_loop(worker_receiver, worker_transmitter)
+            worker::worker_loop(worker_receiver, worker_transmitter, initial_data)
                 .await
                 .unwrap();
         }));
@@ -62,6 +97,7 @@ impl App for TrainSim {
         let frame_handle = frame.clone();
         self.join_handle = Some(tokio::spawn(async move {
             while let Some(workerdata) = ui_receiver.recv().await {
+                publishers.publish(&workerdata);
                 if let Ok(mut data) = data_handle.lock() {
                     *data = Some(workerdata);
                     frame_handle.request_repaint();
@@ -77,6 +113,10 @@ impl App for TrainSim {
     fn update(&mut self, ctx: &eframe::egui::Context, _frame: &eframe::epi::Frame) {
         let frame = Frame::none().fill(Color32::from_hex("#3A3C49").unwrap());
 
+        let mut export_status = None;
+        let mut graph_from = self.graph_from;
+        let mut graph_to = self.graph_to;
+
         //Get a handle on our Arc
         let data_handle = self.worker_data.clone();
         //If we can lock mutex
@@ -97,6 +137,104 @@ impl App for TrainSim {
 
                         ui.label(data.count.to_string());
 
+                        ui.label(format_time(data.time));
+
+                        //Select the dispatcher strategy
+                        ui.horizontal(|ui| {
+                            for strategy in Strategy::ALL {
+                                let name = strategy.dispatcher().name();
+                                if ui
+                                    .selectable_label(data.strategy == strategy, name)
+                                    .clicked()
+                                {
+                                    if let Some(tx) = &self.ui_transmitter {
+                                        tx.send(Command::SetStrategy(strategy)).unwrap();
+                                    }
+                                }
+                            }
+                        });
+
+                        if !data.dispatch_queue.is_empty() {
+                            let queue: Vec<String> = data
+                                .dispatch_queue
+                                .iter()
+                                .map(|number| number.to_string())
+                                .collect();
+                            ui.label(format!("Waiting for a route: {}", queue.join(", ")));
+                        }
+
+                        if let Some(error) = data.script.as_ref().and_then(|s| s.error.as_ref()) {
+                            ui.colored_label(Color32::RED, error);
+                        }
+
+                        for warning in &data.warnings {
+                            ui.colored_label(Color32::YELLOW, warning);
+                        }
+
+                        for fault in faults::active_faults(data) {
+                            ui.colored_label(
+                                Color32::from_hex("#FF8C42").unwrap(),
+                                fault.to_string(),
+                            );
+                        }
+
+                        for trains in &data.deadlocks {
+                            let trains: Vec<String> =
+                                trains.iter().map(|number| number.to_string()).collect();
+                            ui.colored_label(
+                                Color32::RED,
+                                format!("Deadlock between trains {}", trains.join(", ")),
+                            );
+                        }
+
+                        for train in &data.trains {
+                            match train.atp {
+                                AtpStatus::Emergency => ui.colored_label(
+                                    Color32::RED,
+                                    format!("Train {} emergency braking", train.train_number),
+                                ),
+                                AtpStatus::Tripped => ui.colored_label(
+                                    Color32::RED,
+                                    format!(
+                                        "Train {} passed a signal at danger",
+                                        train.train_number
+                                    ),
+                                ),
+                                AtpStatus::Warning => ui.colored_label(
+                                    Color32::YELLOW,
+                                    format!("Train {} overspeed warning", train.train_number),
+                                ),
+                                AtpStatus::Normal => continue,
+                            };
+                        }
+
+                        ui.collapsing("Statistics", |ui| {
+                            let report = stats::report(data);
+                            ui.label(format!("Total delay {:.1} min", report.total_delay_minutes));
+                            ui.label(format!(
+                                "Energy {:.1} kWh, {:.1} kWh regenerated",
+                                report.total_energy_kwh, report.total_regenerated_kwh
+                            ));
+                            ui.label(format!("ATP interventions {}", report.incidents.len()));
+                            for train in &report.trains {
+                                ui.label(format!(
+                                    "Train {}: {:.1} m/s, {:.0} s at red, {:.0}% punctual, {:.1} kWh net ({:?})",
+                                    train.train_number,
+                                    train.average_speed,
+                                    train.signal_wait,
+                                    train.punctuality,
+                                    train.net_energy_kwh,
+                                    train.traction
+                                ));
+                            }
+                            for switch in &report.switches {
+                                ui.label(format!(
+                                    "Switch {}: {} throws",
+                                    switch.structure, switch.throws
+                                ));
+                            }
+                        });
+
                         //Frame has a little padding
                         Frame::none()
                             .margin(Margin::symmetric(10.0, 10.0))
@@ -107,75 +245,40 @@ impl App for TrainSim {
                                     Sense::click(),
                                 );
 
-                                //Scale transform to transform our points to points within the available space
-                                let to_screen = emath::RectTransform::from_to(
-                                    Rect::from_min_size(Pos2::ZERO, response.rect.size()),
-                                    response.rect,
-                                );
+                                self.draw_train_system(&painter, response.rect, data);
+                            });
 
-                                let mut lines: Vec<PathShape> = vec![];
-
-                                let stroke_white = Stroke::new(2.0, Color32::WHITE);
-                                let stroke_yellow = Stroke::new(2.0, Color32::YELLOW);
-
-                                //Draw our track
-                                // let sections = &data.track.sections;
-
-                                // for (i, section) in sections.into_iter().enumerate() {
-                                //     let index = i as f32;
-                                //     let offset = index * 100.0;
-                                //     let center_y = response.rect.height() / 2.0;
-                                //     let div_low = center_y - 5.0;
-                                //     let div_hi = center_y + 5.0;
-                                //     //Draw section divider
-                                //     let divider = PathShape::line(
-                                //         vec![
-                                //             to_screen.transform_pos(Pos2::new(offset, div_low)),
-                                //             to_screen.transform_pos(Pos2::new(offset, div_hi)),
-                                //         ],
-                                //         stroke_white,
-                                //     );
-
-                                //     let section_line = PathShape::line(
-                                //         vec![
-                                //             to_screen.transform_pos(Pos2::new(offset, center_y)),
-                                //             to_screen
-                                //                 .transform_pos(Pos2::new(offset + 100.0, center_y)),
-                                //         ],
-                                //         if section.active {
-                                //             stroke_yellow
-                                //         } else {
-                                //             stroke_white
-                                //         },
-                                //     );
-
-                                //     //Draw a final divider at the end of the track
-                                //     if i == sections.len() - 1 {
-                                //         let last_divider = PathShape::line(
-                                //             vec![
-                                //                 to_screen.transform_pos(Pos2::new(
-                                //                     offset + 100.0,
-                                //                     div_low,
-                                //                 )),
-                                //                 to_screen.transform_pos(Pos2::new(
-                                //                     offset + 100.0,
-                                //                     div_hi,
-                                //                 )),
-                                //             ],
-                                //             stroke_white,
-                                //         );
-                                //         lines.push(last_divider);
-                                //     }
-
-                                //     lines.push(divider);
-                                //     lines.push(section_line);
-                                // }
-
-                                // //paint points
-                                // for line in lines {
-                                //     painter.add(line);
-                                // }
+                        //Time-distance diagram of the trains along a route between two structures
+                        ui.collapsing("Train graph", |ui| {
+                            let last = data.train_system.structures.len().saturating_sub(1);
+                            ui.horizontal(|ui| {
+                                ui.label("From");
+                                ui.add(DragValue::new(&mut graph_from).clamp_range(0..=last));
+                                ui.label("To");
+                                ui.add(DragValue::new(&mut graph_to).clamp_range(0..=last));
                             });
+
+                            match Corridor::new(&data.train_system, graph_from, graph_to) {
+                                Some(corridor) => {
+                                    let (response, painter) = ui.allocate_painter(
+                                        Vec2::new(ui.available_width(), 300.0),
+                                        Sense::hover(),
+                                    );
+                                    self.draw_train_graph(&painter, response.rect, data, &corridor);
+                                }
+                                None => {
+                                    ui.label("There is no route between these structures");
+                                }
+                            }
+                        });
+
+                        //Write diagrams of the layout as it is now to the working directory
+                        if ui.button("Export diagrams").clicked() {
+                            export_status = Some(export_diagrams(data));
+                        }
+                        if let Some(status) = &self.export_status {
+                            ui.label(status);
+                        }
                         // if ui.button("reset").clicked() {
                         //     if let Some(tx) = &self.ui_transmitter {
                         //         tx.send(Command::Reset).unwrap();
@@ -194,6 +297,12 @@ impl App for TrainSim {
                 });
             }
         };
+
+        if export_status.is_some() {
+            self.export_status = export_status;
+        }
+        self.graph_from = graph_from;
+        self.graph_to = graph_to;
     }
 
     //Set Window title
@@ -210,16 +319,43 @@ impl App for TrainSim {
     }
 }
 
+impl Default for TrainSim {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl TrainSim {
     pub fn new() -> TrainSim {
+        TrainSim::with_data(WorkerData {
+            auto_dispatch: true,
+            ..Default::default()
+        })
+    }
+
+    ///With Data
+    /// Creates the app with the data the simulation starts from
+    pub fn with_data(data: WorkerData) -> TrainSim {
         TrainSim {
             worker_data: Arc::new(Mutex::new(None)),
+            graph_from: 0,
+            graph_to: data.train_system.structures.len().saturating_sub(1),
+            initial_data: Some(data),
+            export_status: None,
+            interfaces: Interfaces::default(),
             worker_handle: None,
             join_handle: None,
             ui_transmitter: None,
         }
     }
 
+    ///With Interfaces
+    /// Starts the interfaces together with the worker loop
+    pub fn with_interfaces(mut self, interfaces: Interfaces) -> TrainSim {
+        self.interfaces = interfaces;
+        self
+    }
+
     ///Configure Fonts
     /// Create Font definitions and sets them into our Context
     /// Assign custom font for the Proportional Font Family
@@ -280,4 +416,195 @@ impl TrainSim {
         );
         ctx.set_style(style);
     }
+
+    ///Draw Train System
+    /// Draws all structures with their sections and the trains on them, see diagram::draw
+    /// Positions come from the geometry layout and are scaled to fit the painter rectangle
+    fn draw_train_system(&self, painter: &Painter, rect: Rect, data: &WorkerData) {
+        let geometry = Geometry::layout(&data.train_system);
+
+        let bounds = geometry.bounds();
+        if !bounds.is_finite() {
+            return;
+        }
+
+        //Scale transform to transform our points to points within the available space
+        let to_screen = emath::RectTransform::from_to(
+            bounds.expand2(Vec2::new(TRACK_SPACING, TRACK_SPACING)),
+            rect,
+        );
+
+        let drawing = diagram::draw(data, &geometry, &to_screen);
+        for line in drawing.lines {
+            painter.add(PathShape::line(vec![line.start, line.end], line.stroke));
+        }
+        for polygon in drawing.polygons {
+            painter.add(PathShape::convex_polygon(
+                polygon.points,
+                polygon.color,
+                Stroke::none(),
+            ));
+        }
+        for circle in drawing.circles {
+            painter.circle_filled(circle.center, circle.radius, circle.color);
+        }
+        for label in drawing.labels {
+            painter.text(
+                label.position,
+                Align2::CENTER_BOTTOM,
+                label.text,
+                FontId::proportional(label.size),
+                label.color,
+            );
+        }
+    }
+    ///Draw Train Graph
+    /// Draws the time-distance diagram of a corridor, time runs from left to right and distance downwards
+    /// Occupied sections are shaded yellow, the planned lines from the timetables are dashed
+    /// The actual lines are drawn in the train color with the train number at the end, the green line is now
+    fn draw_train_graph(
+        &self,
+        painter: &Painter,
+        rect: Rect,
+        data: &WorkerData,
+        corridor: &Corridor,
+    ) {
+        let painter = painter.sub_region(rect);
+        let start = data.time - GRAPH_PAST;
+        let length = corridor.length().max(1.0);
+
+        //Room for the structure numbers on the left and the times at the bottom
+        let plot = Rect::from_min_max(
+            rect.min + Vec2::new(30.0, 5.0),
+            rect.max - Vec2::new(5.0, 16.0),
+        );
+        let to_screen = emath::RectTransform::from_to(
+            Rect::from_min_max(
+                Pos2::ZERO,
+                Pos2::new((GRAPH_PAST + GRAPH_FUTURE) as f32, length as f32),
+            ),
+            plot,
+        );
+        let point = |time: f64, distance: f64| {
+            to_screen.transform_pos(Pos2::new((time - start) as f32, distance as f32))
+        };
+
+        let dimmed = Color32::from_hex("#6B6D7A").unwrap();
+        let train_color = Color32::from_hex("#FF8C42").unwrap();
+
+        let occupations = data.history.occupations(data.time);
+        for block in corridor.occupied(&occupations, &data.train_system) {
+            painter.rect_filled(
+                Rect::from_two_pos(point(block.start, block.low), point(block.end, block.high)),
+                Rounding::none(),
+                Color32::from_rgba_unmultiplied(255, 255, 0, 40),
+            );
+        }
+
+        //A line at the start of every structure with its number
+        for (step, distance) in &corridor.steps {
+            painter.line_segment(
+                [
+                    point(start, *distance),
+                    point(data.time + GRAPH_FUTURE, *distance),
+                ],
+                Stroke::new(1.0, dimmed),
+            );
+            painter.text(
+                Pos2::new(
+                    rect.left() + 25.0,
+                    point(start, distance + step.length / 2.0).y,
+                ),
+                Align2::RIGHT_CENTER,
+                step.structure.to_string(),
+                FontId::proportional(10.0),
+                dimmed,
+            );
+        }
+
+        let mut time = (start / GRAPH_GRID).ceil() * GRAPH_GRID;
+        while time <= data.time + GRAPH_FUTURE {
+            painter.line_segment(
+                [point(time, 0.0), point(time, length)],
+                Stroke::new(1.0, dimmed),
+            );
+            painter.text(
+                Pos2::new(point(time, 0.0).x, rect.bottom() - 2.0),
+                Align2::CENTER_BOTTOM,
+                &format_time(time)[..5],
+                FontId::proportional(10.0),
+                dimmed,
+            );
+            time += GRAPH_GRID;
+        }
+
+        for train in &data.trains {
+            let planned: Vec<Pos2> = corridor
+                .planned(train)
+                .iter()
+                .map(|(time, distance)| point(*time, *distance))
+                .collect();
+            painter.extend(Shape::dashed_line(
+                &planned,
+                Stroke::new(1.0, Color32::WHITE),
+                6.0,
+                4.0,
+            ));
+
+            //The samples end with where the train is right now
+            let mut samples = data
+                .history
+                .trains
+                .get(&train.train_number)
+                .cloned()
+                .unwrap_or_default();
+            if let Some(front) = train.path.front() {
+                samples.push(Sample {
+                    time: data.time,
+                    structure: front.structure,
+                    distance: front.structure_distance(train.head_offset),
+                });
+            }
+
+            let lines = corridor.actual(&samples);
+            for line in &lines {
+                painter.add(PathShape::line(
+                    line.iter()
+                        .map(|(time, distance)| point(*time, *distance))
+                        .collect(),
+                    Stroke::new(2.0, train_color),
+                ));
+            }
+            if let Some((time, distance)) = lines.last().and_then(|line| line.last()) {
+                painter.text(
+                    point(*time, *distance) + Vec2::new(4.0, 0.0),
+                    Align2::LEFT_CENTER,
+                    train.train_number.to_string(),
+                    FontId::proportional(12.0),
+                    Color32::WHITE,
+                );
+            }
+        }
+
+        painter.line_segment(
+            [point(data.time, 0.0), point(data.time, length)],
+            Stroke::new(2.0, Color32::from_hex("#7BD389").unwrap()),
+        );
+    }
+}
+
+///Diagram Files
+///Files the app writes the diagrams of the layout to
+const DIAGRAM_FILES: [&str; 2] = ["layout.dot", "layout.svg"];
+
+///Export Diagrams
+///Writes the DOT graph and the SVG drawing of the layout, returns what happened for the UI
+fn export_diagrams(data: &WorkerData) -> String {
+    let contents = [diagram::dot(&data.train_system), diagram::svg(data)];
+    for (file, content) in DIAGRAM_FILES.iter().zip(contents) {
+        if let Err(error) = std::fs::write(file, content) {
+            return format!("Failed to write {}: {}", file, error);
+        }
+    }
+    format!("Diagrams written to {}", DIAGRAM_FILES.join(" and "))
 }
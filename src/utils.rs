@@ -33,3 +33,35 @@ impl ColorHex for Color32 {
         None
     }
 }
+
+///Parse Time
+///Converts a time of day like "08:15" or "08:15:30" to seconds since midnight
+pub fn parse_time(time: &str) -> Option<f64> {
+    let parts: Vec<&str> = time.trim().split(':').collect();
+    if parts.len() < 2 || parts.len() > 3 {
+        return None;
+    }
+
+    let mut seconds = 0.0;
+    for (i, part) in parts.iter().enumerate() {
+        let value: u32 = part.parse().ok()?;
+        if i > 0 && value >= 60 {
+            return None;
+        }
+        seconds += value as f64 * [3600.0, 60.0, 1.0][i];
+    }
+
+    Some(seconds)
+}
+
+///Format Time
+///Converts seconds since midnight to a time of day like "08:15:30"
+pub fn format_time(seconds: f64) -> String {
+    let seconds = seconds.max(0.0) as u64;
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
+}
@@ -1,5 +1,22 @@
+use crate::atp::{self, Incident, IncidentKind};
+use crate::consist;
+use crate::deadlock;
+use crate::dispatcher::{self, Strategy};
+use crate::driver;
+use crate::faults::{self, Fault};
+use crate::graph::{self, History};
+use crate::scripting::{self, Script};
+use crate::shunting;
+use crate::stats::{self, Statistics};
+use crate::tables;
+use crate::train::physics;
+use crate::train::routing;
 use crate::train::train_structs::*;
 use futures::future::FutureExt;
+use rand::rngs::StdRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::time::Duration;
 use tokio::sync::mpsc::UnboundedReceiver;
 use tokio::sync::mpsc::UnboundedSender;
@@ -11,34 +28,136 @@ use tokio::{
 
 ///Data structure
 ///This struct holds the data that is passed between worker and UI
+///Time is the simulation clock in seconds since midnight
+///The dispatch queue holds the trains waiting at a signal for their route, in the order they are handled
+///Deadlocks holds the groups of trains that wait for each other
+///Events are scripted commands that still have to run, ordered by time
+///Statistics are collected every tick, see the stats report
+///Strategy selects the dispatcher, lookahead is the number of moves it checks for a deadlock before setting a route
+///The script, when there is one, runs every tick before the dispatcher
+///Notifications are what happened since the data was last sent, e.g. to API clients
+///History holds the positions and occupations of the last hours for the train graph
+///Warnings are what loading the scenario reported, e.g. railML elements that were left out
 #[derive(Debug, Clone)]
 pub struct WorkerData {
     pub count: usize,
+    pub time: f64,
     pub train_system: TrainSystem,
+    pub trains: Vec<Train>,
+    pub auto_dispatch: bool,
+    pub strategy: Strategy,
+    pub lookahead: usize,
+    pub dispatch_queue: Vec<i32>,
+    pub deadlocks: Vec<Vec<i32>>,
+    pub events: Vec<ScriptedEvent>,
+    pub statistics: Statistics,
+    pub script: Option<Script>,
+    pub notifications: Vec<Notification>,
+    pub history: History,
+    pub warnings: Vec<String>,
+}
+
+impl Default for WorkerData {
+    fn default() -> Self {
+        WorkerData {
+            count: 0,
+            time: 0.0,
+            train_system: TrainSystem::new(),
+            trains: vec![],
+            auto_dispatch: false,
+            strategy: Strategy::default(),
+            lookahead: deadlock::LOOKAHEAD,
+            dispatch_queue: vec![],
+            deadlocks: vec![],
+            events: vec![],
+            statistics: Statistics::default(),
+            script: None,
+            notifications: vec![],
+            history: History::default(),
+            warnings: vec![],
+        }
+    }
+}
+
+///Scripted Event
+///A command that runs once the simulation clock reaches its time
+#[derive(Debug, Clone)]
+pub struct ScriptedEvent {
+    pub time: f64,
+    pub command: Command,
+}
+
+///Notification
+///Something that happened in the simulation, times are in seconds since midnight
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Notification {
+    Arrived {
+        train: i32,
+        structure: usize,
+        time: f64,
+    },
+    Departed {
+        train: i32,
+        structure: usize,
+        time: f64,
+    },
+    RouteSet {
+        train: i32,
+        signal: usize,
+    },
+    Deadlock {
+        trains: Vec<i32>,
+    },
+    FaultInjected {
+        fault: Fault,
+    },
+    FaultCleared {
+        fault: Fault,
+    },
+    AtpIntervention {
+        incident: Incident,
+    },
 }
 
 ///Worker Message Enumerator
-///For now just contains Quit and Counter for updating our counter from the UI
-#[derive(Debug)]
+///Contains Quit, Counter for updating our counter from the UI and train commands
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Command {
     Quit,
     Counter(usize),
     Reset,
+    ReverseTrain(i32),
+    ResetSection(usize, usize),
+    ThrowSwitch(usize, SwitchPosition),
+    SetDestination(i32, Option<usize>),
+    AutoDispatch(bool),
+    InjectFault(Fault),
+    ClearFault(Fault),
+    SetRoute(i32),
+    HoldTrain(i32, bool),
+    SetStrategy(Strategy),
+    SetSpeed(i32, Option<f64>),
+    SetSensor(usize, usize, bool),
+    AlignTable(usize, usize, bool),
+    Uncouple(i32, usize, i32),
+    Couple(i32, i32),
+    Shunt(i32, usize),
+    SetShunting(i32, bool),
+    SetDriver(i32, Driver),
+    ResetAtp(i32),
 }
 
 ///Worker loop keeps running our defined tasks until the program is quit
 ///it allows for the UI to be updated through our weak handle
 ///sender and receivers are used for callback communication and other signals
+///The loop starts from the given data, e.g. a loaded scenario
+///Notifications are cleared once they have been sent
 pub async fn worker_loop(
     mut r: UnboundedReceiver<Command>,
     t: UnboundedSender<WorkerData>,
+    mut data: WorkerData,
 ) -> tokio::io::Result<()> {
-    let mut data = WorkerData {
-        count: 0,
-        train_system: TrainSystem::new(),
-    };
-
-    let mut data_ref = &mut data;
+    let data_ref = &mut data;
 
     let mut interval = interval(Duration::from_secs(1));
 
@@ -51,16 +170,12 @@ pub async fn worker_loop(
 
             _ = interval.tick() => {
 
-                //Run until time = 10
-                if data_ref.count < 10 {
-
-                //Increment
-                data_ref.count += 1;
-                //update train position
-                //update_train_position(data_ref);
+                //advance the simulation, each tick is a second
+                tick(data_ref, 1.0);
 
                 //update ui
-                channel.send(data_ref.clone()).unwrap();}
+                channel.send(data_ref.clone()).unwrap();
+                data_ref.notifications.clear();
                 continue;
             }
 
@@ -78,67 +193,624 @@ pub async fn worker_loop(
         match m {
             Command::Quit => return Ok(()),
 
-            Command::Counter(number) => {
-                data_ref.count = number;
+            Command::Counter(_) | Command::Reset => {
+                apply_command(data_ref, m);
                 interval = set_new_interval();
-                //update_train_position(data_ref);
                 channel.send(data_ref.clone()).unwrap();
+                data_ref.notifications.clear();
             }
 
-            Command::Reset => {
-                data_ref.count = 0;
-                interval = set_new_interval();
-                //update_train_position(data_ref);
+            m => {
+                apply_command(data_ref, m);
                 channel.send(data_ref.clone()).unwrap();
+                data_ref.notifications.clear();
+            }
+        }
+    }
+}
+
+///Tick
+///Advances the simulation by the elapsed seconds
+///Scripted events and timetables are handled first, then the shunt moves and the dispatcher set routes and the trains move
+pub fn tick(data: &mut WorkerData, elapsed: f64) {
+    //Increment
+    data.count += 1;
+    data.time += elapsed;
+
+    run_events(data);
+    update_timetables(data);
+    scripting::run(data);
+    shunting::update(data);
+
+    //set routes for waiting trains
+    if data.auto_dispatch {
+        dispatcher::dispatch(data);
+    }
+
+    tables::update(data, elapsed);
+    update_train_positions(data, elapsed);
+
+    let deadlocks = deadlock::detect(data);
+    for trains in deadlocks
+        .iter()
+        .filter(|trains| !data.deadlocks.contains(trains))
+    {
+        data.notifications.push(Notification::Deadlock {
+            trains: trains.clone(),
+        });
+    }
+    data.deadlocks = deadlocks;
+    stats::record(data, elapsed);
+    graph::record(data);
+}
+
+///Run Headless
+///Runs the simulation without UI for a duration in seconds, as fast as possible
+///Nobody listens to the notifications so only those of the last tick are kept
+pub fn run_headless(data: &mut WorkerData, duration: f64, step: f64) {
+    let end = data.time + duration;
+
+    while data.time < end {
+        data.notifications.clear();
+        tick(data, step.min(end - data.time));
+    }
+}
+
+///Apply Command
+///Runs a command on the simulation, used for commands from the UI and for scripted events
+pub fn apply_command(data: &mut WorkerData, command: Command) {
+    match command {
+        Command::Quit => {}
+
+        Command::Counter(number) => {
+            data.count = number;
+        }
+
+        Command::Reset => {
+            data.count = 0;
+        }
+
+        Command::ReverseTrain(number) => {
+            if let Some(train) = data
+                .trains
+                .iter_mut()
+                .find(|train| train.train_number == number)
+            {
+                train.reverse();
+                train.speed = 0.0;
+            }
+            update_sections(data);
+        }
+
+        Command::ThrowSwitch(idx, position) => {
+            data.train_system.throw_switch(idx, position);
+        }
+
+        Command::AlignTable(idx, track, turn) => {
+            tables::align(data, idx, track, turn);
+        }
+
+        Command::Uncouple(number, vehicle, new_number) => {
+            consist::uncouple(data, number, vehicle, new_number);
+        }
+
+        Command::Couple(number, other) => {
+            consist::couple(data, number, other);
+        }
+
+        Command::Shunt(number, target) => {
+            shunting::start(data, number, target);
+        }
+
+        Command::SetShunting(number, shunting) => {
+            if let Some(train) = data
+                .trains
+                .iter_mut()
+                .find(|train| train.train_number == number)
+            {
+                train.shunting = shunting;
+                if !shunting {
+                    train.shunt_move = None;
+                }
+            }
+        }
+
+        Command::SetDriver(number, driver) => {
+            if let Some(train) = data
+                .trains
+                .iter_mut()
+                .find(|train| train.train_number == number)
+            {
+                train.driver = driver;
+            }
+        }
+
+        Command::ResetAtp(number) => {
+            if let Some(train) = data
+                .trains
+                .iter_mut()
+                .find(|train| train.train_number == number && train.speed == 0.0)
+            {
+                train.atp = AtpStatus::Normal;
+            }
+        }
+
+        Command::SetDestination(number, destination) => {
+            if let Some(train) = data
+                .trains
+                .iter_mut()
+                .find(|train| train.train_number == number)
+            {
+                train.destination = destination;
+            }
+        }
+
+        Command::AutoDispatch(enabled) => {
+            data.auto_dispatch = enabled;
+        }
+
+        Command::InjectFault(fault) => {
+            if faults::inject(data, fault) {
+                data.notifications
+                    .push(Notification::FaultInjected { fault });
+            }
+        }
+
+        Command::ClearFault(fault) => {
+            if faults::clear(data, fault) {
+                data.notifications
+                    .push(Notification::FaultCleared { fault });
+            }
+        }
+
+        Command::SetRoute(number) => {
+            dispatcher::route_train(data, number);
+        }
+
+        Command::HoldTrain(number, held) => {
+            if let Some(train) = data
+                .trains
+                .iter_mut()
+                .find(|train| train.train_number == number)
+            {
+                train.held = held;
+            }
+        }
+
+        Command::SetStrategy(strategy) => {
+            data.strategy = strategy;
+        }
+
+        Command::SetSpeed(number, speed_limit) => {
+            if let Some(train) = data
+                .trains
+                .iter_mut()
+                .find(|train| train.train_number == number)
+            {
+                train.speed_limit = speed_limit;
+            }
+        }
+
+        Command::SetSensor(structure, section, occupied) => {
+            if let Some(section) = data
+                .train_system
+                .structures
+                .get_mut(&structure)
+                .and_then(|structure| structure.sections_mut().get_mut(section))
+            {
+                section.sensor_occupied = occupied;
+            }
+            update_sections(data);
+        }
+
+        Command::ResetSection(structure, section) => {
+            if let Some(section) = data
+                .train_system
+                .structures
+                .get_mut(&structure)
+                .and_then(|structure| structure.sections_mut().get_mut(section))
+            {
+                section.reset();
+            }
+            update_sections(data);
+        }
+    }
+}
+
+///Run Events
+///Applies all scripted events whose time has come
+pub fn run_events(data: &mut WorkerData) {
+    let time = data.time;
+    let due: Vec<ScriptedEvent> = data
+        .events
+        .iter()
+        .filter(|event| event.time <= time)
+        .cloned()
+        .collect();
+    data.events.retain(|event| event.time > time);
+
+    for event in due {
+        apply_command(data, event.command);
+    }
+}
+
+///Update Timetables
+///A train with a timetable gets the structure of its next stop as destination
+///Arrival is when it stands still at the end of that structure, it departs at its departure time
+///When the next stop is behind the train, e.g. at a terminus, it reverses before it departs
+///The last stop without a departure time is the end of the journey, the train stays there
+///A train that broke down or is held doesn't depart until it is cleared or released, nor one that hasn't started
+///A shunting train follows its shunt move instead of its timetable
+pub fn update_timetables(data: &mut WorkerData) {
+    let time = data.time;
+    let system = &data.train_system;
+    let notifications = &mut data.notifications;
+
+    for train in data.trains.iter_mut().filter(|train| !train.shunting) {
+        let stop = match train.timetable.get(train.next_stop) {
+            Some(stop) => *stop,
+            None => continue,
+        };
+        train.destination = Some(stop.structure);
+
+        let arrived = train.path.front().is_some_and(|front| {
+            front.structure == stop.structure
+                && train.head_offset >= front.length - ARRIVAL_MARGIN
+                && train.speed == 0.0
+        });
+        if !arrived {
+            continue;
+        }
+
+        let started = train.started(time);
+        let current = &mut train.timetable[train.next_stop];
+        if current.actual_arrival.is_none() {
+            current.actual_arrival = Some(time);
+            notifications.push(Notification::Arrived {
+                train: train.train_number,
+                structure: stop.structure,
+                time,
+            });
+        }
+
+        match current.departure {
+            Some(departure)
+                if departure <= time && !train.broken_down && !train.held && started =>
+            {
+                current.actual_departure = Some(time);
+                train.next_stop += 1;
+                notifications.push(Notification::Departed {
+                    train: train.train_number,
+                    structure: stop.structure,
+                    time,
+                });
+            }
+            _ => continue,
+        }
+
+        if let Some(next) = train.timetable.get(train.next_stop) {
+            train.destination = Some(next.structure);
+
+            let front = train.path[0];
+            if routing::find_route(system, &front, next.structure).is_none() {
+                train.reverse();
+            }
+        }
+    }
+}
+
+///Distance from the end of a structure in which a train counts as arrived
+pub const ARRIVAL_MARGIN: f64 = 0.5;
+
+///Movement Authority
+///Distance from the front of a train to the first point where it has to stop
+///That is a signal the train has to stop at, the end of its destination, a terminus, a switch set against it, a closed structure
+///or a table that is not aligned to its track
+///We look no further than the limit, the train can stop within that distance anyway
+pub fn movement_authority(system: &TrainSystem, train: &Train, limit: f64) -> f64 {
+    let mut step = match train.path.front() {
+        Some(front) => *front,
+        None => return 0.0,
+    };
+    //A train on a moving table goes nowhere
+    if system
+        .structures
+        .get(&step.structure)
+        .is_some_and(|structure| structure.is_moving())
+    {
+        return 0.0;
+    }
+    let mut distance = step.length - train.head_offset;
+
+    while distance < limit {
+        if train.destination == Some(step.structure) {
+            break;
+        }
+        if let Some(signal) = system.signal_at(step.exit_pin) {
+            if signal.stops(train.shunting) {
+                break;
+            }
+        }
+
+        match routing::next_step(system, &step) {
+            Some(next) if !system.closures.contains(&next.structure) => {
+                distance += next.length;
+                step = next;
             }
+            _ => break,
         }
     }
+
+    distance.max(0.0)
 }
 
-///Update Train Position
-///After each tick we update train position based on elapsed time and if we need to brake for next stop
-///For now we use a simple track A --> B, 1000M long, no acceleration or braking yet
-/// Train travels 100M / S (crazy fast)
-// pub fn update_train_position(data: &mut WorkerData) -> &mut WorkerData {
-//     let mut train = &mut data.train;
-//     let track = &mut data.track;
+///Update Train Positions
+///After each tick we move every train based on its speed and the elapsed time
+///Its driver chooses traction and braking up to its maximum speed, or its speed limit, see driver
+///The train protection supervises its speed against its movement authority and applies the emergency brake when
+///the train runs too fast, a train braking for an emergency that can't stop in time passes a signal showing Stop
+///and is tripped until its train protection is reset
+///Every intervention is logged as an incident
+///Its running resistance and the gradients and curves it is on take from its acceleration, see effective performance
+///It follows the connections in its direction of travel, through switches as they are set
+///It stops before a signal showing Stop and at the end of its destination
+///Passing a signal sets it back to Stop, structures of a route are released once the train has left them
+///When it can't go any further it stops, at a terminus it reverses so it departs back the way it came
+///A train that broke down brakes to a stand and stays there
+///A shunting train also stops short of the train ahead of it, so it can run onto an occupied track to couple
+pub fn update_train_positions(data: &mut WorkerData, elapsed: f64) {
+    let time = data.time;
+    let system = &mut data.train_system;
+    let occupied: Vec<shunting::Occupation> = data
+        .trains
+        .iter()
+        .map(|train| (train.train_number, train.occupied_intervals()))
+        .collect();
+    let mut incidents = vec![];
+
+    for train in data.trains.iter_mut() {
+        if train.path.is_empty() {
+            continue;
+        }
+        if !train.started(time) {
+            train.speed = 0.0;
+            train.train_status = TrainStatus::Stopped;
+            continue;
+        }
+
+        let performance = physics::effective_performance(system, train);
+        let limit =
+            performance.braking_distance(performance.max_speed) + performance.max_speed * elapsed;
+        let clearance = match train.shunting {
+            true => shunting::clearance(system, train, &occupied, limit),
+            false => f64::INFINITY,
+        };
+        let authority = movement_authority(system, train, limit).min(clearance);
+        let permitted = atp::permitted_speed(&performance, authority);
+
+        //The driver chooses traction and braking, the train protection checks the speed that comes of it
+        //Once it intervenes, the emergency brake takes over
+        let forces = physics::forces(system, train);
+        let control = driver::control(
+            system,
+            train,
+            time,
+            elapsed,
+            authority,
+            &forces,
+            &performance,
+        );
+        train.speed = match train.atp.braking() {
+            true => train.speed - atp::emergency_deceleration(&performance) * elapsed,
+            false => train.speed + forces.acceleration(control.throttle, control.brake) * elapsed,
+        }
+        .max(0.0);
+
+        if atp::supervise(train, permitted, performance.deceleration * elapsed) {
+            incidents.push(Incident {
+                time,
+                train: train.train_number,
+                kind: IncidentKind::Overspeed,
+                structure: train.path.front().map(|front| front.structure),
+                speed: train.speed,
+                permitted,
+            });
+        }
+
+        //A train braking for an emergency can't be held at the end of its authority, it may run past a signal
+        let mut remaining = match train.atp.braking() {
+            true => (train.speed * elapsed).min(clearance),
+            false => (train.speed * elapsed).min(authority),
+        };
+        let mut moved = 0.0;
+        let mut passed = None;
 
-//     //We move a 100M each tick, back of the train is one train length behind the position if train is not at the end
-//     let position: usize = train.train_length + (data.count * 100);
+        loop {
+            let front = train.path[0];
+            let travel = remaining.min(front.length - train.head_offset);
 
-//     if position > track.track_length {
-//         println!("Done");
-//         return data;
-//     }
-//     let back_of_train = position - train.train_length;
+            train.head_offset += travel;
+            moved += travel;
+            remaining -= travel;
 
-//     //Check if the front or back of the train is one of the sections, that means it is active
-//     for i in 0..track.sections.len() {
-//         let section = &mut track.sections[i];
-//         section.active = false;
+            if train.head_offset < front.length {
+                break;
+            }
 
-//         if back_of_train > section.distance_start && back_of_train < section.distance_end {
-//             section.active = true;
-//             section.train_number = train.train_number;
-//         }
+            //We are at the end of the structure, check if we may leave it
+            if train.destination == Some(front.structure) {
+                break;
+            }
 
-//         if position > section.distance_start && position < section.distance_end {
-//             section.active = true;
-//             section.train_number = train.train_number;
-//         }
-//     }
+            let signal = system.signal_at(front.exit_pin).map(|signal| signal.idx);
+            let mut danger = false;
+            if let Some(idx) = signal {
+                if system.signals[&idx].stops(train.shunting) {
+                    if !train.atp.braking() || remaining <= 0.0 {
+                        break;
+                    }
+                    danger = true;
+                }
+            }
 
-//     //If we reached the end of the track, we've stopped
-//     if position == track.track_length {
-//         train.train_status = TrainStatus::Stopped;
-//     } else if back_of_train == 0 {
-//         train.train_status = TrainStatus::Stopped;
-//     } else {
-//         train.train_status = TrainStatus::Running
-//     }
+            match routing::next_step(system, &front) {
+                Some(next) if system.closures.contains(&next.structure) => break,
+                Some(next) => {
+                    if remaining <= 0.0 {
+                        break;
+                    }
+
+                    train.path.push_front(next);
+                    train.head_offset = 0.0;
+
+                    if train.route.front().map(|step| step.structure) == Some(next.structure) {
+                        train.route.pop_front();
+                    }
+
+                    //The train passed the signal so it goes back to Stop
+                    if let Some(signal) = signal.and_then(|idx| system.signals.get_mut(&idx)) {
+                        signal.aspect = Aspect::Stop;
+                    }
+
+                    if let Some(idx) = signal.filter(|_| danger) {
+                        passed = Some(idx);
+                        train.atp = AtpStatus::Tripped;
+                        incidents.push(Incident {
+                            time,
+                            train: train.train_number,
+                            kind: IncidentKind::SignalPassedAtDanger(idx),
+                            structure: Some(front.structure),
+                            speed: train.speed,
+                            permitted,
+                        });
+                    }
+                }
+                None => {
+                    if routing::is_terminus(system, front.exit_pin) {
+                        train.reverse();
+                        train.speed = 0.0;
+                    }
+                    break;
+                }
+            }
+        }
 
-//     data
-// }
+        if authority - moved <= 0.0 && passed.is_none() {
+            train.speed = 0.0;
+        }
+
+        train.trim_path();
+        train.train_status = if moved > 0.0 {
+            TrainStatus::Running
+        } else {
+            TrainStatus::Stopped
+        };
+    }
+
+    for incident in incidents {
+        data.notifications.push(Notification::AtpIntervention {
+            incident: incident.clone(),
+        });
+        data.statistics.incidents.push(incident);
+    }
+
+    release_reservations(data);
+    update_sections(data);
+}
+
+///Release Reservations
+///A reserved structure is released when its train is not on it and it is not part of the route ahead of the train
+pub fn release_reservations(data: &mut WorkerData) {
+    let trains = &data.trains;
+
+    data.train_system.reservations.retain(|idx, number| {
+        trains
+            .iter()
+            .find(|train| train.train_number == *number)
+            .is_some_and(|train| {
+                train.path.iter().any(|step| step.structure == *idx)
+                    || train.route.iter().any(|step| step.structure == *idx)
+            })
+    });
+}
+
+///Update Sections
+///Sections are measured from the input pin so the positions of each train are converted to that first
+///Track circuits are active when any part of a train is on them
+///Axle counters add the difference in axles inside since the last update to their count
+///so a counter that was reset while a train was on it ends up with a wrong count
+///A false occupancy or an occupied sensor of a model layout keeps a section active either way
+pub fn update_sections(data: &mut WorkerData) {
+    let mut axles_inside: HashMap<(usize, usize), usize> = HashMap::new();
+    let mut occupied_by: HashMap<(usize, usize), i32> = HashMap::new();
+
+    for train in &data.trains {
+        for (step, start, end) in train.occupied_intervals() {
+            let a = step.structure_distance(start);
+            let b = step.structure_distance(end);
+            let (low, high) = (a.min(b), a.max(b));
+
+            if let Some(structure) = data.train_system.structures.get(&step.structure) {
+                for (i, section) in structure.sections().iter().enumerate() {
+                    let (start, end) = (section.distance_start as f64, section.distance_end as f64);
+                    //A section without length is occupied by a train that runs over it
+                    let occupied = match start < end {
+                        true => low < end && high > start,
+                        false => low <= end && high >= start,
+                    };
+                    if occupied {
+                        occupied_by.insert((step.structure, i), train.train_number);
+                    }
+                }
+            }
+        }
+
+        for (step, offset) in train.axle_positions() {
+            let distance = step.structure_distance(offset);
+
+            if let Some(structure) = data.train_system.structures.get(&step.structure) {
+                let last = structure.sections().len().saturating_sub(1);
+                let found = structure.sections().iter().position(|section| {
+                    distance < section.distance_end as f64
+                        && distance >= section.distance_start as f64
+                });
+
+                if let Some(i) = found.or((distance >= step.length).then_some(last)) {
+                    *axles_inside.entry((step.structure, i)).or_insert(0) += 1;
+                }
+            }
+        }
+    }
+
+    for (idx, structure) in data.train_system.structures.iter_mut() {
+        for (i, section) in structure.sections_mut().iter_mut().enumerate() {
+            let train_number = occupied_by.get(&(*idx, i)).copied();
+            let inside = axles_inside.get(&(*idx, i)).copied().unwrap_or(0);
+
+            section.train_number = train_number.unwrap_or(0);
+
+            match section.detection {
+                Detection::TrackCircuit => {
+                    section.active = train_number.is_some()
+                        || section.false_occupancy
+                        || section.sensor_occupied;
+                }
+                Detection::AxleCounter => {
+                    section.axle_count += inside as i32 - section.counted_axles as i32;
+                    if section.axle_count < 0 {
+                        section.disturbed = true;
+                    }
+                    section.active = section.axle_count != 0
+                        || section.disturbed
+                        || section.false_occupancy
+                        || section.sensor_occupied;
+                }
+            }
+
+            section.counted_axles = inside;
+        }
+    }
+}
 
 ///Set New Interval
 ///When doing an action where you want to 'restart' counting our interval
@@ -146,46 +818,65 @@ pub async fn worker_loop(
 pub fn set_new_interval() -> tokio::time::Interval {
     //move interval point to round this up to a second
     let now = Instant::now();
-    let interval = interval_at(now + Duration::from_secs(1), Duration::from_secs(1));
-    interval
+    interval_at(now + Duration::from_secs(1), Duration::from_secs(1))
 }
 
 ///Generate Sections
 ///This function creates a number of sections within our train track
 ///A section is either active (a train is running on it) or it is inactive
 ///Sections have a distance in meters from left to right which we use to determine if our train is on it
-///Random will be implemented later to set random distances for each section for more realistic tracks
+///Random sets random distances for each section for more realistic tracks, see seeded sections
 pub fn generate_sections(amount: usize, random: bool, total_distance: usize) -> Vec<Section> {
-    let mut collection: Vec<Section> = Vec::with_capacity(amount);
+    if random {
+        return seeded_sections(amount, rand::random(), total_distance);
+    }
 
-    let mut current_distance: usize = 0;
+    let boundaries: Vec<usize> = (1..amount).map(|i| i * (total_distance / amount)).collect();
 
-    if random {
-        for i in 1..amount + 1 {
-            let distance_end = i * (total_distance / amount);
-
-            collection.push(Section {
-                active: false,
-                train_number: 0,
-                distance_start: current_distance,
-                distance_end: distance_end,
-            });
+    boundary_sections(&boundaries, total_distance, Detection::TrackCircuit)
+}
 
-            current_distance = distance_end;
-        }
-    } else {
-        for i in 1..amount + 1 {
-            let distance_end = i * (total_distance / amount);
+///Seeded Sections
+///Creates an amount of sections of random length, the same seed always gives the same sections
+///Every section is at least a meter long, so the amount is limited to the total distance
+pub fn seeded_sections(amount: usize, seed: u64, total_distance: usize) -> Vec<Section> {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let amount = amount.clamp(1, total_distance.max(1));
+
+    let mut boundaries: Vec<usize> =
+        rand::seq::index::sample(&mut rng, total_distance.saturating_sub(1), amount - 1)
+            .into_iter()
+            .map(|boundary| boundary + 1)
+            .collect();
+    boundaries.sort_unstable();
+
+    boundary_sections(&boundaries, total_distance, Detection::TrackCircuit)
+}
 
-            collection.push(Section {
-                active: false,
-                train_number: 0,
-                distance_start: current_distance,
-                distance_end: distance_end,
-            });
+///Boundary Sections
+///Creates sections between the given boundaries, the first section starts at 0 and the last ends at the total distance
+///Boundaries outside the structure or out of order are skipped, a structure without length gets a single section
+pub fn boundary_sections(
+    boundaries: &[usize],
+    total_distance: usize,
+    detection: Detection,
+) -> Vec<Section> {
+    let mut collection: Vec<Section> = Vec::with_capacity(boundaries.len() + 1);
+
+    let mut current_distance: usize = 0;
 
-            current_distance = distance_end;
+    for distance_end in boundaries.iter().copied().chain([total_distance]) {
+        if distance_end <= current_distance || distance_end > total_distance {
+            continue;
         }
+
+        collection.push(Section::new(current_distance, distance_end, detection));
+
+        current_distance = distance_end;
+    }
+
+    if collection.is_empty() {
+        collection.push(Section::new(0, total_distance, detection));
     }
 
     collection